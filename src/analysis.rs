@@ -0,0 +1,380 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::fmt;
+
+use crate::{grid::{self, Coord}, map::{Layer, Map, TileSet}, pathfinding::{self, PathOptions}};
+
+// Passable tiles labelled with the connected region they belong to, region 0 is the largest
+pub struct Regions {
+    width: u32,
+    height: u32,
+    labels: Vec<Option<usize>>,
+    sizes: Vec<u32>,
+}
+
+impl Regions {
+    // Two tiles are connected when a unit could step from one to the other, see pathfinding::neighbors
+    pub fn flood_fill<F>(width: u32, height: u32, options: &PathOptions, passable: F) -> Regions
+    where F: Fn(u32, u32) -> bool {
+        let step_cost = |_, (x, y)| if passable(x, y) { Some(1) } else { None };
+        let mut labels = vec![None; (width * height) as usize];
+        let mut sizes = Vec::new();
+
+        for (i, coord) in grid::region(Coord::new(0, 0), width, height).enumerate() {
+            let (x, y) = (coord.x as u32, coord.y as u32);
+            if labels[i].is_some() || !passable(x, y) {
+                continue;
+            }
+
+            let label = sizes.len();
+            let mut size = 0;
+            labels[i] = Some(label);
+
+            let mut stack = vec![(x, y)];
+            while let Some((x, y)) = stack.pop() {
+                size += 1;
+                for (nx, ny, _) in pathfinding::neighbors(width, height, x, y, options, &step_cost) {
+                    if let Some(n) = Coord::from((nx, ny)).index(width, height) {
+                        if labels[n].is_none() {
+                            labels[n] = Some(label);
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+            }
+
+            sizes.push(size);
+        }
+
+        // Renumber from the largest region down, ties keep the order they were found in
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by_key(|&label| std::cmp::Reverse(sizes[label]));
+        let mut renumber = vec![0; sizes.len()];
+        for (new, &old) in order.iter().enumerate() {
+            renumber[old] = new;
+        }
+        for label in labels.iter_mut().flatten() {
+            *label = renumber[*label];
+        }
+        let sizes = order.iter().map(|&old| sizes[old]).collect();
+
+        Regions { width, height, labels, sizes }
+    }
+
+    pub fn of_layer(layer: &Layer, tile_set: &TileSet, movement_class: &str, options: &PathOptions) -> Regions {
+        Regions::flood_fill(layer.width, layer.height, options, |x, y| tile_set.terrain(layer.get(x, y)).is_passable(movement_class))
+    }
+
+    // Uses the topmost tile of every position, like the pathfinding on a map
+    pub fn of_map(map: &Map, movement_class: &str, options: &PathOptions) -> Regions {
+        let options = PathOptions { topology: map.topology, wrap: map.wrap, ..*options };
+        Regions::flood_fill(map.width(), map.height(), &options, |x, y| map.terrain(x, y).is_passable(movement_class))
+    }
+
+    pub fn len(&self) -> usize {
+        self.sizes.len()
+    }
+
+    pub fn sizes(&self) -> &[u32] {
+        &self.sizes
+    }
+
+    pub fn size(&self, region: usize) -> u32 {
+        self.sizes[region]
+    }
+
+    // None for impassable tiles and positions outside the map
+    pub fn region_at(&self, x: u32, y: u32) -> Option<usize> {
+        Coord::from((x, y)).index(self.width, self.height).and_then(|i| self.labels[i])
+    }
+
+    pub fn tiles(&self, region: usize) -> Vec<(u32, u32)> {
+        grid::region(Coord::new(0, 0), self.width, self.height)
+            .zip(self.labels.iter())
+            .filter(|(_, label)| **label == Some(region))
+            .map(|(coord, _)| (coord.x as u32, coord.y as u32))
+            .collect()
+    }
+}
+
+// Passable tiles whose loss would split the region they are in (articulation points of the tile graph), found with
+// an iterative depth first search so large open maps don't overflow the stack
+pub fn chokepoints<F>(width: u32, height: u32, options: &PathOptions, passable: F) -> Vec<(u32, u32)>
+where F: Fn(u32, u32) -> bool {
+    let step_cost = |_, (x, y)| if passable(x, y) { Some(1) } else { None };
+    // Only ever called with tiles from the search, which are inside the map
+    let index = |x: u32, y: u32| Coord::from((x, y)).index(width, height).unwrap();
+
+    // 0 is not visited yet
+    let mut discovered = vec![0u32; (width * height) as usize];
+    let mut low = vec![0u32; (width * height) as usize];
+    let mut is_chokepoint = vec![false; (width * height) as usize];
+    let mut time = 0;
+
+    for root in grid::region(Coord::new(0, 0), width, height) {
+        let root = (root.x as u32, root.y as u32);
+        if discovered[index(root.0, root.1)] != 0 || !passable(root.0, root.1) {
+            continue;
+        }
+
+        time += 1;
+        discovered[index(root.0, root.1)] = time;
+        low[index(root.0, root.1)] = time;
+        let mut root_children = 0;
+
+        // (tile, its neighbors, next neighbor to visit, parent)
+        let mut stack = vec![(root, pathfinding::neighbors(width, height, root.0, root.1, options, &step_cost), 0, None)];
+
+        while let Some((tile, neighbors, next, parent)) = stack.last_mut() {
+            let (tile, parent) = (*tile, *parent);
+
+            if *next < neighbors.len() {
+                let (nx, ny, _) = neighbors[*next];
+                *next += 1;
+
+                if discovered[index(nx, ny)] == 0 {
+                    time += 1;
+                    discovered[index(nx, ny)] = time;
+                    low[index(nx, ny)] = time;
+                    if tile == root {
+                        root_children += 1;
+                    }
+                    stack.push(((nx, ny), pathfinding::neighbors(width, height, nx, ny, options, &step_cost), 0, Some(tile)));
+                } else if parent != Some((nx, ny)) {
+                    low[index(tile.0, tile.1)] = low[index(tile.0, tile.1)].min(discovered[index(nx, ny)]);
+                }
+            } else {
+                stack.pop();
+                if let Some(parent) = parent {
+                    let (t, p) = (index(tile.0, tile.1), index(parent.0, parent.1));
+                    low[p] = low[p].min(low[t]);
+                    if parent != root && low[t] >= discovered[p] {
+                        is_chokepoint[p] = true;
+                    }
+                }
+            }
+        }
+
+        if root_children > 1 {
+            is_chokepoint[index(root.0, root.1)] = true;
+        }
+    }
+
+    grid::region(Coord::new(0, 0), width, height)
+        .zip(is_chokepoint)
+        .filter(|(_, chokepoint)| *chokepoint)
+        .map(|(coord, _)| (coord.x as u32, coord.y as u32))
+        .collect()
+}
+
+// Everything the map checker reports for one movement class
+pub struct Analysis {
+    pub movement_class: String,
+    pub regions: Regions,
+    pub chokepoints: Vec<(u32, u32)>,
+    // Regions no start is in, every region but the largest when the map has no starts
+    pub isolated: Vec<usize>,
+    // Starts, by index into MapInfo::starts, on tiles the movement class can't enter
+    pub blocked_starts: Vec<usize>,
+    // Pairs of starts that can't reach each other
+    pub disconnected: Vec<(usize, usize)>,
+}
+
+impl Analysis {
+    pub fn new(map: &Map, movement_class: &str, options: &PathOptions) -> Analysis {
+        let options = PathOptions { topology: map.topology, wrap: map.wrap, ..*options };
+        let passable = |x, y| map.terrain(x, y).is_passable(movement_class);
+
+        let regions = Regions::of_map(map, movement_class, &options);
+        let chokepoints = chokepoints(map.width(), map.height(), &options, passable);
+
+        let start_regions: Vec<Option<usize>> = map.info.starts.iter().map(|&(x, y)| regions.region_at(x, y)).collect();
+
+        let blocked_starts = (0..start_regions.len()).filter(|&i| start_regions[i].is_none()).collect();
+
+        let mut disconnected = Vec::new();
+        for a in 0..start_regions.len() {
+            for b in a + 1..start_regions.len() {
+                if start_regions[a].is_none() || start_regions[a] != start_regions[b] {
+                    disconnected.push((a, b));
+                }
+            }
+        }
+
+        let isolated = if start_regions.is_empty() {
+            (1..regions.len()).collect()
+        } else {
+            (0..regions.len()).filter(|region| !start_regions.contains(&Some(*region))).collect()
+        };
+
+        Analysis {
+            movement_class: movement_class.to_string(),
+            regions,
+            chokepoints,
+            isolated,
+            blocked_starts,
+            disconnected,
+        }
+    }
+
+    // Every start can reach every other start
+    pub fn is_connected(&self) -> bool {
+        self.blocked_starts.is_empty() && self.disconnected.is_empty()
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "movement class \"{}\": {} regions", self.movement_class, self.regions.len())?;
+        for (region, size) in self.regions.sizes().iter().enumerate() {
+            let isolated = if self.isolated.contains(&region) { ", isolated" } else { "" };
+            writeln!(f, "  region {}: {} tiles{}", region, size, isolated)?;
+        }
+
+        writeln!(f, "  {} chokepoints", self.chokepoints.len())?;
+
+        for start in &self.blocked_starts {
+            writeln!(f, "  start {} is on an impassable tile", start)?;
+        }
+        for (a, b) in &self.disconnected {
+            writeln!(f, "  start {} can't reach start {}", a, b)?;
+        }
+
+        write!(f, "  starts {}", if self.is_connected() { "connected" } else { "not connected" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generation::noise::Noise, grid::Wrap, testing};
+
+    // Rows are listed bottom first, '#' can't be entered
+    fn passable(rows: &'static [&'static str]) -> impl Fn(u32, u32) -> bool {
+        move |x, y| rows[y as usize].as_bytes()[x as usize] != b'#'
+    }
+
+    fn brute_force_chokepoints<F: Fn(u32, u32) -> bool>(width: u32, height: u32, passable: F) -> Vec<(u32, u32)> {
+        let options = PathOptions::new();
+        let regions = Regions::flood_fill(width, height, &options, &passable).len();
+        grid::region(Coord::new(0, 0), width, height)
+            .map(|coord| (coord.x as u32, coord.y as u32))
+            .filter(|&(x, y)| passable(x, y))
+            .filter(|&tile| Regions::flood_fill(width, height, &options, |x, y| (x, y) != tile && passable(x, y)).len() > regions)
+            .collect()
+    }
+
+    #[test]
+    fn regions_are_numbered_by_size() {
+        let rows = &["..#..#", "#.#.##", "###.#."];
+        let regions = Regions::flood_fill(6, 3, &PathOptions::new(), passable(rows));
+
+        assert_eq!(regions.len(), 3);
+        assert_eq!(regions.sizes(), &[4, 3, 1]);
+        assert_eq!(regions.region_at(3, 0), Some(0));
+        assert_eq!(regions.region_at(3, 2), Some(0));
+        assert_eq!(regions.region_at(0, 0), Some(1));
+        assert_eq!(regions.region_at(5, 2), Some(2));
+        assert_eq!(regions.region_at(0, 1), None);
+        assert_eq!(regions.region_at(6, 0), None);
+        assert_eq!(regions.tiles(1), vec![(0, 0), (1, 0), (1, 1)]);
+        assert_eq!(regions.tiles(2), vec![(5, 2)]);
+    }
+
+    #[test]
+    fn wrapping_joins_regions_across_the_seam() {
+        let rows = &[".#.", ".#."];
+        assert_eq!(Regions::flood_fill(3, 2, &PathOptions::new(), passable(rows)).len(), 2);
+
+        let options = PathOptions { wrap: Wrap::Horizontal, ..PathOptions::new() };
+        assert_eq!(Regions::flood_fill(3, 2, &options, passable(rows)).len(), 1);
+    }
+
+    #[test]
+    fn chokepoints_split_their_region() {
+        let rows = &["..#..", ".....", "..#.."];
+        let mut found = chokepoints(5, 3, &PathOptions::new(), passable(rows));
+        found.sort();
+        assert_eq!(found, vec![(1, 1), (2, 1), (3, 1)]);
+
+        let open = &["...", "...", "..."];
+        assert!(chokepoints(3, 3, &PathOptions::new(), passable(open)).is_empty());
+
+        // Compare with removing every tile in turn on a few scattered maps
+        for seed in 0..20 {
+            let noise = Noise::new(seed);
+            let passable = |x, y| noise.value(x as i32, y as i32) < 0.65;
+            let mut found = chokepoints(9, 7, &PathOptions::new(), passable);
+            found.sort();
+            let mut expected = brute_force_chokepoints(9, 7, passable);
+            expected.sort();
+            assert_eq!(found, expected, "seed {}", seed);
+        }
+    }
+
+    fn map(rows: &[&str], starts: Vec<(u32, u32)>) -> Map {
+        let mut tiles = testing::tile_set(&["grass", "water"]);
+        let water = tiles.find("water").unwrap();
+        tiles.tile_mut(water).terrain.passable = false;
+
+        let mut layer = Layer::new(rows[0].len() as u32, rows.len() as u32);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                layer.set(x as u32, y as u32, tiles.find(if c == '#' { "water" } else { "grass" }).unwrap());
+            }
+        }
+
+        let mut map = Map::with_tile_set(tiles);
+        map.layers.push(layer);
+        map.info.starts = starts;
+        map
+    }
+
+    #[test]
+    fn starts_are_checked_for_connections() {
+        let rows = &["...#..#.", "...#..##", "...####."];
+
+        let analysis = Analysis::new(&map(rows, vec![(0, 0), (1, 2)]), "default", &PathOptions::new());
+        assert!(analysis.is_connected());
+        assert_eq!(analysis.regions.sizes(), &[9, 4, 1, 1]);
+        assert_eq!(analysis.isolated, vec![1, 2, 3]);
+
+        let analysis = Analysis::new(&map(rows, vec![(0, 0), (4, 0), (3, 1)]), "default", &PathOptions::new());
+        assert!(!analysis.is_connected());
+        assert_eq!(analysis.blocked_starts, vec![2]);
+        assert_eq!(analysis.disconnected, vec![(0, 1), (0, 2), (1, 2)]);
+        assert_eq!(analysis.isolated, vec![2, 3]);
+
+        let report = analysis.to_string();
+        assert!(report.contains("4 regions"));
+        assert!(report.contains("region 2: 1 tiles, isolated"));
+        assert!(report.contains("start 2 is on an impassable tile"));
+        assert!(report.contains("start 0 can't reach start 1"));
+        assert!(report.ends_with("starts not connected"));
+
+        let analysis = Analysis::new(&map(rows, Vec::new()), "default", &PathOptions::new());
+        assert_eq!(analysis.isolated, vec![1, 2, 3]);
+        assert!(analysis.is_connected());
+    }
+}
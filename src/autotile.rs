@@ -0,0 +1,349 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::collections::HashMap;
+
+use engine::renderer::texture::TextureRegion;
+use crate::{grid::{Edge, Topology}, io::{error::LoadError, resource::{Node, Resource}}, map::{AtlasTexture, Layer, TileIndex, TileSet}};
+
+// Mask bits for square grids, north is up (+y). Hex grids use bit 1 << i for the i-th neighbor of
+// Topology::neighbors instead, six bits in all.
+pub const N: u8 = 1;
+pub const NE: u8 = 2;
+pub const E: u8 = 4;
+pub const SE: u8 = 8;
+pub const S: u8 = 16;
+pub const SW: u8 = 32;
+pub const W: u8 = 64;
+pub const NW: u8 = 128;
+
+const SQUARE_BITS: [((i32, i32), u8); 8] = [
+    ((0, 1), N), ((1, 1), NE), ((1, 0), E), ((1, -1), SE),
+    ((0, -1), S), ((-1, -1), SW), ((-1, 0), W), ((-1, 1), NW),
+];
+
+#[derive(Clone)]
+pub struct AutotileVariant {
+    pub mask: u8,
+    // None when the data leaves it out, see AutotileVariant::care
+    pub care: Option<u8>,
+    pub texture: TextureRegion,
+    pub atlas: usize,
+    pub bounds: (u32, u32, u32, u32),
+}
+
+impl AutotileVariant {
+    pub fn care(&self, topology: Topology) -> u8 {
+        match (self.care, topology) {
+            (Some(care), _) => care,
+            (None, Topology::Square) => N | E | S | W,
+            (None, Topology::Hex(_, _)) => 0b11_1111,
+        }
+    }
+
+    pub fn matches(&self, mask: u8, topology: Topology) -> bool {
+        let care = self.care(topology);
+        mask & care == self.mask & care
+    }
+}
+
+// autotiles:
+//   - tile: "sand"
+//     connects: ["sand", "castle"]
+//     variants:
+//       - mask: "N S"
+//         texture: "sand_ns"
+//       - mask: "N E S W"
+//         care: "N NE E SE S SW W NW"
+//         texture: "sand_center"
+//
+// A neighbor sets its bit in the mask when one of the connected tiles, by default only the tile itself, is in
+// that cell on any layer, so a road on its own layer still meets a castle on another. Neighbors outside
+// every layer always connect so the map edge doesn't get a border. The first variant whose
+// mask matches on the bits in care is drawn, with the tile's own texture as fallback. Without care only the
+// sides count: N E S W on square grids and all six neighbors on hex grids.
+//
+// On hex grids the directions don't fit, bit 1 << i stands for the i-th neighbor in Topology::neighbors
+// order, so masks are given as numbers there, like mask: 17 for the neighbors 0 and 4.
+#[derive(Clone)]
+pub struct Autotile {
+    pub connects: Vec<TileIndex>,
+    pub variants: Vec<AutotileVariant>,
+}
+
+fn parse_mask(res: &Node) -> Result<u8, LoadError> {
+    match res.resource() {
+        Resource::I64(_) => {
+            let mask = res.as_i64()?;
+            if mask < 0 || mask > 255 {
+                return Err(res.error(&format!("mask {} is outside of 0..255", mask)));
+            }
+            Ok(mask as u8)
+        },
+        _ => {
+            let mut mask = 0;
+            for name in res.as_str()?.split_whitespace() {
+                mask |= match name {
+                    "N" => N, "NE" => NE, "E" => E, "SE" => SE,
+                    "S" => S, "SW" => SW, "W" => W, "NW" => NW,
+                    other => return Err(res.error(&format!("unknown direction \"{}\"", other))),
+                };
+            }
+            Ok(mask)
+        },
+    }
+}
+
+impl Autotile {
+    pub fn from_resource(res: &Node, textures: &HashMap<&str, AtlasTexture>, tile_set: &TileSet, tile: TileIndex) -> Result<Autotile, LoadError> {
+        let mut connects = Vec::new();
+        match res.get_optional("connects") {
+            Some(connects_d) => {
+                for name_d in connects_d.as_vec()? {
+                    let name = name_d.as_str()?;
+                    connects.push(tile_set.find(name).ok_or_else(|| name_d.error(&format!("unknown tile \"{}\"", name)))?);
+                }
+            },
+            None => connects.push(tile),
+        }
+
+        let mut variants = Vec::new();
+        for variant_d in res.get("variants")?.as_vec()? {
+            let texture_d = variant_d.get("texture")?;
+            let texture = match textures.get(texture_d.as_str()?) {
+                Some(texture) => texture,
+                None => return Err(texture_d.error(&format!("unknown texture \"{}\"", texture_d.as_str()?))),
+            };
+
+            let care = match variant_d.get_optional("care") {Some(x) => Some(parse_mask(&x)?), None => None};
+
+            variants.push(AutotileVariant {
+                mask: parse_mask(&variant_d.get("mask")?)?,
+                care,
+                texture: texture.region.clone(),
+                atlas: texture.atlas,
+                bounds: texture.bounds,
+            });
+        }
+
+        Ok(Autotile { connects, variants })
+    }
+
+    // Steps off the layer are handled by edge, the ones that land nowhere connect to everything
+    pub fn mask(&self, layers: &[Layer], x: u32, y: u32, topology: Topology, edge: Edge) -> u8 {
+        let connected = |neighbor: (i32, i32)| {
+            let mut tiles = layers.iter()
+                .filter_map(|layer| edge.apply(neighbor.into(), layer.width, layer.height).and_then(|neighbor| layer.try_get(neighbor)))
+                .peekable();
+            tiles.peek().is_none() || tiles.any(|tile| self.connects.contains(&tile))
+        };
+
+        let mut mask = 0;
+        match topology {
+            Topology::Square => {
+                for ((dx, dy), bit) in SQUARE_BITS.iter() {
+                    if connected((x as i32 + dx, y as i32 + dy)) {
+                        mask |= bit;
+                    }
+                }
+            },
+            Topology::Hex(_, _) => {
+                for (i, neighbor) in topology.neighbors(x as i32, y as i32, false).into_iter().enumerate() {
+                    if connected(neighbor) {
+                        mask |= 1 << i;
+                    }
+                }
+            },
+        }
+
+        mask
+    }
+
+    pub fn variant(&self, mask: u8, topology: Topology) -> Option<&AutotileVariant> {
+        self.variants.iter().find(|variant| variant.matches(mask, topology))
+    }
+
+    pub fn texture(&self, mask: u8, topology: Topology) -> Option<&TextureRegion> {
+        self.variant(mask, topology).map(|variant| &variant.texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::{HexCoordinates, HexOrientation}, testing};
+
+    const DATA: &str = "
+atlas:
+  - path: \"sand.png\"
+textures:
+  - name: \"sand\"
+    bounds: [0, 0, 16, 16]
+  - name: \"sand_ns\"
+    bounds: [16, 0, 16, 16]
+  - name: \"sand_center\"
+    bounds: [32, 0, 16, 16]
+  - name: \"water\"
+    bounds: [48, 0, 16, 16]
+tiles:
+  - name: \"sand\"
+    texture: \"sand\"
+  - name: \"water\"
+    texture: \"water\"
+  - name: \"castle\"
+    texture: \"water\"
+autotiles:
+  - tile: \"sand\"
+    connects: [\"sand\", \"castle\"]
+    variants:
+      - mask: \"N S\"
+        texture: \"sand_ns\"
+      - mask: 255
+        care: \"N NE E SE S SW W NW\"
+        texture: \"sand_center\"
+";
+
+    fn tile_set(data: &str) -> Result<TileSet, LoadError> {
+        testing::data_tile_set(data, "data")
+    }
+
+    // Rows are listed bottom first: 's' is sand, 'w' water and 'c' a castle
+    fn layer(tiles: &TileSet, rows: &[&str]) -> Layer {
+        let mut layer = Layer::new(rows[0].len() as u32, rows.len() as u32);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let name = match c {
+                    's' => "sand",
+                    'w' => "water",
+                    _ => "castle",
+                };
+                layer.set(x as u32, y as u32, tiles.find(name).unwrap());
+            }
+        }
+        layer
+    }
+
+    fn sand(tiles: &TileSet) -> &Autotile {
+        tiles.tile(tiles.find("sand").unwrap()).autotile.as_ref().unwrap()
+    }
+
+    #[test]
+    fn masks_mark_connected_neighbors() {
+        let tiles = tile_set(DATA).unwrap();
+        let autotile = sand(&tiles);
+        let layers = [layer(&tiles, &[
+            "scs",
+            "wsw",
+            "sss",
+        ])];
+
+        let mask = autotile.mask(&layers, 1, 1, Topology::Square, Edge::None);
+        assert_eq!(mask, N | NE | S | SE | SW | NW);
+        assert_eq!(autotile.variant(mask, Topology::Square).map(|variant| variant.bounds), Some((16, 0, 16, 16)));
+
+        // Off the layer everything connects, unless the step wraps or clamps onto water
+        assert_eq!(autotile.mask(&layers, 0, 1, Topology::Square, Edge::None), 255);
+        assert_eq!(autotile.mask(&layers, 0, 1, Topology::Square, Edge::Wrap), !W);
+        assert_eq!(autotile.mask(&layers, 1, 2, Topology::Square, Edge::Clamp), !(SE | SW));
+    }
+
+    #[test]
+    fn other_layers_connect() {
+        let tiles = tile_set(DATA).unwrap();
+        let autotile = sand(&tiles);
+        let mut castles = Layer::new(2, 1);
+        castles.set(1, 0, tiles.find("castle").unwrap());
+        let mut layers = vec![layer(&tiles, &["sw"]), castles];
+
+        // The castle stands on the water to the east
+        assert_eq!(autotile.mask(&layers, 0, 0, Topology::Square, Edge::None), 255);
+        layers.pop();
+        assert_eq!(autotile.mask(&layers, 0, 0, Topology::Square, Edge::None), !E);
+    }
+
+    #[test]
+    fn first_matching_variant_wins() {
+        let tiles = tile_set(DATA).unwrap();
+        let autotile = sand(&tiles);
+
+        assert_eq!(autotile.variants[0].care, None);
+        assert_eq!(autotile.variants[0].care(Topology::Square), N | E | S | W);
+        assert_eq!(autotile.variants[1].mask, 255);
+
+        assert_eq!(autotile.variant(N | S | NE | SW, Topology::Square).map(|variant| variant.bounds), Some((16, 0, 16, 16)));
+        assert_eq!(autotile.variant(255, Topology::Square).map(|variant| variant.bounds), Some((32, 0, 16, 16)));
+        assert_eq!(autotile.variant(N | E | S | W, Topology::Square).map(|variant| variant.bounds), None);
+        assert!(autotile.texture(N, Topology::Square).is_none());
+
+        let water = tiles.find("water").unwrap();
+        assert!(tiles.tile(water).autotile.is_none());
+    }
+
+    #[test]
+    fn hex_masks_follow_the_neighbor_order() {
+        let tiles = tile_set(DATA).unwrap();
+        let autotile = sand(&tiles);
+        let topology = Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset);
+
+        let mut layers = [layer(&tiles, &[
+            "sss",
+            "sss",
+            "sss",
+        ])];
+        assert_eq!(autotile.mask(&layers, 1, 1, topology, Edge::None), 0b11_1111);
+
+        for (i, (x, y)) in topology.neighbors(1, 1, false).into_iter().enumerate() {
+            let sand = layers[0].get(x as u32, y as u32);
+            layers[0].set(x as u32, y as u32, tiles.find("water").unwrap());
+            assert_eq!(autotile.mask(&layers, 1, 1, topology, Edge::None), 0b11_1111 & !(1 << i));
+            layers[0].set(x as u32, y as u32, sand);
+        }
+    }
+
+    #[test]
+    fn hex_care_defaults_to_all_neighbors() {
+        let tiles = tile_set(DATA).unwrap();
+        let autotile = sand(&tiles);
+        let topology = Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset);
+
+        // "N S" are the bits of the neighbors 0 and 4, the other four have to be cut off as well
+        assert_eq!(autotile.variants[0].care(topology), 0b11_1111);
+        assert_eq!(autotile.variant(N | S, topology).map(|variant| variant.bounds), Some((16, 0, 16, 16)));
+        assert!(autotile.variant(N | S | 1 << 1, topology).is_none());
+        assert!(autotile.variant(N | S | NE, Topology::Square).is_some());
+    }
+
+    #[test]
+    fn broken_autotiles_are_errors() {
+        for (from, to) in &[
+            ("mask: 255", "mask: 256"),
+            ("mask: \"N S\"", "mask: \"N UP\""),
+            ("care: \"N NE E SE S SW W NW\"", "care: -1"),
+            ("connects: [\"sand\", \"castle\"]", "connects: [\"sand\", \"lava\"]"),
+            ("texture: \"sand_ns\"", "texture: \"sand_nesw\""),
+            ("tile: \"sand\"", "tile: \"dune\""),
+        ] {
+            assert!(tile_set(&DATA.replace(from, to)).is_err(), "{}", to);
+        }
+    }
+}
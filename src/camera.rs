@@ -76,4 +76,24 @@ impl Camera {
         self.x = new_cam_x;
         self.y = new_cam_y;
     }
+
+    // Moves the camera back by whole periods of a wrapping map, see Map::periods, so panning around it keeps going
+    // without ever running off
+    pub fn wrap(&mut self, horizontal: Option<(f32, f32)>, vertical: Option<(f32, f32)>) {
+        if let Some((px, py)) = vertical {
+            if py > 0.0 {
+                let k = (self.y / py).floor();
+                self.x -= k * px;
+                self.y -= k * py;
+            }
+        }
+
+        if let Some((px, py)) = horizontal {
+            if px > 0.0 {
+                let k = (self.x / px).floor();
+                self.x -= k * px;
+                self.y -= k * py;
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,253 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::collections::HashMap;
+
+use crate::{grid::{self, Coord}, io::{bytes::{ByteReader, ByteWriter}, encoding::LayerEncoding, error::LoadError, hash::ContentHasher, resource::{Node, Resource}}};
+
+// heights:
+//   step: 0.25
+//   uphill_cost: 1
+//   defense_bonus: 1
+//   vision_bonus: 1
+//   encoding: rows
+//   data:
+//     - "0 1 2 2"
+//     - "0 0 1 2"
+//
+// One whole height level per tile, 0 is the lowest. A tile is drawn step tiles higher per level. Climbing costs
+// uphill_cost extra movement per level, a defender gets defense_bonus per level it stands above its attacker and
+// units see vision_bonus tiles further per level they stand on. Rows are written top row first like layers.
+#[derive(Clone)]
+pub struct Heights {
+    pub width: u32,
+    pub height: u32,
+    values: Vec<u8>,
+    pub encoding: LayerEncoding,
+    pub step: f32,
+    pub uphill_cost: u32,
+    pub defense_bonus: i32,
+    pub vision_bonus: u32,
+}
+
+impl Heights {
+    pub fn new(width: u32, height: u32) -> Heights {
+        Heights {
+            width,
+            height,
+            values: vec![0; width as usize * height as usize],
+            encoding: LayerEncoding::List,
+            step: 0.25,
+            uphill_cost: 1,
+            defense_bonus: 1,
+            vision_bonus: 1,
+        }
+    }
+
+    // Splits an elevation field in [0, 1], one value per tile in Layer::coords order, into levels
+    pub fn from_elevation(elevation: &[f32], width: u32, height: u32, levels: u8) -> Heights {
+        let mut heights = Heights::new(width, height);
+        let levels = levels.max(1);
+        for (value, e) in heights.values.iter_mut().zip(elevation) {
+            *value = ((e.max(0.0).min(1.0) * levels as f32) as u8).min(levels - 1);
+        }
+        heights
+    }
+
+    // The map size is the widest layer by the tallest one, so it gets checked against the tile limit again
+    pub fn from_resource(res: &Node, width: u32, height: u32) -> Result<Heights, LoadError> {
+        let size = grid::tile_count(width, height).map_err(|e| res.error(&e))?;
+        let mut heights = Heights::new(width, height);
+
+        heights.step = match res.get_optional("step") {Some(x) => x.as_f64()? as f32, None => 0.25};
+        heights.uphill_cost = match res.get_optional("uphill_cost") {Some(x) => x.as_u32()?, None => 1};
+        heights.defense_bonus = match res.get_optional("defense_bonus") {Some(x) => x.as_i64()? as i32, None => 1};
+        heights.vision_bonus = match res.get_optional("vision_bonus") {Some(x) => x.as_u32()?, None => 1};
+        heights.encoding = LayerEncoding::from_resource(res.get_optional("encoding"))?;
+
+        let data = res.get("data")?;
+        let values = heights.encoding.decode(&data, width, height)?;
+        if values.len() != size {
+            return Err(data.error(&format!("expected {} heights for a {}x{} map but found {}", size, width, height, values.len())));
+        }
+
+        // The data is top row first, the values bottom row first
+        for (i, value) in values.into_iter().enumerate() {
+            if value > u8::MAX as u32 {
+                return Err(data.error(&format!("height {} is above the highest level {}", value, u8::MAX)));
+            }
+            let (x, row) = (i as u32 % width, i as u32 / width);
+            heights.set(x, height - 1 - row, value as u8);
+        }
+
+        Ok(heights)
+    }
+
+    pub fn to_resource(&self) -> Resource {
+        let mut values = Vec::with_capacity(self.values.len());
+        for row in (0..self.height).rev() {
+            for x in 0..self.width {
+                values.push(self.get(x, row) as u32);
+            }
+        }
+
+        let mut heights = HashMap::new();
+        heights.insert("step".to_string(), Resource::F64(self.step as f64));
+        heights.insert("uphill_cost".to_string(), Resource::I64(self.uphill_cost as i64));
+        heights.insert("defense_bonus".to_string(), Resource::I64(self.defense_bonus as i64));
+        heights.insert("vision_bonus".to_string(), Resource::I64(self.vision_bonus as i64));
+        if self.encoding != LayerEncoding::List {
+            heights.insert("encoding".to_string(), Resource::Str(self.encoding.name().to_string()));
+        }
+        heights.insert("data".to_string(), self.encoding.encode(&values, self.width));
+        Resource::Map(heights)
+    }
+
+    // step as f32 bits, uphill cost, defense bonus, vision bonus, then one byte per tile bottom row first
+    pub fn write_bytes(&self, out: &mut ByteWriter) {
+        out.write_u32(self.step.to_bits());
+        out.write_var(self.uphill_cost as u64);
+        out.write_u32(self.defense_bonus as u32);
+        out.write_var(self.vision_bonus as u64);
+        out.write_bytes(&self.values);
+    }
+
+    pub fn read_bytes(input: &mut ByteReader, width: u32, height: u32) -> Result<Heights, LoadError> {
+        let size = grid::tile_count(width, height).map_err(|e| input.error(&e))?;
+        let mut heights = Heights::new(width, height);
+        heights.step = f32::from_bits(input.read_u32()?);
+        heights.uphill_cost = input.read_var()? as u32;
+        heights.defense_bonus = input.read_u32()? as i32;
+        heights.vision_bonus = input.read_var()? as u32;
+        heights.values = input.read_bytes(size)?.to_vec();
+        Ok(heights)
+    }
+
+    // The encoding only changes how the yaml looks, so it's left out
+    pub fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_u32(self.width);
+        hasher.write_u32(self.height);
+        hasher.write_f32(self.step);
+        hasher.write_u32(self.uphill_cost);
+        hasher.write_i32(self.defense_bonus);
+        hasher.write_u32(self.vision_bonus);
+        hasher.write_bytes(&self.values);
+    }
+
+    pub fn try_get<C: Into<Coord>>(&self, coord: C) -> Option<u8> {
+        coord.into().index(self.width, self.height).map(|i| self.values[i])
+    }
+
+    // Outside the map everything lies at level 0
+    pub fn get(&self, x: u32, y: u32) -> u8 {
+        self.try_get((x, y)).unwrap_or(0)
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, value: u8) {
+        if let Some(i) = Coord::from((x, y)).index(self.width, self.height) {
+            self.values[i] = value;
+        }
+    }
+
+    // Levels gained stepping from one tile to the other, negative going down
+    pub fn climb(&self, from: (u32, u32), to: (u32, u32)) -> i32 {
+        self.get(to.0, to.1) as i32 - self.get(from.0, from.1) as i32
+    }
+
+    // A width x height copy with the value of every tile moved to point(x, y), see Map::transform
+    pub fn transformed<P: Fn(i32, i32) -> (i32, i32)>(&self, width: u32, height: u32, point: P) -> Heights {
+        let mut heights = Heights { values: vec![0; width as usize * height as usize], width, height, ..self.clone() };
+        for coord in grid::region(Coord::new(0, 0), self.width, self.height) {
+            let (x, y) = point(coord.x, coord.y);
+            if let (Some(value), Some(i)) = (self.try_get(coord), Coord::new(x, y).index(width, height)) {
+                heights.values[i] = value;
+            }
+        }
+        heights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Heights {
+        let mut heights = Heights::new(3, 2);
+        heights.set(0, 0, 1);
+        heights.set(2, 1, 3);
+        heights.uphill_cost = 2;
+        heights
+    }
+
+    #[test]
+    fn yaml_round_trip() {
+        let heights = sample();
+        let res = heights.to_resource();
+        let loaded = Heights::from_resource(&res.node("test"), 3, 2).unwrap();
+        assert_eq!(loaded.values, heights.values);
+        assert_eq!(loaded.uphill_cost, 2);
+
+        // New heights and heights loaded without an encoding both use the layers' default
+        assert_eq!(heights.encoding, LayerEncoding::List);
+        assert_eq!(loaded.encoding, heights.encoding);
+    }
+
+    #[test]
+    fn yaml_rows_are_top_first() {
+        let res = Resource::from_string("data: [\"0 0 3\", \"1 0 0\"]\nencoding: rows").unwrap();
+        let heights = Heights::from_resource(&res.node("test"), 3, 2).unwrap();
+        assert_eq!(heights.get(2, 1), 3);
+        assert_eq!(heights.get(0, 0), 1);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let heights = sample();
+        let mut out = ByteWriter::new();
+        heights.write_bytes(&mut out);
+        let loaded = Heights::read_bytes(&mut ByteReader::new(&out.data, "test"), 3, 2).unwrap();
+        assert_eq!(loaded.values, heights.values);
+        assert!(Heights::read_bytes(&mut ByteReader::new(&out.data[..out.data.len() - 1], "test"), 3, 2).is_err());
+    }
+
+    #[test]
+    fn oversized_maps_are_rejected() {
+        let res = Resource::from_string("data: []").unwrap();
+        assert!(Heights::from_resource(&res.node("test"), u32::MAX, u32::MAX).is_err());
+        assert!(Heights::read_bytes(&mut ByteReader::new(&[0; 16], "test"), 1 << 20, 1 << 20).is_err());
+    }
+
+    #[test]
+    fn climbing() {
+        let heights = sample();
+        assert_eq!(heights.climb((1, 0), (0, 0)), 1);
+        assert_eq!(heights.climb((0, 0), (1, 0)), -1);
+        assert_eq!(heights.get(10, 10), 0);
+    }
+
+    #[test]
+    fn elevation_splits_into_levels() {
+        let heights = Heights::from_elevation(&[0.0, 0.3, 0.5, 1.0], 4, 1, 4);
+        assert_eq!(heights.values, vec![0, 1, 2, 3]);
+    }
+}
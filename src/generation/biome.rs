@@ -0,0 +1,282 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use crate::{elevation::Heights, generation::noise::Noise, grid::{self, Coord}, io::{error::LoadError, resource::Node}, map::{Layer, Map, TileIndex, TileSet}};
+
+#[derive(Clone)]
+pub struct Biome {
+    pub tile: TileIndex,
+    pub elevation: (f32, f32),
+    pub moisture: (f32, f32),
+    pub feature: Option<(TileIndex, f32)>,
+}
+
+impl Biome {
+    fn contains(&self, elevation: f32, moisture: f32) -> bool {
+        elevation >= self.elevation.0 && elevation < self.elevation.1 && moisture >= self.moisture.0 && moisture < self.moisture.1
+    }
+}
+
+// biomes:
+//   seed: 1234
+//   scale: 16.0
+//   octaves: 4
+//   levels: 4
+//   table:
+//     - tile: "sand"
+//       elevation: [0.0, 0.4]
+//     - tile: "dirt"
+//       elevation: [0.4, 0.65]
+//       moisture: [0.0, 1.0]
+//       feature:
+//         tile: "castle"
+//         density: 0.01
+//     - tile: "stone"
+//       elevation: [0.65, 1.0]
+//
+// Biomes are checked in order and the first one containing the elevation and moisture of a tile wins,
+// leaving out a range means the biome accepts any value. The base tiles end up in layer 0 and the
+// features in layer 1. With levels the elevation is also split into that many height levels.
+#[derive(Clone)]
+pub struct TerrainGenerator {
+    pub seed: u64,
+    pub width: u32,
+    pub height: u32,
+    pub scale: f32,
+    pub octaves: u32,
+    pub levels: Option<u8>,
+    pub biomes: Vec<Biome>,
+}
+
+fn range(res: Option<Node>) -> Result<(f32, f32), LoadError> {
+    match res {
+        Some(res) => {
+            let range = (res.get_index(0)?.as_f64()? as f32, res.get_index(1)?.as_f64()? as f32);
+            if range.0 > range.1 {
+                return Err(res.error("range starts after it ends"));
+            }
+            // The top of the range is exclusive, nudge 1.0 so that it still covers the highest values
+            Ok((range.0, if range.1 >= 1.0 { f32::MAX } else { range.1 }))
+        },
+        None => Ok((f32::MIN, f32::MAX)),
+    }
+}
+
+fn tile(res: &Node, tile_set: &TileSet) -> Result<TileIndex, LoadError> {
+    let name = res.as_str()?;
+    tile_set.find(name).ok_or_else(|| res.error(&format!("unknown tile \"{}\"", name)))
+}
+
+impl TerrainGenerator {
+    pub fn new(seed: u64, width: u32, height: u32) -> TerrainGenerator {
+        TerrainGenerator {
+            seed,
+            width,
+            height,
+            scale: 16.0,
+            octaves: 4,
+            levels: None,
+            biomes: Vec::new(),
+        }
+    }
+
+    pub fn from_resource(res: &Node, tile_set: &TileSet, width: u32, height: u32) -> Result<TerrainGenerator, LoadError> {
+        let mut generator = TerrainGenerator::new(0, width, height);
+
+        generator.seed = match res.get_optional("seed") {Some(x) => x.as_i64()? as u64, None => 0};
+        generator.scale = match res.get_optional("scale") {Some(x) => x.as_f64()? as f32, None => 16.0};
+        generator.octaves = match res.get_optional("octaves") {Some(x) => x.as_u32()?, None => 4};
+        generator.levels = match res.get_optional("levels") {
+            Some(x) => match x.as_u32()? {
+                levels @ 1..=255 => Some(levels as u8),
+                _ => return Err(x.error("levels has to be between 1 and 255")),
+            },
+            None => None,
+        };
+
+        if generator.scale <= 0.0 {
+            return Err(res.get("scale")?.error("scale has to be larger than 0"));
+        }
+
+        for biome_d in res.get("table")?.as_vec()? {
+            let feature = match biome_d.get_optional("feature") {
+                Some(feature) => Some((tile(&feature.get("tile")?, tile_set)?, feature.get("density")?.as_f64()? as f32)),
+                None => None,
+            };
+
+            generator.biomes.push(Biome {
+                tile: tile(&biome_d.get("tile")?, tile_set)?,
+                elevation: range(biome_d.get_optional("elevation"))?,
+                moisture: range(biome_d.get_optional("moisture"))?,
+                feature,
+            });
+        }
+
+        if generator.biomes.is_empty() {
+            return Err(res.get("table")?.error("biome table is empty"));
+        }
+
+        Ok(generator)
+    }
+
+    fn noise(&self, channel: u64) -> Noise {
+        Noise::new(Noise::new(self.seed).hash(channel as i32, 0))
+    }
+
+    fn field(&self, channel: u64) -> Vec<f32> {
+        let noise = self.noise(channel);
+        let mut field = Vec::with_capacity((self.width * self.height) as usize);
+
+        for coord in grid::region(Coord::new(0, 0), self.width, self.height) {
+            field.push(noise.fractal(coord.x as f32 / self.scale, coord.y as f32 / self.scale, self.octaves));
+        }
+
+        field
+    }
+
+    // Indexed by x + y * width, in [0, 1)
+    pub fn elevation(&self) -> Vec<f32> {
+        self.field(0)
+    }
+
+    pub fn moisture(&self) -> Vec<f32> {
+        self.field(1)
+    }
+
+    pub fn generate(&self, tile_set: &TileSet) -> Map {
+        let elevation = self.elevation();
+        let moisture = self.moisture();
+        let features = self.noise(2);
+
+        let mut ground = Layer::new(self.width, self.height);
+        let mut decoration = Layer::new(self.width, self.height);
+
+        // The fields are in the same order as the layer positions
+        for (i, coord) in grid::region(Coord::new(0, 0), self.width, self.height).enumerate() {
+            let biome = self.biomes.iter()
+                .find(|biome| biome.contains(elevation[i], moisture[i]))
+                .unwrap_or(&self.biomes[self.biomes.len() - 1]);
+
+            let _ = ground.try_set(coord, biome.tile);
+
+            if let Some((feature, density)) = biome.feature {
+                if features.value(coord.x, coord.y) < density {
+                    let _ = decoration.try_set(coord, feature);
+                }
+            }
+        }
+
+        let mut map = Map::with_tile_set(tile_set.clone());
+        map.layers.push(ground);
+        map.layers.push(decoration);
+        if let Some(levels) = self.levels {
+            map.heights = Some(Heights::from_elevation(&elevation, self.width, self.height, levels));
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::resource::Resource, testing};
+
+    fn tile_set() -> TileSet {
+        testing::tile_set(&["sand", "dirt", "stone", "castle"])
+    }
+
+    fn generator(config: &str) -> Result<TerrainGenerator, LoadError> {
+        let res = Resource::from_string(config).unwrap();
+        TerrainGenerator::from_resource(&res.node("biomes"), &tile_set(), 24, 16)
+    }
+
+    const CONFIG: &str = "
+        seed: 1234
+        scale: 6.0
+        levels: 4
+        table:
+          - tile: sand
+            elevation: [0.0, 0.4]
+          - tile: dirt
+            elevation: [0.4, 0.6]
+            feature:
+              tile: castle
+              density: 0.5
+          - tile: stone
+            elevation: [0.6, 1.0]
+    ";
+
+    #[test]
+    fn same_seed_same_map() {
+        let tiles = tile_set();
+        let a = generator(CONFIG).unwrap().generate(&tiles);
+        let b = generator(CONFIG).unwrap().generate(&tiles);
+        let mut other = generator(CONFIG).unwrap();
+        other.seed = 4321;
+        let c = other.generate(&tiles);
+
+        assert_eq!(a.content_hash(), b.content_hash());
+        assert_ne!(a.content_hash(), c.content_hash());
+    }
+
+    #[test]
+    fn tiles_follow_the_biome_table() {
+        let tiles = tile_set();
+        let generator = generator(CONFIG).unwrap();
+        let map = generator.generate(&tiles);
+        let elevation = generator.elevation();
+        let (sand, dirt, stone, castle) = (tiles.find("sand").unwrap(), tiles.find("dirt").unwrap(), tiles.find("stone").unwrap(), tiles.find("castle").unwrap());
+
+        assert_eq!(map.layers.len(), 2);
+        for (i, coord) in map.layers[0].coords().enumerate() {
+            let expected = match elevation[i] {
+                e if e < 0.4 => sand,
+                e if e < 0.6 => dirt,
+                _ => stone,
+            };
+            assert!(map.layers[0].try_get(coord) == Some(expected));
+
+            let feature = map.layers[1].try_get(coord).unwrap();
+            assert!(feature == unsafe { TileIndex::new(0) } || (feature == castle && expected == dirt));
+
+            let level = map.height_at(coord.x as u32, coord.y as u32);
+            assert_eq!(level, (elevation[i] * 4.0) as u8);
+        }
+        assert!(map.layers[1].coords().any(|coord| map.layers[1].try_get(coord) == Some(castle)));
+    }
+
+    #[test]
+    fn broken_tables_are_errors() {
+        for broken in &[
+            "table: []",
+            "table: [{tile: lava}]",
+            "{scale: 0.0, table: [{tile: sand}]}",
+            "{levels: 0, table: [{tile: sand}]}",
+            "table: [{tile: sand, elevation: [0.5, 0.2]}]",
+            "table: [{tile: sand, feature: {tile: castle}}]",
+        ] {
+            assert!(generator(broken).is_err(), "{}", broken);
+        }
+        assert!(generator("table: [{tile: sand}]").unwrap().levels.is_none());
+    }
+}
@@ -0,0 +1,349 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use crate::{generation::noise::Noise, grid::{self, Coord, Edge}, io::{error::LoadError, resource::Node}, map::{Layer, TileIndex, TileSet}};
+
+// What lies outside the layer when counting neighbors
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Border {
+    Wrap,
+    Clamp,
+    Solid,
+}
+
+// cellular:
+//   seed: 0
+//   iterations: 5
+//   fill: 0.45
+//   rule: "B5678/S45678"
+//   radius: 1
+//   border: wrap
+//   background: "stone"
+//   tiles:
+//     - tile: "dirt"
+//       weight: 3.0
+//     - tile: "sand"
+//       weight: 1.0
+//
+// Cells start alive with the probability fill and pick one of the tiles by weight, dead cells are background.
+// A dead cell is born when its number of alive neighbors is listed in B and takes the most common tile among
+// them, an alive cell survives when its count is listed in S. Counts above 9 can be written as B12,13 or B10-14.
+#[derive(Clone)]
+pub struct CellularConfig {
+    pub width: u32,
+    pub height: u32,
+    pub iterations: u32,
+    pub seed: u64,
+    pub fill: f32,
+    pub birth: Vec<u32>,
+    pub survive: Vec<u32>,
+    pub radius: u32,
+    pub border: Border,
+    pub background: TileIndex,
+    pub tiles: Vec<(TileIndex, f32)>,
+}
+
+fn parse_counts(counts: &str) -> Option<Vec<u32>> {
+    let mut parsed = Vec::new();
+
+    if counts.contains(',') || counts.contains('-') {
+        for token in counts.split(',') {
+            match token.find('-') {
+                Some(dash) => {
+                    let from: u32 = token[..dash].trim().parse().ok()?;
+                    let to: u32 = token[dash + 1..].trim().parse().ok()?;
+                    parsed.extend(from..=to);
+                },
+                None => parsed.push(token.trim().parse().ok()?),
+            }
+        }
+    } else {
+        for c in counts.chars() {
+            parsed.push(c.to_digit(10)?);
+        }
+    }
+
+    Some(parsed)
+}
+
+// Parses rules written as B3/S23
+pub fn parse_rule(rule: &str) -> Option<(Vec<u32>, Vec<u32>)> {
+    let mut birth = None;
+    let mut survive = None;
+
+    for part in rule.split('/') {
+        let part = part.trim();
+        match part.chars().next()? {
+            'B' | 'b' => birth = Some(parse_counts(&part[1..])?),
+            'S' | 's' => survive = Some(parse_counts(&part[1..])?),
+            _ => return None,
+        }
+    }
+
+    Some((birth?, survive?))
+}
+
+impl CellularConfig {
+    pub fn new(width: u32, height: u32, iterations: u32, alive: TileIndex, dead: TileIndex) -> CellularConfig {
+        CellularConfig {
+            width,
+            height,
+            iterations,
+            seed: 0,
+            fill: 0.5,
+            birth: vec![5, 6, 7, 8],
+            survive: vec![4, 5, 6, 7, 8],
+            radius: 1,
+            border: Border::Wrap,
+            background: dead,
+            tiles: vec![(alive, 1.0)],
+        }
+    }
+
+    pub fn from_resource(res: &Node, tile_set: &TileSet, width: u32, height: u32) -> Result<CellularConfig, LoadError> {
+        let tile = |res: &Node| -> Result<TileIndex, LoadError> {
+            let name = res.as_str()?;
+            tile_set.find(name).ok_or_else(|| res.error(&format!("unknown tile \"{}\"", name)))
+        };
+
+        let background = tile(&res.get("background")?)?;
+
+        let mut tiles = Vec::new();
+        for tile_d in res.get("tiles")?.as_vec()? {
+            let weight = match tile_d.get_optional("weight") {Some(x) => x.as_f64()? as f32, None => 1.0};
+            tiles.push((tile(&tile_d.get("tile")?)?, weight));
+        }
+        if tiles.is_empty() {
+            return Err(res.get("tiles")?.error("at least one alive tile is needed"));
+        }
+
+        let mut config = CellularConfig::new(width, height, 5, tiles[0].0, background);
+        config.tiles = tiles;
+
+        config.width = match res.get_optional("width") {Some(x) => x.as_u32()?, None => width};
+        config.height = match res.get_optional("height") {Some(x) => x.as_u32()?, None => height};
+        config.iterations = match res.get_optional("iterations") {Some(x) => x.as_u32()?, None => 5};
+        config.seed = match res.get_optional("seed") {Some(x) => x.as_i64()? as u64, None => 0};
+        config.fill = match res.get_optional("fill") {Some(x) => x.as_f64()? as f32, None => 0.5};
+        config.radius = match res.get_optional("radius") {Some(x) => x.as_u32()?, None => 1};
+
+        if let Some(rule) = res.get_optional("rule") {
+            let (birth, survive) = parse_rule(rule.as_str()?).ok_or_else(|| rule.error("invalid rule, expected something like B5678/S45678"))?;
+            config.birth = birth;
+            config.survive = survive;
+        }
+
+        if let Some(border) = res.get_optional("border") {
+            config.border = match border.as_str()? {
+                "wrap" => Border::Wrap,
+                "clamp" => Border::Clamp,
+                "solid" => Border::Solid,
+                other => return Err(border.error(&format!("unknown border \"{}\", expected wrap, clamp or solid", other))),
+            };
+        }
+
+        if config.radius == 0 {
+            return Err(res.get("radius")?.error("radius has to be at least 1"));
+        }
+
+        Ok(config)
+    }
+
+    pub fn generate(&self) -> Layer {
+        let (width, height) = (self.width, self.height);
+        let cells_of = grid::region(Coord::new(0, 0), width, height);
+
+        // 0 is dead, alive cells store 1 + their index in tiles
+        let mut cells = vec![0usize; (self.width * self.height) as usize];
+        let mut next = cells.clone();
+
+        let fill_noise = Noise::new(self.seed);
+        let tile_noise = Noise::new(fill_noise.hash(-1, -1));
+        let total_weight: f32 = self.tiles.iter().map(|(_, weight)| weight).sum();
+
+        for (i, coord) in cells_of.clone().enumerate() {
+            if fill_noise.value(coord.x, coord.y) < self.fill {
+                let mut pick = tile_noise.value(coord.x, coord.y) * total_weight;
+                let mut class = self.tiles.len();
+                for (c, (_, weight)) in self.tiles.iter().enumerate() {
+                    if pick < *weight {
+                        class = c + 1;
+                        break;
+                    }
+                    pick -= weight;
+                }
+                cells[i] = class;
+            }
+        }
+
+        let edge = match self.border {
+            Border::Wrap => Edge::Wrap,
+            Border::Clamp => Edge::Clamp,
+            Border::Solid => Edge::None,
+        };
+        let mut class_counts = vec![0u32; self.tiles.len() + 1];
+
+        for _ in 0..self.iterations {
+            for (index, coord) in cells_of.clone().enumerate() {
+                let mut alive = 0;
+                for count in class_counts.iter_mut() {
+                    *count = 0;
+                }
+
+                for neighbor in grid::area(coord, self.radius) {
+                    if neighbor == coord {
+                        continue;
+                    }
+
+                    // Solid borders count as alive, the others are resolved to a cell inside
+                    let class = match edge.apply(neighbor, width, height).and_then(|n| n.index(width, height)) {
+                        Some(i) => cells[i],
+                        None => {
+                            alive += 1;
+                            continue;
+                        },
+                    };
+                    if class != 0 {
+                        alive += 1;
+                        class_counts[class] += 1;
+                    }
+                }
+
+                next[index] = if cells[index] != 0 {
+                    if self.survive.contains(&alive) { cells[index] } else { 0 }
+                } else if self.birth.contains(&alive) {
+                    // Born cells take after their most common neighbor, solid borders alone give the first tile
+                    let mut class = 1;
+                    for c in 1..class_counts.len() {
+                        if class_counts[c] > class_counts[class] {
+                            class = c;
+                        }
+                    }
+                    class
+                } else {
+                    0
+                };
+            }
+            std::mem::swap(&mut cells, &mut next);
+        }
+
+        let mut layer = Layer::new(self.width, self.height);
+        for (coord, class) in cells_of.zip(cells) {
+            let tile = if class == 0 { self.background } else { self.tiles[class - 1].0 };
+            let _ = layer.try_set(coord, tile);
+        }
+
+        layer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::resource::Resource, testing};
+
+    fn tile_set() -> TileSet {
+        testing::tile_set(&["stone", "dirt", "sand"])
+    }
+
+    fn config(config: &str) -> Result<CellularConfig, LoadError> {
+        let res = Resource::from_string(config).unwrap();
+        CellularConfig::from_resource(&res.node("cellular"), &tile_set(), 12, 9)
+    }
+
+    fn count(layer: &Layer, tile: TileIndex) -> usize {
+        layer.coords().filter(|&coord| layer.try_get(coord) == Some(tile)).count()
+    }
+
+    #[test]
+    fn rules_parse() {
+        assert_eq!(parse_rule("B3/S23"), Some((vec![3], vec![2, 3])));
+        assert_eq!(parse_rule("s23/b3"), Some((vec![3], vec![2, 3])));
+        assert_eq!(parse_rule("B5678/S"), Some((vec![5, 6, 7, 8], vec![])));
+        assert_eq!(parse_rule("B12,13/S10-12,20"), Some((vec![12, 13], vec![10, 11, 12, 20])));
+        assert_eq!(parse_rule("B3 / S2-3"), Some((vec![3], vec![2, 3])));
+
+        for broken in &["", "B3", "S23", "B3/X23", "B3a/S23", "B1-/S2", "B,/S2"] {
+            assert_eq!(parse_rule(broken), None, "{}", broken);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_layer() {
+        let config = config("{seed: 9, fill: 0.5, background: stone, tiles: [{tile: dirt, weight: 3.0}, {tile: sand}]}").unwrap();
+        let a = config.generate();
+        let b = config.generate();
+        assert!(a.coords().all(|coord| a.try_get(coord) == b.try_get(coord)));
+
+        let tiles = tile_set();
+        let (stone, dirt, sand) = (tiles.find("stone").unwrap(), tiles.find("dirt").unwrap(), tiles.find("sand").unwrap());
+        assert_eq!((a.width, a.height), (12, 9));
+        assert_eq!(count(&a, stone) + count(&a, dirt) + count(&a, sand), 12 * 9);
+    }
+
+    #[test]
+    fn fill_picks_tiles_by_weight() {
+        let config = config("{seed: 4, fill: 1.0, iterations: 0, width: 40, height: 40, background: stone, tiles: [{tile: dirt, weight: 3.0}, {tile: sand}]}").unwrap();
+        let layer = config.generate();
+        let tiles = tile_set();
+
+        assert_eq!(count(&layer, tiles.find("stone").unwrap()), 0);
+        let (dirt, sand) = (count(&layer, tiles.find("dirt").unwrap()), count(&layer, tiles.find("sand").unwrap()));
+        assert!(dirt > sand * 2 && dirt < sand * 4, "{} dirt, {} sand", dirt, sand);
+    }
+
+    #[test]
+    fn solid_borders_count_as_alive() {
+        let tiles = tile_set();
+        let (stone, dirt) = (tiles.find("stone").unwrap(), tiles.find("dirt").unwrap());
+
+        // Corners have five neighbors outside the layer, the rest of the edge three
+        let solid = config("{fill: 0.0, iterations: 1, rule: B5/S, border: solid, background: stone, tiles: [{tile: dirt}]}").unwrap().generate();
+        assert_eq!(count(&solid, dirt), 4);
+        for &corner in &[(0, 0), (11, 0), (0, 8), (11, 8)] {
+            assert!(solid.try_get(corner) == Some(dirt));
+        }
+
+        let edges = config("{fill: 0.0, iterations: 1, rule: B3/S, border: solid, background: stone, tiles: [{tile: dirt}]}").unwrap().generate();
+        assert_eq!(count(&edges, dirt), 2 * 10 + 2 * 7);
+
+        for border in &["wrap", "clamp"] {
+            let layer = config(&format!("{{fill: 0.0, iterations: 1, rule: B3/S, border: {}, background: stone, tiles: [{{tile: dirt}}]}}", border)).unwrap().generate();
+            assert_eq!(count(&layer, stone), 12 * 9);
+        }
+    }
+
+    #[test]
+    fn broken_configs_are_errors() {
+        for broken in &[
+            "{tiles: [{tile: dirt}]}",
+            "{background: stone, tiles: []}",
+            "{background: lava, tiles: [{tile: dirt}]}",
+            "{background: stone, tiles: [{tile: dirt}], rule: B3}",
+            "{background: stone, tiles: [{tile: dirt}], border: mirror}",
+            "{background: stone, tiles: [{tile: dirt}], radius: 0}",
+        ] {
+            assert!(config(broken).is_err(), "{}", broken);
+        }
+    }
+}
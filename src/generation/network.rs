@@ -0,0 +1,525 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::{collections::{HashSet, VecDeque}, fmt};
+
+use crate::{generation::noise::Noise, grid::{self, Coord}, io::{error::LoadError, resource::Node}, map::{Layer, Map, TileIndex, TileSet}, pathfinding::{self, PathOptions}, terrain::Terrain};
+
+// network:
+//   seed: 0
+//   layer: 2
+//   rivers:
+//     tile: "river"
+//     count: 3
+//     sources: ["mountain"]
+//     water: ["water"]
+//   roads:
+//     tile: "road"
+//     bridge: "bridge"
+//     objects: ["city"]
+//     tags: ["building"]
+//     movement_class: "default"
+//     bridge_cost: 4
+//
+// Carves rivers and lays roads on top of an existing map, into their own layer. Rivers spring next to tiles tagged
+// with one of sources and flow downhill until they reach a tile tagged with water or join another river, without
+// water they run off the edge of the map. Downhill follows the map's heights, on level ground rivers head for the
+// closest water. Roads join every object of the listed kinds and every tile with one of the tags, each one to the
+// closest already joined along the cheapest path. Where a road crosses a river it gets the bridge tile, without
+// one the road is laid over the river. Give the river, road and bridge tiles autotiles that connect to each other
+// to draw them as connected segments.
+#[derive(Clone)]
+pub struct NetworkGenerator {
+    pub seed: u64,
+    pub layer: usize,
+    pub rivers: Option<Rivers>,
+    pub roads: Option<Roads>,
+}
+
+#[derive(Clone)]
+pub struct Rivers {
+    pub tile: TileIndex,
+    pub count: u32,
+    pub sources: Vec<String>,
+    pub water: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Roads {
+    pub tile: TileIndex,
+    pub bridge: Option<TileIndex>,
+    pub objects: Vec<String>,
+    pub tags: Vec<String>,
+    pub movement_class: String,
+    pub bridge_cost: u32,
+}
+
+// What a run of the generator ended up with
+pub struct NetworkReport {
+    pub rivers: u32,
+    pub roads: u32,
+    pub bridges: u32,
+    // Road ends no path could be found to
+    pub unconnected: Vec<(u32, u32)>,
+}
+
+impl fmt::Display for NetworkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rivers and {} roads with {} bridges", self.rivers, self.roads, self.bridges)?;
+        if !self.unconnected.is_empty() {
+            write!(f, ", {} places could not be reached by road", self.unconnected.len())?;
+        }
+        Ok(())
+    }
+}
+
+fn tile(res: &Node, tile_set: &TileSet) -> Result<TileIndex, LoadError> {
+    let name = res.as_str()?;
+    tile_set.find(name).ok_or_else(|| res.error(&format!("unknown tile \"{}\"", name)))
+}
+
+fn strings(res: Option<Node>, default: &[&str]) -> Result<Vec<String>, LoadError> {
+    match res {
+        Some(res) => res.as_vec()?.iter().map(|s| s.as_str().map(|s| s.to_string())).collect(),
+        None => Ok(default.iter().map(|s| s.to_string()).collect()),
+    }
+}
+
+fn has_any_tag(terrain: &Terrain, tags: &[String]) -> bool {
+    tags.iter().any(|tag| terrain.has_tag(tag))
+}
+
+impl NetworkGenerator {
+    pub fn new(seed: u64) -> NetworkGenerator {
+        NetworkGenerator {
+            seed,
+            layer: 2,
+            rivers: None,
+            roads: None,
+        }
+    }
+
+    pub fn from_resource(res: &Node, tile_set: &TileSet) -> Result<NetworkGenerator, LoadError> {
+        let mut generator = NetworkGenerator::new(0);
+
+        generator.seed = match res.get_optional("seed") {Some(x) => x.as_i64()? as u64, None => 0};
+        generator.layer = match res.get_optional("layer") {Some(x) => x.as_u32()? as usize, None => 2};
+
+        if let Some(rivers) = res.get_optional("rivers") {
+            generator.rivers = Some(Rivers {
+                tile: tile(&rivers.get("tile")?, tile_set)?,
+                count: match rivers.get_optional("count") {Some(x) => x.as_u32()?, None => 1},
+                sources: strings(rivers.get_optional("sources"), &["mountain"])?,
+                water: strings(rivers.get_optional("water"), &["water"])?,
+            });
+        }
+
+        if let Some(roads) = res.get_optional("roads") {
+            let objects = strings(roads.get_optional("objects"), &["city"])?;
+            for kind in &objects {
+                if tile_set.object_kind(kind).is_none() {
+                    return Err(roads.get("objects")?.error(&format!("unknown object kind \"{}\"", kind)));
+                }
+            }
+
+            generator.roads = Some(Roads {
+                tile: tile(&roads.get("tile")?, tile_set)?,
+                bridge: match roads.get_optional("bridge") {Some(x) => Some(tile(&x, tile_set)?), None => None},
+                objects,
+                tags: strings(roads.get_optional("tags"), &[])?,
+                movement_class: match roads.get_optional("movement_class") {Some(x) => x.as_str()?.to_string(), None => "default".to_string()},
+                bridge_cost: match roads.get_optional("bridge_cost") {Some(x) => x.as_u32()?, None => 4},
+            });
+        }
+
+        Ok(generator)
+    }
+
+    // Replaces the network layer of the map, adding empty layers below it when the map has too few
+    pub fn apply(&self, map: &mut Map) -> NetworkReport {
+        let (width, height) = (map.width(), map.height());
+
+        while map.layers.len() <= self.layer {
+            map.layers.push(Layer::new(width, height));
+        }
+        map.layers[self.layer] = Layer::new(width, height);
+
+        let mut layer = Layer::new(width, height);
+        let mut report = NetworkReport { rivers: 0, roads: 0, bridges: 0, unconnected: Vec::new() };
+
+        if let Some(rivers) = &self.rivers {
+            let elevation = self.elevation(map, rivers);
+            report.rivers = self.carve_rivers(map, rivers, &elevation, &mut layer);
+        }
+
+        if let Some(roads) = &self.roads {
+            let (built, count, unconnected) = self.lay_roads(map, roads, &layer);
+            for tile in built {
+                let crossing = layer.try_get(tile).map_or(false, |tile| tile != unsafe { TileIndex::new(0) });
+                match roads.bridge {
+                    Some(bridge) if crossing => {
+                        let _ = layer.try_set(tile, bridge);
+                        report.bridges += 1;
+                    },
+                    _ => {
+                        let _ = layer.try_set(tile, roads.tile);
+                    },
+                }
+            }
+            report.roads = count;
+            report.unconnected = unconnected;
+        }
+
+        map.layers[self.layer] = layer;
+        report
+    }
+
+    // One value per tile in Layer::coords order that rivers flow down. Levels of the map's heights come first,
+    // the distance to water decides within a level.
+    fn elevation(&self, map: &Map, rivers: &Rivers) -> Vec<f32> {
+        let mut elevation = self.distance_to_water(map, rivers);
+
+        if let Some(heights) = &map.heights {
+            let span = elevation.iter().filter(|e| **e < f32::MAX).fold(0.0, |a: f32, b| a.max(*b)) + 1.0;
+            for (e, c) in elevation.iter_mut().zip(grid::region(Coord::new(0, 0), map.width(), map.height())) {
+                if *e < f32::MAX {
+                    *e += heights.get(c.x as u32, c.y as u32) as f32 * span;
+                }
+            }
+        }
+
+        elevation
+    }
+
+    // Steps to the closest water tile, or the edges of the map that don't wrap when there is no water, without
+    // crossing the sources of rivers. A little noise breaks the ties so rivers don't all run straight. Tiles cut
+    // off from the water get f32::MAX.
+    fn distance_to_water(&self, map: &Map, rivers: &Rivers) -> Vec<f32> {
+        let (width, height) = (map.width(), map.height());
+        let coords = grid::region(Coord::new(0, 0), width, height);
+        let mut distance = vec![u32::MAX; (width * height) as usize];
+        let mut queue = VecDeque::new();
+
+        let has_water = coords.clone().any(|c| has_any_tag(map.terrain(c.x as u32, c.y as u32), &rivers.water));
+        for (i, c) in coords.clone().enumerate() {
+            let at_edge = (!map.wrap.horizontal() && (c.x == 0 || c.x as u32 == width - 1)) || (!map.wrap.vertical() && (c.y == 0 || c.y as u32 == height - 1));
+            let water = if has_water { has_any_tag(map.terrain(c.x as u32, c.y as u32), &rivers.water) } else { at_edge };
+            if water {
+                distance[i] = 0;
+                queue.push_back(c);
+            }
+        }
+
+        while let Some(c) = queue.pop_front() {
+            let d = match c.index(width, height) {
+                Some(i) => distance[i],
+                None => continue,
+            };
+            for n in map.topology.neighbors_in(c, width, height, false, map.wrap.edge()) {
+                let i = match n.index(width, height) {
+                    Some(i) => i,
+                    None => continue,
+                };
+                if distance[i] == u32::MAX && !has_any_tag(map.terrain(n.x as u32, n.y as u32), &rivers.sources) {
+                    distance[i] = d + 1;
+                    queue.push_back(n);
+                }
+            }
+        }
+
+        let noise = Noise::new(self.seed);
+        coords.zip(distance).map(|(c, d)| if d == u32::MAX { f32::MAX } else { d as f32 + noise.value(c.x, c.y) * 0.5 }).collect()
+    }
+
+    fn carve_rivers(&self, map: &Map, rivers: &Rivers, elevation: &[f32], layer: &mut Layer) -> u32 {
+        let (width, height) = (map.width(), map.height());
+        let topology = map.topology;
+        let terrain = |c: Coord| map.terrain(c.x as u32, c.y as u32);
+        let height_at = |c: Coord| c.index(width, height).map(|i| elevation[i]).unwrap_or(f32::MAX);
+        let is_source = |c: Coord| has_any_tag(terrain(c), &rivers.sources);
+        let is_water = |c: Coord| has_any_tag(terrain(c), &rivers.water);
+
+        // Springs lie at the foot of the high ground, visited in an order picked by the seed
+        let noise = Noise::new(self.seed);
+        let mut springs: Vec<Coord> = grid::region(Coord::new(0, 0), width, height)
+            .filter(|&c| !is_source(c) && !is_water(c) && height_at(c) < f32::MAX)
+            .filter(|&c| topology.neighbors_in(c, width, height, false, map.wrap.edge()).any(is_source))
+            .collect();
+        springs.sort_by_key(|c| noise.hash(c.x, c.y));
+
+        let mut carved = 0;
+        for spring in springs {
+            if carved >= rivers.count {
+                break;
+            }
+            // Keep new rivers from starting right next to old ones
+            if grid::area(spring, 2).any(|c| layer.try_get(c) == Some(rivers.tile)) {
+                continue;
+            }
+
+            let mut current = spring;
+            let mut course = vec![current];
+            loop {
+                let lowest = topology.neighbors_in(current, width, height, false, map.wrap.edge())
+                    .filter(|&n| !is_source(n))
+                    .min_by(|&a, &b| height_at(a).partial_cmp(&height_at(b)).unwrap_or(std::cmp::Ordering::Equal));
+
+                let next = match lowest {
+                    Some(next) if height_at(next) < height_at(current) => next,
+                    _ => break,
+                };
+                if is_water(next) || layer.try_get(next) == Some(rivers.tile) {
+                    break;
+                }
+                course.push(next);
+                current = next;
+            }
+
+            // A single tile puddle is not worth a river
+            if course.len() < 2 {
+                continue;
+            }
+            for c in course {
+                let _ = layer.try_set(c, rivers.tile);
+            }
+            carved += 1;
+        }
+
+        carved
+    }
+
+    // The places roads join, in the order they are joined
+    fn road_ends(&self, map: &Map, roads: &Roads) -> Vec<(u32, u32)> {
+        let mut ends: Vec<(u32, u32)> = map.object_layers.iter()
+            .flat_map(|layer| &layer.objects)
+            .filter(|object| roads.objects.contains(&object.kind))
+            .map(|object| (object.x, object.y))
+            .collect();
+
+        for c in grid::region(Coord::new(0, 0), map.width(), map.height()) {
+            let tile = (c.x as u32, c.y as u32);
+            if !ends.contains(&tile) && has_any_tag(map.terrain(tile.0, tile.1), &roads.tags) {
+                ends.push(tile);
+            }
+        }
+
+        ends
+    }
+
+    // Returns the tiles to put road on, the number of roads and the ends that could not be reached. The ends keep
+    // their own tiles.
+    fn lay_roads(&self, map: &Map, roads: &Roads, rivers: &Layer) -> (HashSet<(u32, u32)>, u32, Vec<(u32, u32)>) {
+        let (width, height) = (map.width(), map.height());
+        let options = PathOptions { topology: map.topology, wrap: map.wrap, ..PathOptions::new() };
+        let mut ends = self.road_ends(map, roads);
+        let mut built = HashSet::new();
+        let mut count = 0;
+        let mut unconnected = Vec::new();
+
+        if ends.is_empty() {
+            return (built, count, unconnected);
+        }
+
+        // Join the closest ends first so the network grows outwards from the first one
+        let first = ends.remove(0);
+        ends.sort_by_key(|&end| map.distance(first, end, options.diagonal));
+        let mut joined = vec![first];
+
+        for end in ends {
+            let closest = *joined.iter()
+                .min_by_key(|&&other| map.distance(end, other, options.diagonal))
+                .unwrap_or(&first);
+
+            // Following an existing road is cheaper than breaking new ground, crossing a river costs a bridge
+            let path = pathfinding::find_path(width, height, end, closest, &options, |_, (x, y)| {
+                let cost = map.movement_cost(x, y, &roads.movement_class)?;
+                if built.contains(&(x, y)) {
+                    Some(1)
+                } else if rivers.try_get((x, y)).map_or(false, |tile| tile != unsafe { TileIndex::new(0) }) {
+                    Some(cost * 2 + roads.bridge_cost)
+                } else {
+                    Some(cost * 2)
+                }
+            });
+
+            match path {
+                Some(path) => {
+                    built.extend(path.tiles);
+                    joined.push(end);
+                    count += 1;
+                },
+                None => unconnected.push(end),
+            }
+        }
+
+        for end in &joined {
+            built.remove(end);
+        }
+        (built, count, unconnected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{elevation::Heights, io::resource::Resource, map_object::{MapObject, ObjectLayer}, testing};
+
+    const DATA: &str = "
+atlas:
+  - path: \"none.png\"
+textures:
+  - name: \"plain\"
+    bounds: [0, 0, 16, 16]
+tiles:
+  - name: \"stone\"
+    texture: \"plain\"
+    terrain:
+      passable: false
+      tags: [\"mountain\"]
+  - name: \"water\"
+    texture: \"plain\"
+    terrain:
+      passable: false
+      tags: [\"water\"]
+  - name: \"land\"
+    texture: \"plain\"
+  - name: \"river\"
+    texture: \"plain\"
+  - name: \"road\"
+    texture: \"plain\"
+  - name: \"bridge\"
+    texture: \"plain\"
+objects:
+  - kind: \"city\"
+    texture: \"plain\"
+";
+
+    // Land with a mountain in the middle of the top row and water below it, so every river that springs at the
+    // mountain cuts the cities on either side apart
+    fn valley() -> Map {
+        let tiles = testing::data_tile_set(DATA, "data").unwrap();
+        let (stone, water, land) = (tiles.find("stone").unwrap(), tiles.find("water").unwrap(), tiles.find("land").unwrap());
+
+        let mut ground = Layer::new(7, 5);
+        for c in ground.coords().collect::<Vec<_>>() {
+            let tile = match (c.x, c.y) {
+                (3, 4) => stone,
+                (3, 0) => water,
+                _ => land,
+            };
+            ground.try_set(c, tile).unwrap();
+        }
+
+        let mut map = Map::with_tile_set(tiles);
+        map.layers.push(ground);
+        let mut objects = ObjectLayer::new("objects");
+        objects.objects.push(MapObject::new("city", 0, 2));
+        objects.objects.push(MapObject::new("city", 6, 2));
+        map.object_layers.push(objects);
+        map
+    }
+
+    fn generator(map: &Map, config: &str) -> NetworkGenerator {
+        let res = Resource::from_string(config).unwrap();
+        NetworkGenerator::from_resource(&res.node("network"), map.tile_set()).unwrap()
+    }
+
+    fn tiles_of(map: &Map, layer: usize, tile: &str) -> Vec<Coord> {
+        let tile = map.tile_set().find(tile).unwrap();
+        map.layers[layer].coords().filter(|&c| map.layers[layer].try_get(c) == Some(tile)).collect()
+    }
+
+    #[test]
+    fn roads_bridge_rivers() {
+        for seed in 0..8 {
+            let config = format!("{{seed: {}, layer: 1, rivers: {{tile: river}}, roads: {{tile: road, bridge: bridge}}}}", seed);
+
+            let mut map = valley();
+            let report = generator(&map, &config).apply(&mut map);
+            assert_eq!((report.rivers, report.roads), (1, 1));
+            assert!(report.unconnected.is_empty());
+            assert!(report.bridges >= 1);
+
+            let mut rivers_only = valley();
+            let rivers_config = format!("{{seed: {}, layer: 1, rivers: {{tile: river}}}}", seed);
+            generator(&rivers_only, &rivers_config).apply(&mut rivers_only);
+            let rivers = tiles_of(&rivers_only, 1, "river");
+
+            let bridges = tiles_of(&map, 1, "bridge");
+            assert_eq!(bridges.len() as u32, report.bridges);
+            assert!(bridges.iter().all(|c| rivers.contains(c)));
+            assert!(tiles_of(&map, 1, "road").iter().all(|c| !rivers.contains(c)));
+        }
+    }
+
+    #[test]
+    fn without_a_bridge_roads_cover_rivers() {
+        let mut map = valley();
+        let report = generator(&map, "{layer: 1, rivers: {tile: river}, roads: {tile: road}}").apply(&mut map);
+        assert_eq!((report.rivers, report.roads, report.bridges), (1, 1, 0));
+        assert!(tiles_of(&map, 1, "bridge").is_empty());
+        assert!(!tiles_of(&map, 1, "road").is_empty());
+    }
+
+    #[test]
+    fn heights_decide_downhill() {
+        let mut map = valley();
+        let network = generator(&map, "{rivers: {tile: river}}");
+        let rivers = network.rivers.clone().unwrap();
+        let index = |x: u32, y: u32| Coord::new(x as i32, y as i32).index(7, 5).unwrap();
+
+        // On level ground the closest water is downhill
+        let elevation = network.elevation(&map, &rivers);
+        assert!(elevation[index(3, 1)] < elevation[index(3, 2)]);
+        assert!(elevation[index(0, 1)] > elevation[index(2, 1)]);
+        assert_eq!(elevation[index(3, 4)], f32::MAX);
+
+        // A raised tile next to the water lies above tiles further away
+        let mut heights = Heights::new(7, 5);
+        heights.set(2, 1, 1);
+        map.heights = Some(heights);
+        let elevation = network.elevation(&map, &rivers);
+        assert!(elevation[index(2, 1)] > elevation[index(0, 0)]);
+        assert!(elevation[index(3, 1)] < elevation[index(3, 2)]);
+    }
+
+    #[test]
+    fn unknown_tiles_are_an_error() {
+        let map = valley();
+        let res = Resource::from_string("{roads: {tile: road, bridge: ford}}").unwrap();
+        assert!(NetworkGenerator::from_resource(&res.node("network"), map.tile_set()).is_err());
+    }
+
+    #[test]
+    fn shipped_data_draws_connected_segments() {
+        let path = "res/data_jt.yaml";
+        let tiles = TileSet::from_file_headless(path).unwrap();
+        let data = Resource::from_file(path).unwrap();
+        let network = NetworkGenerator::from_resource(&data.node(path).get("network").unwrap(), &tiles).unwrap();
+
+        let roads = network.roads.unwrap();
+        for tile in &[network.rivers.unwrap().tile, roads.tile, roads.bridge.unwrap()] {
+            assert!(tiles.tile(*tile).autotile.is_some(), "{} has no autotile", tiles.tile(*tile).name);
+        }
+    }
+}
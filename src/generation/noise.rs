@@ -0,0 +1,127 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+// Hash based value noise, the same seed gives the same values on every machine
+#[derive(Clone, Copy)]
+pub struct Noise {
+    seed: u64,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Noise {
+        Noise { seed }
+    }
+
+    pub fn hash(&self, x: i32, y: i32) -> u64 {
+        let mut h = self.seed ^ (x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+        h ^= h >> 30;
+        h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        h ^= h >> 27;
+        h = h.wrapping_mul(0x94D0_49BB_1331_11EB);
+        h ^ (h >> 31)
+    }
+
+    // Value in [0, 1) for a single lattice point
+    pub fn value(&self, x: i32, y: i32) -> f32 {
+        (self.hash(x, y) >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn sample(&self, x: f32, y: f32) -> f32 {
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = smooth(x - x0);
+        let ty = smooth(y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let a = lerp(self.value(x0, y0), self.value(x0 + 1, y0), tx);
+        let b = lerp(self.value(x0, y0 + 1), self.value(x0 + 1, y0 + 1), tx);
+
+        lerp(a, b, ty)
+    }
+
+    // Sum of octaves with halving amplitude and doubling frequency, normalized back to [0, 1)
+    pub fn fractal(&self, x: f32, y: f32, octaves: u32) -> f32 {
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut max = 0.0;
+
+        for octave in 0..octaves.max(1) {
+            let octave_noise = Noise::new(self.seed.wrapping_add(octave as u64));
+            total += octave_noise.sample(x * frequency, y * frequency) * amplitude;
+            max += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+
+        total / max
+    }
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_values() {
+        let (a, b, other) = (Noise::new(42), Noise::new(42), Noise::new(43));
+        for i in -20..20 {
+            let (x, y) = (i as f32 * 0.37, i as f32 * -1.3);
+            assert_eq!(a.sample(x, y), b.sample(x, y));
+            assert_eq!(a.fractal(x, y, 4), b.fractal(x, y, 4));
+        }
+        assert!((-20..20).any(|i| a.value(i, 0) != other.value(i, 0)));
+    }
+
+    #[test]
+    fn values_stay_in_range() {
+        let noise = Noise::new(7);
+        for x in -30..30 {
+            for y in -30..30 {
+                let (fx, fy) = (x as f32 * 0.29, y as f32 * 0.41);
+                assert!((0.0..1.0).contains(&noise.value(x, y)));
+                assert!((0.0..1.0).contains(&noise.sample(fx, fy)));
+                assert!((0.0..1.0).contains(&noise.fractal(fx, fy, 5)));
+            }
+        }
+    }
+
+    #[test]
+    fn samples_pass_through_the_lattice() {
+        let noise = Noise::new(3);
+        for x in -5..5 {
+            for y in -5..5 {
+                assert_eq!(noise.sample(x as f32, y as f32), noise.value(x, y));
+            }
+        }
+        assert_eq!(noise.fractal(1.5, 2.5, 0), noise.fractal(1.5, 2.5, 1));
+    }
+}
@@ -0,0 +1,559 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::{f32::consts::PI, fmt};
+
+use crate::{analysis::Regions, generation::noise::Noise, grid::{self, Coord}, io::{error::LoadError, resource::Node}, map::{Layer, Map, TileSet}, map_object::{MapObject, ObjectLayer}, pathfinding::PathOptions};
+
+// How the copies of player 0's sector are laid out. Horizontal mirrors left to right and Vertical bottom to top for
+// two players, Both mirrors both ways for four. Rotational turns N copies around the center of the map, which is
+// exact for 2 and for 4 on square maps and rounded to the closest tile otherwise.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Symmetry {
+    Horizontal,
+    Vertical,
+    Both,
+    Rotational(u32),
+}
+
+impl Symmetry {
+    // symmetry:
+    //   type: rotational
+    //   players: 3
+    pub fn from_resource(res: &Node) -> Result<Symmetry, LoadError> {
+        let kind = res.get("type")?;
+        match kind.as_str()? {
+            "horizontal" => Ok(Symmetry::Horizontal),
+            "vertical" => Ok(Symmetry::Vertical),
+            "both" => Ok(Symmetry::Both),
+            "rotational" => {
+                let players = res.get("players")?;
+                match players.as_u32()? {
+                    0 => Err(players.error("players has to be at least 1")),
+                    n => Ok(Symmetry::Rotational(n)),
+                }
+            },
+            other => Err(kind.error(&format!("unknown symmetry \"{}\", expected horizontal, vertical, both or rotational", other))),
+        }
+    }
+
+    pub fn players(&self) -> u32 {
+        match self {
+            Symmetry::Horizontal | Symmetry::Vertical => 2,
+            Symmetry::Both => 4,
+            Symmetry::Rotational(n) => *n,
+        }
+    }
+
+    // Which player's sector a tile lies in. Tiles on the border between sectors go to the lower player.
+    pub fn sector(&self, x: u32, y: u32, width: u32, height: u32) -> u32 {
+        let (a, b) = doubled(x, y, width, height);
+
+        match self {
+            Symmetry::Horizontal => (a > 0) as u32,
+            Symmetry::Vertical => (b > 0) as u32,
+            Symmetry::Both => (a > 0) as u32 + 2 * (b > 0) as u32,
+            Symmetry::Rotational(n) if a == 0 && b == 0 || *n <= 1 => 0,
+            // Counted counter clockwise from the positive x axis, kept in integers where possible to avoid
+            // rounding on the axes
+            Symmetry::Rotational(2) => if b > 0 || (b == 0 && a > 0) { 0 } else { 1 },
+            Symmetry::Rotational(4) => {
+                if a > 0 && b >= 0 { 0 } else if a <= 0 && b > 0 { 1 } else if a < 0 && b <= 0 { 2 } else { 3 }
+            },
+            Symmetry::Rotational(n) => {
+                let angle = (b as f32).atan2(a as f32).rem_euclid(2.0 * PI);
+                (angle / (2.0 * PI) * *n as f32) as u32 % n
+            },
+        }
+    }
+
+    // Where player's copy of a tile lies, None when it falls off the map
+    pub fn image(&self, player: u32, x: u32, y: u32, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (a, b) = doubled(x, y, width, height);
+
+        let (a, b) = match self {
+            Symmetry::Horizontal => if player % 2 == 1 { (-a, b) } else { (a, b) },
+            Symmetry::Vertical => if player % 2 == 1 { (a, -b) } else { (a, b) },
+            Symmetry::Both => (if player & 1 != 0 { -a } else { a }, if player & 2 != 0 { -b } else { b }),
+            Symmetry::Rotational(n) => {
+                let n = (*n).max(1);
+                let player = player % n;
+                if (4 * player) % n == 0 {
+                    match 4 * player / n {
+                        0 => (a, b),
+                        1 => (-b, a),
+                        2 => (-a, -b),
+                        _ => (b, -a),
+                    }
+                } else {
+                    let angle = 2.0 * PI * player as f32 / n as f32;
+                    let (sin, cos) = angle.sin_cos();
+                    let (a, b) = (a as f32, b as f32);
+                    let (x, y) = (a * cos - b * sin, a * sin + b * cos);
+                    return undoubled(x, y, width, height);
+                }
+            },
+        };
+
+        undoubled(a as f32, b as f32, width, height)
+    }
+
+    // The tile in player 0's sector a tile is a copy of
+    pub fn source(&self, x: u32, y: u32, width: u32, height: u32) -> Option<(u32, u32)> {
+        let sector = self.sector(x, y, width, height);
+        let inverse = match self {
+            Symmetry::Rotational(n) => {
+                let n = (*n).max(1);
+                (n - sector) % n
+            },
+            _ => sector,
+        };
+        self.image(inverse, x, y, width, height)
+    }
+
+    // A copy of the layer where every sector repeats player 0's. Tiles whose source falls off the map keep their tile.
+    pub fn apply(&self, layer: &Layer) -> Layer {
+        let mut out = layer.clone();
+        for coord in layer.coords() {
+            let (x, y) = (coord.x as u32, coord.y as u32);
+            if let Some(tile) = self.source(x, y, layer.width, layer.height).and_then(|source| layer.try_get(source)) {
+                let _ = out.try_set(coord, tile);
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for Symmetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Symmetry::Horizontal => write!(f, "horizontal"),
+            Symmetry::Vertical => write!(f, "vertical"),
+            Symmetry::Both => write!(f, "both"),
+            Symmetry::Rotational(n) => write!(f, "rotational {}", n),
+        }
+    }
+}
+
+// Twice the offset from the center of the map, which keeps the center of even sized maps on whole numbers
+fn doubled(x: u32, y: u32, width: u32, height: u32) -> (i32, i32) {
+    (2 * x as i32 - (width as i32 - 1), 2 * y as i32 - (height as i32 - 1))
+}
+
+fn undoubled(a: f32, b: f32, width: u32, height: u32) -> Option<(u32, u32)> {
+    let x = ((a + width as f32 - 1.0) / 2.0).round() as i32;
+    let y = ((b + height as f32 - 1.0) / 2.0).round() as i32;
+    Coord::new(x, y).to_u32().filter(|&(x, y)| x < width && y < height)
+}
+
+// symmetry:
+//   type: both
+//   seed: 0
+//   movement_class: "default"
+//   resources:
+//     - kind: "city"
+//       count: 2
+//
+// Turns a map from any of the other generators into a symmetric one. Player 0's sector is kept and copied to the
+// others, along with the objects in it. Player 0 starts on the first start in its sector, or the passable tile of
+// the sector furthest from the center. Resources are objects placed per player on random tiles that can be
+// reached from the start.
+#[derive(Clone)]
+pub struct SymmetricGenerator {
+    pub symmetry: Symmetry,
+    pub seed: u64,
+    pub movement_class: String,
+    pub resources: Vec<(String, u32)>,
+}
+
+impl SymmetricGenerator {
+    pub fn new(symmetry: Symmetry) -> SymmetricGenerator {
+        SymmetricGenerator {
+            symmetry,
+            seed: 0,
+            movement_class: "default".to_string(),
+            resources: Vec::new(),
+        }
+    }
+
+    pub fn from_resource(res: &Node, tile_set: &TileSet) -> Result<SymmetricGenerator, LoadError> {
+        let mut generator = SymmetricGenerator::new(Symmetry::from_resource(res)?);
+
+        generator.seed = match res.get_optional("seed") {Some(x) => x.as_i64()? as u64, None => 0};
+        if let Some(class) = res.get_optional("movement_class") {
+            generator.movement_class = class.as_str()?.to_string();
+        }
+
+        if let Some(resources) = res.get_optional("resources") {
+            for resource_d in resources.as_vec()? {
+                let kind_d = resource_d.get("kind")?;
+                let kind = kind_d.as_str()?;
+                if tile_set.object_kind(kind).is_none() {
+                    return Err(kind_d.error(&format!("unknown object kind \"{}\"", kind)));
+                }
+                generator.resources.push((kind.to_string(), resource_d.get("count")?.as_u32()?));
+            }
+        }
+
+        Ok(generator)
+    }
+
+    pub fn apply(&self, map: &mut Map) -> Result<(), String> {
+        if map.topology.is_hex() {
+            return Err("symmetric maps need a square grid".to_string());
+        }
+
+        let symmetry = self.symmetry;
+        let players = symmetry.players();
+        let (width, height) = (map.width(), map.height());
+        let in_sector = |(x, y): (u32, u32)| symmetry.sector(x, y, width, height) == 0;
+        // Turning a map that isn't square can take the copies of a tile off it
+        let has_copies = |(x, y): (u32, u32)| (0..players).all(|player| symmetry.image(player, x, y, width, height).is_some());
+
+        for layer in &mut map.layers {
+            if layer.width != width || layer.height != height {
+                layer.reframe(width, height, 0, 0);
+            }
+            *layer = symmetry.apply(layer);
+        }
+
+        if let Some(heights) = &mut map.heights {
+            let source = heights.clone();
+            for coord in grid::region(Coord::new(0, 0), width, height) {
+                let (x, y) = (coord.x as u32, coord.y as u32);
+                if let Some((sx, sy)) = symmetry.source(x, y, width, height) {
+                    heights.set(x, y, source.get(sx, sy));
+                }
+            }
+        }
+
+        for object_layer in &mut map.object_layers {
+            let objects: Vec<MapObject> = object_layer.objects.drain(..).filter(|object| in_sector((object.x, object.y))).collect();
+            for object in objects {
+                object_layer.objects.extend(self.copies(&object, width, height));
+            }
+        }
+
+        let regions = Regions::of_map(map, &self.movement_class, &PathOptions::new());
+        let usable = |tile: (u32, u32)| in_sector(tile) && has_copies(tile) && regions.region_at(tile.0, tile.1).is_some();
+
+        // Furthest from the center means closest to the edge of the sector, so the copies end up far apart
+        let start = match map.info.starts.iter().copied().find(|&start| usable(start)) {
+            Some(start) => start,
+            None => grid::region(Coord::new(0, 0), width, height)
+                .map(|coord| (coord.x as u32, coord.y as u32))
+                .filter(|&tile| usable(tile))
+                .max_by_key(|&(x, y)| {
+                    let (a, b) = doubled(x, y, width, height);
+                    (a * a + b * b, std::cmp::Reverse((y, x)))
+                })
+                .ok_or_else(|| "player 0's sector has no passable tile for a start".to_string())?,
+        };
+
+        let mut starts = Vec::new();
+        for player in 0..players {
+            starts.extend(symmetry.image(player, start.0, start.1, width, height));
+        }
+        map.info.starts = starts;
+        map.info.players = players;
+
+        if !self.resources.is_empty() {
+            let region = regions.region_at(start.0, start.1);
+            let taken = |tile: (u32, u32), map: &Map| map.info.starts.contains(&tile) || map.object_at(tile.0, tile.1).is_some();
+
+            let noise = Noise::new(self.seed);
+            let mut candidates: Vec<(u32, u32)> = grid::region(Coord::new(0, 0), width, height)
+                .map(|coord| (coord.x as u32, coord.y as u32))
+                .filter(|&tile| usable(tile) && regions.region_at(tile.0, tile.1) == region)
+                .collect();
+            candidates.sort_by_key(|&(x, y)| noise.hash(x as i32, y as i32));
+
+            if map.object_layers.is_empty() {
+                map.object_layers.push(ObjectLayer::new("objects"));
+            }
+
+            let mut candidates = candidates.into_iter();
+            for (kind, count) in &self.resources {
+                let object = match map.tile_set().object_kind(kind) {
+                    Some(kind) => kind.create(0, 0),
+                    None => MapObject::new(kind, 0, 0),
+                };
+
+                for _ in 0..*count {
+                    let tile = candidates.by_ref().find(|&tile| !taken(tile, map)).ok_or_else(|| format!("no room left for {}", kind))?;
+                    let copies = self.copies(&MapObject { x: tile.0, y: tile.1, ..object.clone() }, width, height);
+                    map.object_layers[0].objects.extend(copies);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // One copy of an object in player 0's sector per player, tiles on a sector border get only one
+    fn copies(&self, object: &MapObject, width: u32, height: u32) -> Vec<MapObject> {
+        let mut copies: Vec<MapObject> = Vec::new();
+        for player in 0..self.symmetry.players() {
+            if let Some((x, y)) = self.symmetry.image(player, object.x, object.y, width, height) {
+                if !copies.iter().any(|copy| copy.x == x && copy.y == y) {
+                    copies.push(MapObject { x, y, ..object.clone() });
+                }
+            }
+        }
+        copies
+    }
+}
+
+// How well a map matches a symmetry, each part is the share of tiles, objects and starts whose copies for the other
+// players match them
+#[derive(Clone, Copy, Debug)]
+pub struct SymmetryScore {
+    pub tiles: f32,
+    pub objects: f32,
+    pub starts: f32,
+}
+
+impl SymmetryScore {
+    // A map is only as fair as its least symmetric part
+    pub fn total(&self) -> f32 {
+        self.tiles.min(self.objects).min(self.starts)
+    }
+}
+
+impl fmt::Display for SymmetryScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.0}% (tiles {:.0}%, objects {:.0}%, starts {:.0}%)", self.total() * 100.0, self.tiles * 100.0, self.objects * 100.0, self.starts * 100.0)
+    }
+}
+
+pub fn score(map: &Map, symmetry: Symmetry) -> SymmetryScore {
+    let (width, height) = (map.width(), map.height());
+    let images = |x: u32, y: u32| (1..symmetry.players()).filter_map(move |player| symmetry.image(player, x, y, width, height));
+    let share = |matches: u32, total: u32| if total == 0 { 1.0 } else { matches as f32 / total as f32 };
+
+    let (mut matches, mut total) = (0, 0);
+    for layer in &map.layers {
+        for coord in layer.coords() {
+            let (x, y) = (coord.x as u32, coord.y as u32);
+            let tile = layer.try_get(coord);
+            for image in images(x, y) {
+                total += 1;
+                if layer.try_get(image) == tile {
+                    matches += 1;
+                }
+            }
+        }
+    }
+    let tiles = share(matches, total);
+
+    let (mut matches, mut total) = (0, 0);
+    for object in map.object_layers.iter().flat_map(|layer| &layer.objects) {
+        for (x, y) in images(object.x, object.y) {
+            total += 1;
+            if map.object_layers.iter().any(|layer| layer.objects.iter().any(|other| other.kind == object.kind && other.x == x && other.y == y)) {
+                matches += 1;
+            }
+        }
+    }
+    let objects = share(matches, total);
+
+    let (mut matches, mut total) = (0, 0);
+    for &(x, y) in &map.info.starts {
+        for image in images(x, y) {
+            total += 1;
+            if map.info.starts.contains(&image) {
+                matches += 1;
+            }
+        }
+    }
+    let starts = share(matches, total);
+
+    SymmetryScore { tiles, objects, starts }
+}
+
+// The best scoring of the common symmetries and a rotational one for the map's player count
+pub fn detect(map: &Map) -> (Symmetry, SymmetryScore) {
+    let mut candidates = vec![Symmetry::Horizontal, Symmetry::Vertical, Symmetry::Both, Symmetry::Rotational(2), Symmetry::Rotational(4)];
+    if map.info.players > 1 && !candidates.contains(&Symmetry::Rotational(map.info.players)) {
+        candidates.push(Symmetry::Rotational(map.info.players));
+    }
+
+    let mut best = (candidates[0], score(map, candidates[0]));
+    for symmetry in candidates.into_iter().skip(1) {
+        let score = score(map, symmetry);
+        if score.total() > best.1.total() {
+            best = (symmetry, score);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::{HexCoordinates, HexOrientation, Topology}, io::resource::Resource, map::TileIndex, testing};
+
+    const EXACT: [(Symmetry, u32, u32); 6] = [
+        (Symmetry::Horizontal, 7, 4),
+        (Symmetry::Vertical, 6, 5),
+        (Symmetry::Both, 6, 4),
+        (Symmetry::Rotational(2), 7, 5),
+        (Symmetry::Rotational(4), 6, 6),
+        (Symmetry::Rotational(4), 5, 5),
+    ];
+
+    fn tiles(width: u32, height: u32) -> impl Iterator<Item = (u32, u32)> {
+        grid::region(Coord::new(0, 0), width, height).map(|coord| (coord.x as u32, coord.y as u32))
+    }
+
+    #[test]
+    fn copies_land_where_expected() {
+        assert_eq!(Symmetry::Horizontal.image(1, 0, 1, 6, 4), Some((5, 1)));
+        assert_eq!(Symmetry::Vertical.image(1, 0, 1, 6, 4), Some((0, 2)));
+        assert_eq!(Symmetry::Both.image(3, 1, 0, 6, 4), Some((4, 3)));
+        assert_eq!(Symmetry::Rotational(2).image(1, 1, 0, 6, 4), Some((4, 3)));
+        assert_eq!(Symmetry::Rotational(4).image(1, 3, 0, 4, 4), Some((3, 3)));
+        assert_eq!(Symmetry::Rotational(4).image(1, 0, 0, 6, 2), None);
+
+        assert_eq!(Symmetry::Both.sector(0, 0, 6, 4), 0);
+        assert_eq!(Symmetry::Both.sector(5, 0, 6, 4), 1);
+        assert_eq!(Symmetry::Both.sector(0, 3, 6, 4), 2);
+        assert_eq!(Symmetry::Both.sector(5, 3, 6, 4), 3);
+        assert_eq!(Symmetry::Horizontal.sector(3, 0, 7, 4), 0);
+    }
+
+    #[test]
+    fn every_tile_is_a_copy_of_player_0s_sector() {
+        for &(symmetry, width, height) in &EXACT {
+            for (x, y) in tiles(width, height) {
+                let sector = symmetry.sector(x, y, width, height);
+                assert!(sector < symmetry.players());
+
+                let source = symmetry.source(x, y, width, height).unwrap();
+                assert_eq!(symmetry.sector(source.0, source.1, width, height), 0, "{} {}x{} at {}, {}", symmetry, width, height, x, y);
+                assert_eq!(symmetry.image(sector, source.0, source.1, width, height), Some((x, y)));
+            }
+        }
+
+        // Sectors of rounded rotations still cover the map, every one of them in use
+        let symmetry = Symmetry::Rotational(3);
+        let sectors: Vec<u32> = tiles(9, 9).map(|(x, y)| symmetry.sector(x, y, 9, 9)).collect();
+        assert!((0..3).all(|sector| sectors.contains(&sector)));
+    }
+
+    #[test]
+    fn applied_layers_are_symmetric() {
+        for &(symmetry, width, height) in &EXACT {
+            let mut layer = Layer::new(width, height);
+            let noise = Noise::new(width as u64 * 31 + height as u64);
+            for (x, y) in tiles(width, height) {
+                layer.set(x, y, unsafe { TileIndex::new(1 + (noise.value(x as i32, y as i32) * 5.0) as usize) });
+            }
+
+            let applied = symmetry.apply(&layer);
+            for (x, y) in tiles(width, height) {
+                if symmetry.sector(x, y, width, height) == 0 {
+                    assert!(applied.get(x, y) == layer.get(x, y));
+                }
+                for player in 0..symmetry.players() {
+                    let (ix, iy) = symmetry.image(player, x, y, width, height).unwrap();
+                    assert!(applied.get(ix, iy) == applied.get(x, y));
+                }
+            }
+        }
+    }
+
+    fn open_map(width: u32, height: u32) -> Map {
+        let tile_set = testing::tile_set(&["grass"]);
+        let grass = tile_set.find("grass").unwrap();
+
+        let mut layer = Layer::new(width, height);
+        for (x, y) in tiles(width, height) {
+            layer.set(x, y, grass);
+        }
+        let mut map = Map::with_tile_set(tile_set);
+        map.layers.push(layer);
+        map
+    }
+
+    #[test]
+    fn generated_maps_are_fair() {
+        let mut map = open_map(8, 6);
+        let mut objects = ObjectLayer::new("objects");
+        objects.objects.push(MapObject::new("city", 1, 1));
+        objects.objects.push(MapObject::new("ruin", 7, 5));
+        map.object_layers.push(objects);
+
+        let mut generator = SymmetricGenerator::new(Symmetry::Both);
+        generator.resources.push(("gold".to_string(), 2));
+        generator.apply(&mut map).unwrap();
+
+        assert_eq!(map.info.players, 4);
+        assert_eq!(map.info.starts, vec![(0, 0), (7, 0), (0, 5), (7, 5)]);
+        let kinds = |kind: &str| map.objects().filter(|object| object.kind == kind).count();
+        assert_eq!((kinds("city"), kinds("ruin"), kinds("gold")), (4, 0, 8));
+        assert!(map.objects().all(|object| !map.info.starts.contains(&(object.x, object.y))));
+
+        let score = score(&map, Symmetry::Both);
+        assert_eq!(score.total(), 1.0);
+        // Mirroring both ways also mirrors each way on its own, the first perfect match is reported
+        let (detected, detected_score) = detect(&map);
+        assert_eq!(detected, Symmetry::Horizontal);
+        assert_eq!(detected_score.total(), 1.0);
+        assert!(score.to_string().starts_with("100%"));
+
+        // One tile out of place is noticed
+        map.layers[0].set(2, 2, unsafe { TileIndex::new(0) });
+        assert!(super::score(&map, Symmetry::Both).tiles < 1.0);
+    }
+
+    #[test]
+    fn hex_maps_and_crowded_sectors_are_errors() {
+        let mut map = open_map(4, 4);
+        map.topology = Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset);
+        assert!(SymmetricGenerator::new(Symmetry::Both).apply(&mut map).is_err());
+
+        let mut map = open_map(4, 4);
+        let mut generator = SymmetricGenerator::new(Symmetry::Both);
+        generator.resources.push(("gold".to_string(), 4));
+        assert!(generator.apply(&mut map).is_err());
+    }
+
+    #[test]
+    fn symmetries_load() {
+        let load = |config: &str| {
+            let res = Resource::from_string(config).unwrap();
+            Symmetry::from_resource(&res.node("symmetry"))
+        };
+
+        assert_eq!(load("type: horizontal").unwrap(), Symmetry::Horizontal);
+        assert_eq!(load("type: both").unwrap().players(), 4);
+        assert_eq!(load("{type: rotational, players: 3}").unwrap(), Symmetry::Rotational(3));
+        for broken in &["type: rotational", "{type: rotational, players: 0}", "type: diagonal", "players: 2"] {
+            assert!(load(broken).is_err(), "{}", broken);
+        }
+
+        let res = Resource::from_string("{type: both, resources: [{kind: gold, count: 1}]}").unwrap();
+        assert!(SymmetricGenerator::from_resource(&res.node("symmetry"), &TileSet::new()).is_err());
+    }
+}
@@ -0,0 +1,661 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::collections::HashMap;
+
+use crate::io::{error::LoadError, resource::{Node, Resource}};
+
+const SQRT_3: f32 = 1.732_050_8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HexOrientation {
+    Pointy,
+    Flat,
+}
+
+// Offset stores every other row (pointy) or column (flat) shoved half a tile, which keeps the map rectangular.
+// Axial stores the hex coordinates directly, which turns a rectangular layer into a rhombus on screen.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HexCoordinates {
+    Offset,
+    Axial,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Topology {
+    Square,
+    Hex(HexOrientation, HexCoordinates),
+}
+
+// A tile position. Signed so positions next to a layer, and steps off its edges, can still be written down.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Coord {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Coord {
+    pub fn new(x: i32, y: i32) -> Coord {
+        Coord { x, y }
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32) -> Coord {
+        Coord::new(self.x + dx, self.y + dy)
+    }
+
+    pub fn inside(&self, width: u32, height: u32) -> bool {
+        self.x >= 0 && self.y >= 0 && (self.x as u32) < width && (self.y as u32) < height
+    }
+
+    // Position in a row major vector with the bottom row first, None outside width x height
+    pub fn index(&self, width: u32, height: u32) -> Option<usize> {
+        if self.inside(width, height) {
+            Some(self.x as usize + self.y as usize * width as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_u32(&self) -> Option<(u32, u32)> {
+        if self.x >= 0 && self.y >= 0 { Some((self.x as u32, self.y as u32)) } else { None }
+    }
+}
+
+impl From<(i32, i32)> for Coord {
+    fn from((x, y): (i32, i32)) -> Coord {
+        Coord::new(x, y)
+    }
+}
+
+impl From<(u32, u32)> for Coord {
+    fn from((x, y): (u32, u32)) -> Coord {
+        Coord::new(x as i32, y as i32)
+    }
+}
+
+// What a step off the edge of a width x height area lands on
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Edge {
+    // Nothing, the step is dropped
+    None,
+    // The closest tile on the edge
+    Clamp,
+    // The tile on the opposite side
+    Wrap,
+    // The tile on the opposite side for steps off the left or right, nothing for steps off the top or bottom
+    WrapHorizontal,
+}
+
+impl Edge {
+    pub fn apply(&self, coord: Coord, width: u32, height: u32) -> Option<Coord> {
+        if coord.inside(width, height) {
+            return Some(coord);
+        }
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let (width, height) = (width as i32, height as i32);
+        match self {
+            Edge::None => None,
+            Edge::Clamp => Some(Coord::new(coord.x.max(0).min(width - 1), coord.y.max(0).min(height - 1))),
+            Edge::Wrap => Some(Coord::new(coord.x.rem_euclid(width), coord.y.rem_euclid(height))),
+            Edge::WrapHorizontal if coord.y >= 0 && coord.y < height => Some(Coord::new(coord.x.rem_euclid(width), coord.y)),
+            Edge::WrapHorizontal => None,
+        }
+    }
+}
+
+// How a map joins up at its edges: not at all, left to right like a cylinder, or also top to bottom like a torus
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Wrap {
+    None,
+    Horizontal,
+    Both,
+}
+
+impl Wrap {
+    // grid:
+    //   type: square
+    //   wrap: horizontal
+    pub fn from_resource(res: Option<Node>) -> Result<Wrap, LoadError> {
+        let wrap = match res.and_then(|res| res.get_optional("wrap")) {
+            Some(wrap) => wrap,
+            None => return Ok(Wrap::None),
+        };
+
+        match wrap.as_str()? {
+            "none" => Ok(Wrap::None),
+            "horizontal" => Ok(Wrap::Horizontal),
+            "both" => Ok(Wrap::Both),
+            other => Err(wrap.error(&format!("unknown wrap \"{}\", expected none, horizontal or both", other))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Wrap::None => "none",
+            Wrap::Horizontal => "horizontal",
+            Wrap::Both => "both",
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            Wrap::None => 0,
+            Wrap::Horizontal => 1,
+            Wrap::Both => 2,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Wrap> {
+        match byte {
+            0 => Some(Wrap::None),
+            1 => Some(Wrap::Horizontal),
+            2 => Some(Wrap::Both),
+            _ => None,
+        }
+    }
+
+    pub fn horizontal(&self) -> bool {
+        *self != Wrap::None
+    }
+
+    pub fn vertical(&self) -> bool {
+        *self == Wrap::Both
+    }
+
+    // What a step off the edge of the map lands on
+    pub fn edge(&self) -> Edge {
+        match self {
+            Wrap::None => Edge::None,
+            Wrap::Horizontal => Edge::WrapHorizontal,
+            Wrap::Both => Edge::Wrap,
+        }
+    }
+
+    // Offset hex rows (pointy) or columns (flat) alternate, so the wrapped direction needs an even number of them
+    // for the seam to line up
+    pub fn check(&self, topology: Topology, width: u32, height: u32) -> Result<(), String> {
+        match topology {
+            Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset) if self.vertical() && height % 2 == 1 => Err(format!("can't wrap a pointy offset hex map with an odd height ({}) vertically", height)),
+            Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset) if self.horizontal() && width % 2 == 1 => Err(format!("can't wrap a flat offset hex map with an odd width ({}) horizontally", width)),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Largest number of tiles a layer loaded from a file may have, anything larger is most likely a broken file
+pub const MAX_TILES: u64 = 1 << 26;
+
+// Number of tiles in a width x height layer, checked against MAX_TILES so sizes read from files can't overflow
+pub fn tile_count(width: u32, height: u32) -> Result<usize, String> {
+    let count = width as u64 * height as u64;
+    if count > MAX_TILES {
+        return Err(format!("a {}x{} layer is too large, at most {} tiles are allowed", width, height, MAX_TILES));
+    }
+    Ok(count as usize)
+}
+
+// Every position in a rectangle, bottom row first and left to right within a row
+pub fn region(origin: Coord, width: u32, height: u32) -> impl Iterator<Item = Coord> + Clone {
+    (0..height as i32).flat_map(move |y| (0..width as i32).map(move |x| origin.offset(x, y)))
+}
+
+// The square of positions within radius steps of center, center itself included
+pub fn area(center: Coord, radius: u32) -> impl Iterator<Item = Coord> + Clone {
+    let side = radius * 2 + 1;
+    region(center.offset(-(radius as i32), -(radius as i32)), side, side)
+}
+
+const SQUARE_DIRECTIONS: [(i32, i32); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (-1, 1), (1, -1), (-1, -1)];
+const AXIAL_DIRECTIONS: [(i32, i32); 6] = [(1, 0), (1, -1), (0, -1), (-1, 0), (-1, 1), (0, 1)];
+
+impl Topology {
+    // grid:
+    //   type: hex
+    //   orientation: pointy
+    //   coordinates: offset
+    pub fn from_resource(res: Option<Node>) -> Result<Topology, LoadError> {
+        let res = match res {
+            Some(res) => res,
+            None => return Ok(Topology::Square),
+        };
+
+        match res.get("type")?.as_str()? {
+            "square" => Ok(Topology::Square),
+            "hex" => {
+                let orientation = match res.get_optional("orientation") {
+                    Some(o) => match o.as_str()? {
+                        "pointy" => HexOrientation::Pointy,
+                        "flat" => HexOrientation::Flat,
+                        other => return Err(o.error(&format!("unknown hex orientation \"{}\", expected pointy or flat", other))),
+                    },
+                    None => HexOrientation::Pointy,
+                };
+                let coordinates = match res.get_optional("coordinates") {
+                    Some(c) => match c.as_str()? {
+                        "offset" => HexCoordinates::Offset,
+                        "axial" => HexCoordinates::Axial,
+                        other => return Err(c.error(&format!("unknown hex coordinates \"{}\", expected offset or axial", other))),
+                    },
+                    None => HexCoordinates::Offset,
+                };
+                Ok(Topology::Hex(orientation, coordinates))
+            },
+            other => Err(res.get("type")?.error(&format!("unknown grid type \"{}\", expected square or hex", other))),
+        }
+    }
+
+    pub fn to_resource(&self) -> Resource {
+        let mut grid = HashMap::new();
+
+        match self {
+            Topology::Square => {
+                grid.insert("type".to_string(), Resource::Str("square".to_string()));
+            },
+            Topology::Hex(orientation, coordinates) => {
+                grid.insert("type".to_string(), Resource::Str("hex".to_string()));
+                grid.insert("orientation".to_string(), Resource::Str(match orientation {
+                    HexOrientation::Pointy => "pointy",
+                    HexOrientation::Flat => "flat",
+                }.to_string()));
+                grid.insert("coordinates".to_string(), Resource::Str(match coordinates {
+                    HexCoordinates::Offset => "offset",
+                    HexCoordinates::Axial => "axial",
+                }.to_string()));
+            },
+        }
+
+        Resource::Map(grid)
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            Topology::Square => 0,
+            Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset) => 1,
+            Topology::Hex(HexOrientation::Pointy, HexCoordinates::Axial) => 2,
+            Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset) => 3,
+            Topology::Hex(HexOrientation::Flat, HexCoordinates::Axial) => 4,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Topology> {
+        match byte {
+            0 => Some(Topology::Square),
+            1 => Some(Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset)),
+            2 => Some(Topology::Hex(HexOrientation::Pointy, HexCoordinates::Axial)),
+            3 => Some(Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset)),
+            4 => Some(Topology::Hex(HexOrientation::Flat, HexCoordinates::Axial)),
+            _ => None,
+        }
+    }
+
+    pub fn is_hex(&self) -> bool {
+        match self {
+            Topology::Hex(_, _) => true,
+            Topology::Square => false,
+        }
+    }
+
+    // Neighbors are not clipped to any map, callers throw away the ones outside their layer
+    pub fn neighbors(&self, x: i32, y: i32, diagonal: bool) -> Vec<(i32, i32)> {
+        match self {
+            Topology::Square => {
+                let directions = if diagonal { &SQUARE_DIRECTIONS[..] } else { &SQUARE_DIRECTIONS[..4] };
+                directions.iter().map(|(dx, dy)| (x + dx, y + dy)).collect()
+            },
+            Topology::Hex(_, _) => {
+                let (q, r) = self.to_axial(x, y);
+                AXIAL_DIRECTIONS.iter().map(|(dq, dr)| self.from_axial(q + dq, r + dr)).collect()
+            },
+        }
+    }
+
+    // Neighbors of a tile in a width x height area, steps off the edge are handled by edge. Clamping can return the
+    // tile itself or the same neighbor twice.
+    pub fn neighbors_in(&self, coord: Coord, width: u32, height: u32, diagonal: bool, edge: Edge) -> impl Iterator<Item = Coord> {
+        self.neighbors(coord.x, coord.y, diagonal).into_iter().filter_map(move |n| edge.apply(n.into(), width, height))
+    }
+
+    // The copy of b closest to a on a width x height map that wraps, b itself when nothing wraps. The result can be
+    // outside the map, use it for measuring and walking towards b.
+    pub fn closest_copy(&self, a: (i32, i32), b: (i32, i32), diagonal: bool, wrap: Wrap, width: u32, height: u32) -> (i32, i32) {
+        let (width, height) = (width as i32, height as i32);
+        let xs: &[i32] = if wrap.horizontal() { &[-1, 0, 1] } else { &[0] };
+        let ys: &[i32] = if wrap.vertical() { &[-1, 0, 1] } else { &[0] };
+
+        let mut closest = b;
+        for kx in xs {
+            for ky in ys {
+                let copy = (b.0 + kx * width, b.1 + ky * height);
+                if self.distance(a, copy, diagonal) < self.distance(a, closest, diagonal) {
+                    closest = copy;
+                }
+            }
+        }
+        closest
+    }
+
+    // Distance the short way around a map that wraps
+    pub fn wrapped_distance(&self, a: (i32, i32), b: (i32, i32), diagonal: bool, wrap: Wrap, width: u32, height: u32) -> u32 {
+        self.distance(a, self.closest_copy(a, b, diagonal, wrap, width, height), diagonal)
+    }
+
+    pub fn distance(&self, a: (i32, i32), b: (i32, i32), diagonal: bool) -> u32 {
+        match self {
+            Topology::Square => {
+                let dx = (a.0 - b.0).abs() as u32;
+                let dy = (a.1 - b.1).abs() as u32;
+                if diagonal { dx.max(dy) } else { dx + dy }
+            },
+            Topology::Hex(_, _) => {
+                let (aq, ar) = self.to_axial(a.0, a.1);
+                let (bq, br) = self.to_axial(b.0, b.1);
+                let dq = aq - bq;
+                let dr = ar - br;
+                ((dq.abs() + dr.abs() + (dq + dr).abs()) / 2) as u32
+            },
+        }
+    }
+
+    pub fn to_axial(&self, x: i32, y: i32) -> (i32, i32) {
+        match self {
+            Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset) => (x - (y - (y & 1)) / 2, y),
+            Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset) => (x, y - (x - (x & 1)) / 2),
+            _ => (x, y),
+        }
+    }
+
+    pub fn from_axial(&self, q: i32, r: i32) -> (i32, i32) {
+        match self {
+            Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset) => (q + (r - (r & 1)) / 2, r),
+            Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset) => (q, r + (q - (q & 1)) / 2),
+            _ => (q, r),
+        }
+    }
+
+    // Size of the rect a tile is drawn into, hexes are one unit across their flat sides
+    pub fn tile_size(&self) -> (f32, f32) {
+        match self {
+            Topology::Square => (1.0, 1.0),
+            Topology::Hex(HexOrientation::Pointy, _) => (1.0, 2.0 / SQRT_3),
+            Topology::Hex(HexOrientation::Flat, _) => (2.0 / SQRT_3, 1.0),
+        }
+    }
+
+    pub fn tile_center(&self, x: i32, y: i32) -> (f32, f32) {
+        match self {
+            Topology::Square => (x as f32 + 0.5, y as f32 + 0.5),
+            Topology::Hex(HexOrientation::Pointy, _) => {
+                let (q, r) = self.to_axial(x, y);
+                (q as f32 + r as f32 / 2.0 + 0.5, r as f32 * SQRT_3 / 2.0 + 0.5)
+            },
+            Topology::Hex(HexOrientation::Flat, _) => {
+                let (q, r) = self.to_axial(x, y);
+                (q as f32 * SQRT_3 / 2.0 + 0.5, r as f32 + q as f32 / 2.0 + 0.5)
+            },
+        }
+    }
+
+    // Bottom left corner of the rect a tile is drawn into
+    pub fn tile_origin(&self, x: i32, y: i32) -> (f32, f32) {
+        let (cx, cy) = self.tile_center(x, y);
+        let (w, h) = self.tile_size();
+        (cx - w / 2.0, cy - h / 2.0)
+    }
+
+    // Inverse of tile_center, used for mouse picking
+    pub fn tile_at(&self, x: f32, y: f32) -> (i32, i32) {
+        let x = x - 0.5;
+        let y = y - 0.5;
+
+        match self {
+            Topology::Square => ((x + 0.5).floor() as i32, (y + 0.5).floor() as i32),
+            Topology::Hex(HexOrientation::Pointy, _) => {
+                let r = y * 2.0 / SQRT_3;
+                let q = x - r / 2.0;
+                let (q, r) = round_axial(q, r);
+                self.from_axial(q, r)
+            },
+            Topology::Hex(HexOrientation::Flat, _) => {
+                let q = x * 2.0 / SQRT_3;
+                let r = y - q / 2.0;
+                let (q, r) = round_axial(q, r);
+                self.from_axial(q, r)
+            },
+        }
+    }
+}
+
+fn round_axial(q: f32, r: f32) -> (i32, i32) {
+    let s = -q - r;
+
+    let mut rq = q.round();
+    let mut rr = r.round();
+    let rs = s.round();
+
+    let dq = (rq - q).abs();
+    let dr = (rr - r).abs();
+    let ds = (rs - s).abs();
+
+    if dq > dr && dq > ds {
+        rq = -rr - rs;
+    } else if dr > ds {
+        rr = -rq - rs;
+    }
+
+    (rq as i32, rr as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEXES: [Topology; 4] = [
+        Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset),
+        Topology::Hex(HexOrientation::Pointy, HexCoordinates::Axial),
+        Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset),
+        Topology::Hex(HexOrientation::Flat, HexCoordinates::Axial),
+    ];
+
+    fn sorted(mut tiles: Vec<(i32, i32)>) -> Vec<(i32, i32)> {
+        tiles.sort();
+        tiles
+    }
+
+    #[test]
+    fn odd_offset_rows_are_shoved_right() {
+        let pointy = Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset);
+        assert_eq!(sorted(pointy.neighbors(1, 1, false)), vec![(0, 1), (1, 0), (1, 2), (2, 0), (2, 1), (2, 2)]);
+        assert_eq!(sorted(pointy.neighbors(1, 2, false)), vec![(0, 1), (0, 2), (0, 3), (1, 1), (1, 3), (2, 2)]);
+
+        let flat = Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset);
+        assert_eq!(sorted(flat.neighbors(1, 1, false)), vec![(0, 1), (0, 2), (1, 0), (1, 2), (2, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn hex_neighbors_are_one_step_away_both_ways() {
+        for topology in &HEXES {
+            for x in -3..4 {
+                for y in -3..4 {
+                    let neighbors = topology.neighbors(x, y, true);
+                    let mut unique = sorted(neighbors.clone());
+                    unique.dedup();
+                    assert_eq!(unique.len(), 6);
+                    for &(nx, ny) in &neighbors {
+                        assert_eq!(topology.distance((x, y), (nx, ny), false), 1);
+                        assert!(topology.neighbors(nx, ny, false).contains(&(x, y)));
+                    }
+                    let (q, r) = topology.to_axial(x, y);
+                    assert_eq!(topology.from_axial(q, r), (x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn square_neighbors_and_distances() {
+        assert_eq!(sorted(Topology::Square.neighbors(0, 0, false)), vec![(-1, 0), (0, -1), (0, 1), (1, 0)]);
+        assert_eq!(Topology::Square.neighbors(0, 0, true).len(), 8);
+        assert_eq!(Topology::Square.distance((0, 0), (3, -2), false), 5);
+        assert_eq!(Topology::Square.distance((0, 0), (3, -2), true), 3);
+
+        let pointy = Topology::Hex(HexOrientation::Pointy, HexCoordinates::Axial);
+        assert_eq!(pointy.distance((0, 0), (3, -2), false), 3);
+        assert_eq!(pointy.distance((0, 0), (3, 2), false), 5);
+    }
+
+    #[test]
+    fn picking_finds_the_tile_drawn_there() {
+        for topology in HEXES.iter().chain(&[Topology::Square]) {
+            let (w, h) = topology.tile_size();
+            for x in -3..4 {
+                for y in -3..4 {
+                    let (cx, cy) = topology.tile_center(x, y);
+                    assert_eq!(topology.tile_at(cx, cy), (x, y));
+                    // A little off center towards every corner of the inner part of the tile
+                    for &(dx, dy) in &[(0.3, 0.3), (-0.3, 0.3), (0.3, -0.3), (-0.3, -0.3)] {
+                        assert_eq!(topology.tile_at(cx + dx * w * 0.5, cy + dy * h * 0.5), (x, y));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn topologies_survive_saving() {
+        for topology in HEXES.iter().chain(&[Topology::Square]) {
+            let res = topology.to_resource();
+            assert_eq!(Topology::from_resource(Some(res.node("grid"))).unwrap(), *topology);
+            assert_eq!(Topology::from_byte(topology.to_byte()), Some(*topology));
+        }
+        assert_eq!(Topology::from_resource(None).unwrap(), Topology::Square);
+        assert_eq!(Topology::from_byte(5), None);
+
+        let res = Resource::from_string("{type: hex}").unwrap();
+        assert_eq!(Topology::from_resource(Some(res.node("grid"))).unwrap(), HEXES[0]);
+        for broken in &["{type: hex, orientation: sideways}", "{type: hex, coordinates: cube}", "{type: triangle}", "{orientation: flat}"] {
+            let res = Resource::from_string(broken).unwrap();
+            assert!(Topology::from_resource(Some(res.node("grid"))).is_err());
+        }
+    }
+
+    #[test]
+    fn coords_know_when_they_are_inside() {
+        assert!(Coord::new(0, 0).inside(3, 2));
+        assert!(Coord::new(2, 1).inside(3, 2));
+        assert!(!Coord::new(3, 1).inside(3, 2));
+        assert!(!Coord::new(-1, 0).inside(3, 2));
+        assert!(!Coord::new(0, 0).inside(0, 0));
+
+        assert_eq!(Coord::new(2, 1).index(3, 2), Some(5));
+        assert_eq!(Coord::new(0, 2).index(3, 2), None);
+        assert_eq!(Coord::new(1, -1).to_u32(), None);
+        assert_eq!(Coord::from((4u32, 5u32)).offset(-5, 1), Coord::new(-1, 6));
+    }
+
+    #[test]
+    fn edges_decide_where_steps_off_the_area_land() {
+        let apply = |edge: Edge, x, y| edge.apply(Coord::new(x, y), 4, 3).map(|c| (c.x, c.y));
+
+        for &edge in &[Edge::None, Edge::Clamp, Edge::Wrap, Edge::WrapHorizontal] {
+            assert_eq!(apply(edge, 1, 2), Some((1, 2)));
+            assert_eq!(edge.apply(Coord::new(0, 0), 0, 3), None);
+        }
+
+        assert_eq!(apply(Edge::None, -1, 0), None);
+        assert_eq!(apply(Edge::Clamp, -1, 5), Some((0, 2)));
+        assert_eq!(apply(Edge::Wrap, -1, 5), Some((3, 2)));
+        assert_eq!(apply(Edge::Wrap, 9, -4), Some((1, 2)));
+        assert_eq!(apply(Edge::WrapHorizontal, -1, 1), Some((3, 1)));
+        assert_eq!(apply(Edge::WrapHorizontal, 1, 3), None);
+    }
+
+    #[test]
+    fn regions_and_areas_list_every_position_once() {
+        let region = region(Coord::new(1, 1), 3, 2).map(|c| (c.x, c.y)).collect::<Vec<_>>();
+        assert_eq!(region, vec![(1, 1), (2, 1), (3, 1), (1, 2), (2, 2), (3, 2)]);
+
+        let area = area(Coord::new(0, 0), 2).collect::<Vec<_>>();
+        assert_eq!(area.len(), 25);
+        assert!(area.contains(&Coord::new(-2, 2)) && area.contains(&Coord::new(0, 0)));
+        assert_eq!(super::area(Coord::new(5, 5), 0).collect::<Vec<_>>(), vec![Coord::new(5, 5)]);
+
+        assert_eq!(tile_count(3, 2), Ok(6));
+        assert!(tile_count(u32::MAX, u32::MAX).is_err());
+        assert!(tile_count(1 << 13, 1 << 13).is_ok());
+        assert!(tile_count(1 << 13, (1 << 13) + 1).is_err());
+    }
+
+    #[test]
+    fn wraps_load_and_check_the_map_size() {
+        let load = |config: &str| {
+            let res = Resource::from_string(config).unwrap();
+            Wrap::from_resource(Some(res.node("grid")))
+        };
+        assert_eq!(load("{type: square, wrap: horizontal}").unwrap(), Wrap::Horizontal);
+        assert_eq!(load("{type: square, wrap: both}").unwrap(), Wrap::Both);
+        assert_eq!(load("type: square").unwrap(), Wrap::None);
+        assert_eq!(Wrap::from_resource(None).unwrap(), Wrap::None);
+        assert!(load("{type: square, wrap: sphere}").is_err());
+        for &wrap in &[Wrap::None, Wrap::Horizontal, Wrap::Both] {
+            assert_eq!(Wrap::from_byte(wrap.to_byte()), Some(wrap));
+            assert_eq!(load(&format!("wrap: {}", wrap.name())).unwrap(), wrap);
+        }
+        assert_eq!(Wrap::from_byte(3), None);
+
+        let pointy = Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset);
+        let flat = Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset);
+        assert!(Wrap::Horizontal.check(pointy, 5, 5).is_ok());
+        assert!(Wrap::Both.check(pointy, 5, 5).is_err());
+        assert!(Wrap::Both.check(pointy, 5, 6).is_ok());
+        assert!(Wrap::Horizontal.check(flat, 5, 6).is_err());
+        assert!(Wrap::None.check(flat, 5, 5).is_ok());
+        assert!(Wrap::Both.check(Topology::Square, 5, 5).is_ok());
+    }
+
+    #[test]
+    fn distances_go_the_short_way_around() {
+        let square = Topology::Square;
+        assert_eq!(square.closest_copy((0, 0), (9, 0), false, Wrap::Horizontal, 10, 10), (-1, 0));
+        assert_eq!(square.closest_copy((0, 0), (9, 9), false, Wrap::Horizontal, 10, 10), (-1, 9));
+        assert_eq!(square.closest_copy((0, 0), (9, 9), false, Wrap::Both, 10, 10), (-1, -1));
+        assert_eq!(square.closest_copy((0, 0), (9, 9), false, Wrap::None, 10, 10), (9, 9));
+        assert_eq!(square.wrapped_distance((1, 2), (8, 2), false, Wrap::Horizontal, 10, 10), 3);
+        assert_eq!(square.wrapped_distance((1, 2), (8, 2), false, Wrap::None, 10, 10), 7);
+
+        // On hex maps whose rows wrap the seam neighbors are one step apart
+        for topology in &HEXES {
+            for y in 0..4 {
+                for x in 0..4 {
+                    for (nx, ny) in topology.neighbors(x, y, false) {
+                        if let Some(n) = Wrap::Both.edge().apply(Coord::new(nx, ny), 4, 4) {
+                            assert_eq!(topology.wrapped_distance((x, y), (n.x, n.y), false, Wrap::Both, 4, 4), 1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::{fs::File, io::BufWriter};
+
+use crate::io::error::{LoadError, LoadErrorKind};
+
+// An 8 bit RGBA image in memory, rows top to bottom
+#[derive(Clone)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+fn png_error(path: &str, e: png::DecodingError) -> LoadError {
+    LoadError::new(path, "", LoadErrorKind::Parse(e.to_string()))
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    // Reads only the header
+    pub fn png_size(path: &str) -> Result<(u32, u32), LoadError> {
+        let file = File::open(path).map_err(|e| LoadError::new(path, "", LoadErrorKind::Io(e.to_string())))?;
+        let (info, _) = png::Decoder::new(file).read_info().map_err(|e| png_error(path, e))?;
+        Ok((info.width, info.height))
+    }
+
+    pub fn from_png(path: &str) -> Result<Image, LoadError> {
+        let file = File::open(path).map_err(|e| LoadError::new(path, "", LoadErrorKind::Io(e.to_string())))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+        let (info, mut reader) = decoder.read_info().map_err(|e| png_error(path, e))?;
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).map_err(|e| png_error(path, e))?;
+
+        let pixels = match info.color_type {
+            png::ColorType::RGBA => data,
+            png::ColorType::RGB => data.chunks(3).flat_map(|p| vec![p[0], p[1], p[2], 255]).collect(),
+            png::ColorType::GrayscaleAlpha => data.chunks(2).flat_map(|p| vec![p[0], p[0], p[0], p[1]]).collect(),
+            png::ColorType::Grayscale => data.iter().flat_map(|p| vec![*p, *p, *p, 255]).collect(),
+            png::ColorType::Indexed => return Err(LoadError::new(path, "", LoadErrorKind::Parse("indexed png was not expanded".to_string()))),
+        };
+
+        Ok(Image { width: info.width, height: info.height, pixels })
+    }
+
+    pub fn to_png(&self, path: &str) -> Result<(), LoadError> {
+        let error = |e: String| LoadError::new(path, "", LoadErrorKind::Io(e));
+
+        let file = File::create(path).map_err(|e| error(e.to_string()))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(|e| error(e.to_string()))?;
+        writer.write_image_data(&self.pixels).map_err(|e| error(e.to_string()))
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((x + y * self.width) * 4) as usize;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        let i = ((x + y * self.width) * 4) as usize;
+        self.pixels[i..i + 4].copy_from_slice(&color);
+    }
+
+    // Draws color over the pixel using its alpha
+    pub fn blend(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        let alpha = color[3] as u32;
+        if alpha == 0 {
+            return;
+        }
+
+        let under = self.get(x, y);
+        let mut out = [0; 4];
+        for c in 0..3 {
+            out[c] = ((color[c] as u32 * alpha + under[c] as u32 * (255 - alpha)) / 255) as u8;
+        }
+        out[3] = (alpha + under[3] as u32 * (255 - alpha) / 255) as u8;
+        self.set(x, y, out);
+    }
+}
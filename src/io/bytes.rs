@@ -0,0 +1,217 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use crate::io::error::{LoadError, LoadErrorKind};
+
+pub struct ByteWriter {
+    pub data: Vec<u8>,
+}
+
+impl ByteWriter {
+    pub fn new() -> ByteWriter {
+        ByteWriter {
+            data: Vec::new(),
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Unsigned LEB128, small numbers only take a single byte
+    pub fn write_var(&mut self, value: u64) {
+        let mut value = value;
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.data.push(byte);
+                break;
+            }
+            self.data.push(byte | 0x80);
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_var(s.len() as u64);
+        self.write_bytes(s.as_bytes());
+    }
+
+    // Writes the value using exactly width bytes
+    pub fn write_sized(&mut self, value: u32, width: u8) {
+        match width {
+            1 => self.write_u8(value as u8),
+            2 => self.write_u16(value as u16),
+            _ => self.write_u32(value),
+        }
+    }
+}
+
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    name: &'a str,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8], name: &'a str) -> ByteReader<'a> {
+        ByteReader {
+            data,
+            pos: 0,
+            name,
+        }
+    }
+
+    pub fn error(&self, message: &str) -> LoadError {
+        LoadError::new(self.name, &format!("byte {}", self.pos), LoadErrorKind::Invalid(message.to_string()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        // len can come straight from the data, so don't let it overflow
+        if self.pos.checked_add(len).map_or(true, |end| end > self.data.len()) {
+            return Err(self.error(&format!("unexpected end of data, needed {} bytes but only {} are left", len, self.data.len() - self.pos)));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, LoadError> {
+        let b = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, LoadError> {
+        let b = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, LoadError> {
+        let b = self.read_bytes(8)?;
+        Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+
+    pub fn read_var(&mut self) -> Result<u64, LoadError> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift >= 64 {
+                return Err(self.error("variable length number is too long"));
+            }
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn read_str(&mut self) -> Result<String, LoadError> {
+        let len = self.read_var()? as usize;
+        let bytes = self.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error("string is not valid utf-8"))
+    }
+
+    pub fn read_sized(&mut self, width: u8) -> Result<u32, LoadError> {
+        match width {
+            1 => Ok(self.read_u8()? as u32),
+            2 => Ok(self.read_u16()? as u32),
+            4 => self.read_u32(),
+            _ => Err(self.error(&format!("unsupported index width {}", width))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_and_strings_round_trip() {
+        let mut out = ByteWriter::new();
+        out.write_u8(7);
+        out.write_u16(0xBEEF);
+        out.write_u32(0xDEAD_BEEF);
+        out.write_u64(u64::MAX);
+        out.write_var(300);
+        out.write_str("hex");
+        out.write_sized(70_000, 4);
+
+        let mut input = ByteReader::new(&out.data, "test");
+        assert_eq!(input.read_u8().unwrap(), 7);
+        assert_eq!(input.read_u16().unwrap(), 0xBEEF);
+        assert_eq!(input.read_u32().unwrap(), 0xDEAD_BEEF);
+        assert_eq!(input.read_u64().unwrap(), u64::MAX);
+        assert_eq!(input.read_var().unwrap(), 300);
+        assert_eq!(input.read_str().unwrap(), "hex");
+        assert_eq!(input.read_sized(4).unwrap(), 70_000);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn huge_string_length_is_an_error() {
+        let mut out = ByteWriter::new();
+        out.write_var(u64::MAX);
+        out.write_bytes(b"abc");
+
+        let mut input = ByteReader::new(&out.data, "test");
+        input.read_u8().unwrap();
+        assert!(input.read_bytes(usize::MAX).is_err());
+
+        let mut input = ByteReader::new(&out.data, "test");
+        assert!(input.read_str().is_err());
+    }
+
+    #[test]
+    fn reading_past_the_end_is_an_error() {
+        let mut input = ByteReader::new(&[1, 2, 3], "test");
+        assert!(input.read_u32().is_err());
+        assert!(ByteReader::new(&[0x80, 0x80], "test").read_var().is_err());
+    }
+}
@@ -0,0 +1,184 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use crate::{grid, io::{error::LoadError, resource::{Node, Resource}}};
+
+// How the data of a layer is written in a yaml map file.
+// list: one integer per item, the original format
+// rows: one string per row, e.g. "1 1 2 2 0"
+// rle:  one string per row with count*tile runs, e.g. "2*1 2*2 0"
+// Rows are always written top row first.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LayerEncoding {
+    List,
+    Rows,
+    Rle,
+}
+
+impl LayerEncoding {
+    pub fn from_resource(res: Option<Node>) -> Result<LayerEncoding, LoadError> {
+        match res {
+            Some(res) => match res.as_str()? {
+                "list" => Ok(LayerEncoding::List),
+                "rows" => Ok(LayerEncoding::Rows),
+                "rle" => Ok(LayerEncoding::Rle),
+                other => Err(res.error(&format!("unknown encoding \"{}\", expected list, rows or rle", other))),
+            },
+            None => Ok(LayerEncoding::List),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LayerEncoding::List => "list",
+            LayerEncoding::Rows => "rows",
+            LayerEncoding::Rle => "rle",
+        }
+    }
+
+    pub fn encode(&self, tiles: &[u32], width: u32) -> Resource {
+        match self {
+            LayerEncoding::List => Resource::Vec(tiles.iter().map(|tile| Resource::I64(*tile as i64)).collect()),
+            LayerEncoding::Rows => {
+                Resource::Vec(tiles.chunks(width.max(1) as usize).map(|row| {
+                    Resource::Str(row.iter().map(|tile| tile.to_string()).collect::<Vec<_>>().join(" "))
+                }).collect())
+            },
+            LayerEncoding::Rle => {
+                Resource::Vec(tiles.chunks(width.max(1) as usize).map(|row| {
+                    let mut runs = Vec::new();
+                    let mut i = 0;
+                    while i < row.len() {
+                        let mut run = 1;
+                        while i + run < row.len() && row[i + run] == row[i] {
+                            run += 1;
+                        }
+                        if run == 1 {
+                            runs.push(row[i].to_string());
+                        } else {
+                            runs.push(format!("{}*{}", run, row[i]));
+                        }
+                        i += run;
+                    }
+                    Resource::Str(runs.join(" "))
+                }).collect())
+            },
+        }
+    }
+
+    pub fn decode(&self, data: &Node, width: u32, height: u32) -> Result<Vec<u32>, LoadError> {
+        let size = grid::tile_count(width, height).map_err(|e| data.error(&e))?;
+        let mut tiles = Vec::with_capacity(size);
+
+        match self {
+            LayerEncoding::List => {
+                for tile in data.as_vec()? {
+                    tiles.push(tile.as_u32()?);
+                }
+            },
+            LayerEncoding::Rows | LayerEncoding::Rle => {
+                let rows = data.as_vec()?;
+                if rows.len() != height as usize {
+                    return Err(data.error(&format!("expected {} rows but found {}", height, rows.len())));
+                }
+
+                for row in rows {
+                    let start = tiles.len();
+
+                    for token in row.as_str()?.split_whitespace() {
+                        let (count, tile) = match token.find('*') {
+                            Some(star) if *self == LayerEncoding::Rle => (&token[..star], &token[star + 1..]),
+                            _ => ("1", token),
+                        };
+
+                        let count: usize = count.parse().map_err(|_| row.error(&format!("invalid run length in \"{}\"", token)))?;
+                        let tile: u32 = tile.parse().map_err(|_| row.error(&format!("invalid tile index in \"{}\"", token)))?;
+
+                        // Runs are checked before they are expanded so a huge count can't eat all the memory
+                        let left = width as usize - (tiles.len() - start);
+                        if count > left {
+                            return Err(row.error(&format!("run \"{}\" doesn't fit in the {} tiles left of the row", token, left)));
+                        }
+                        tiles.extend(std::iter::repeat(tile).take(count));
+                    }
+
+                    if tiles.len() - start != width as usize {
+                        return Err(row.error(&format!("expected {} tiles in row but found {}", width, tiles.len() - start)));
+                    }
+                }
+            },
+        }
+
+        Ok(tiles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TILES: [u32; 12] = [0, 0, 0, 1, 2, 2, 3, 3, 3, 3, 0, 1];
+
+    #[test]
+    fn every_encoding_round_trips() {
+        for encoding in &[LayerEncoding::List, LayerEncoding::Rows, LayerEncoding::Rle] {
+            let encoded = encoding.encode(&TILES, 4);
+            let decoded = encoding.decode(&encoded.node("test"), 4, 3).unwrap();
+            assert_eq!(decoded, TILES.to_vec(), "{}", encoding.name());
+        }
+    }
+
+    #[test]
+    fn rle_writes_runs() {
+        let encoded = LayerEncoding::Rle.encode(&TILES, 4);
+        let rows: Vec<&str> = encoded.as_vec().unwrap().iter().map(|row| row.as_str().unwrap()).collect();
+        assert_eq!(rows, vec!["3*0 1", "2*2 2*3", "2*3 0 1"]);
+    }
+
+    #[test]
+    fn rows_must_match_the_width() {
+        let res = Resource::from_string("[\"1 2\", \"1 2 3\"]").unwrap();
+        assert!(LayerEncoding::Rows.decode(&res.node("test"), 2, 2).is_err());
+
+        let res = Resource::from_string("[\"1 2\"]").unwrap();
+        assert!(LayerEncoding::Rows.decode(&res.node("test"), 2, 2).is_err());
+    }
+
+    #[test]
+    fn oversized_runs_are_rejected() {
+        let res = Resource::from_string("[\"3*1\"]").unwrap();
+        assert!(LayerEncoding::Rle.decode(&res.node("test"), 2, 1).is_err());
+
+        let res = Resource::from_string("[\"999999999999*3\"]").unwrap();
+        assert!(LayerEncoding::Rle.decode(&res.node("test"), 2, 1).is_err());
+
+        let res = Resource::from_string("[\"1*1 x*2\"]").unwrap();
+        assert!(LayerEncoding::Rle.decode(&res.node("test"), 2, 1).is_err());
+    }
+
+    #[test]
+    fn huge_sizes_are_rejected() {
+        let res = Resource::from_string("[]").unwrap();
+        assert!(LayerEncoding::List.decode(&res.node("test"), u32::MAX, u32::MAX).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::fmt;
+
+#[derive(Clone, Debug)]
+pub enum LoadErrorKind {
+    Io(String),
+    Parse(String),
+    Missing,
+    WrongType { expected: &'static str, found: &'static str },
+    Invalid(String),
+}
+
+// file is the file being loaded and path the location inside it, e.g. layers[0].data[17]
+#[derive(Clone, Debug)]
+pub struct LoadError {
+    pub file: String,
+    pub path: String,
+    pub kind: LoadErrorKind,
+}
+
+impl LoadError {
+    pub fn new(file: &str, path: &str, kind: LoadErrorKind) -> LoadError {
+        LoadError {
+            file: file.to_string(),
+            path: path.to_string(),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.path.is_empty() {
+            write!(f, "{}: {}: ", self.file, self.path)?;
+        } else if !self.file.is_empty() {
+            write!(f, "{}: ", self.file)?;
+        }
+
+        match &self.kind {
+            LoadErrorKind::Io(e) => write!(f, "unable to read file ({})", e),
+            LoadErrorKind::Parse(e) => write!(f, "unable to parse file ({})", e),
+            LoadErrorKind::Missing => write!(f, "missing value"),
+            LoadErrorKind::WrongType { expected, found } => write!(f, "expected {} but found {}", expected, found),
+            LoadErrorKind::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
@@ -0,0 +1,180 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::collections::HashMap;
+
+use crate::io::resource::Resource;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// 64 bit FNV-1a over a canonical byte form of whatever is fed to it. Numbers are little endian, strings and lists
+// are prefixed with their length and maps are fed in key order, so the same content hashes the same on every
+// machine and every run. Good for spotting mismatches, not for keeping anyone from forging a collision.
+// Yaml writes 1.0 as 1 and reads it back as an integer, so whole floats are fed as integers too.
+pub struct ContentHasher {
+    hash: u64,
+}
+
+impl ContentHasher {
+    pub fn new() -> ContentHasher {
+        ContentHasher {
+            hash: FNV_OFFSET,
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.write_bytes(&[value]);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_bytes(&value.to_bits().to_le_bytes());
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_u64(s.len() as u64);
+        self.write_bytes(s.as_bytes());
+    }
+
+    pub fn write_resource(&mut self, res: &Resource) {
+        match res {
+            Resource::None => self.write_u8(0),
+            Resource::Bool(b) => {
+                self.write_u8(1);
+                self.write_bool(*b);
+            },
+            Resource::I64(num) => {
+                self.write_u8(2);
+                self.write_bytes(&num.to_le_bytes());
+            },
+            Resource::F64(num) if num.fract() == 0.0 && num.abs() < i64::MAX as f64 => {
+                self.write_resource(&Resource::I64(*num as i64));
+            },
+            Resource::F64(num) => {
+                self.write_u8(3);
+                self.write_bytes(&num.to_bits().to_le_bytes());
+            },
+            Resource::Str(s) => {
+                self.write_u8(4);
+                self.write_str(s);
+            },
+            Resource::Vec(v) => {
+                self.write_u8(5);
+                self.write_u64(v.len() as u64);
+                for child in v {
+                    self.write_resource(child);
+                }
+            },
+            Resource::Map(map) => {
+                self.write_u8(6);
+                self.write_map(map);
+            },
+        }
+    }
+
+    pub fn write_map(&mut self, map: &HashMap<String, Resource>) {
+        let mut keys: Vec<&String> = map.keys().collect();
+        keys.sort();
+        self.write_u64(keys.len() as u64);
+        for key in keys {
+            self.write_str(key);
+            self.write_resource(&map[key]);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_of(res: &Resource) -> u64 {
+        let mut hasher = ContentHasher::new();
+        hasher.write_resource(res);
+        hasher.finish()
+    }
+
+    #[test]
+    fn matches_fnv_1a() {
+        let mut hasher = ContentHasher::new();
+        assert_eq!(hasher.finish(), 0xcbf2_9ce4_8422_2325);
+        hasher.write_bytes(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+        hasher.write_bytes(b"bc");
+        assert_eq!(hasher.finish(), 0xe71f_a219_0541_574b);
+    }
+
+    #[test]
+    fn whole_floats_hash_like_integers() {
+        assert_eq!(hash_of(&Resource::F64(1.0)), hash_of(&Resource::I64(1)));
+        assert_eq!(hash_of(&Resource::F64(-3.0)), hash_of(&Resource::I64(-3)));
+        assert_ne!(hash_of(&Resource::F64(1.5)), hash_of(&Resource::I64(1)));
+    }
+
+    #[test]
+    fn maps_hash_in_key_order() {
+        let a = Resource::from_string("{a: 1, b: [x, y], c: {d: true}}").unwrap();
+        let b = Resource::from_string("{c: {d: true}, b: [x, y], a: 1}").unwrap();
+        assert_eq!(hash_of(&a), hash_of(&b));
+
+        let c = Resource::from_string("{a: 1, b: [y, x], c: {d: true}}").unwrap();
+        assert_ne!(hash_of(&a), hash_of(&c));
+    }
+
+    #[test]
+    fn strings_are_length_prefixed() {
+        let mut a = ContentHasher::new();
+        a.write_str("ab");
+        a.write_str("c");
+        let mut b = ContentHasher::new();
+        b.write_str("a");
+        b.write_str("bc");
+        assert_ne!(a.finish(), b.finish());
+    }
+}
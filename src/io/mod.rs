@@ -21,4 +21,8 @@
  *   SOFTWARE.
  */
 
+pub mod bytes;
+pub mod encoding;
+pub mod error;
+pub mod hash;
 pub mod resource;
\ No newline at end of file
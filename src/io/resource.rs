@@ -25,12 +25,16 @@ use std::fs::File;
 use std::collections::HashMap;
 use std::vec::Vec;
 
+use crate::io::error::{LoadError, LoadErrorKind};
+
 lazy_static!(static ref MAP_HOLDER: HashMap<String, Resource> = HashMap::new(););
 lazy_static!(static ref VEC_HOLDER: Vec<Resource> = Vec::new(););
 lazy_static!(static ref RESOURCE_HOLDER: Resource = Resource::None;);
 
+#[derive(Clone)]
 pub enum Resource {
     None,
+    Bool(bool),
     I64(i64),
     F64(f64),
     Str(String),
@@ -39,21 +43,34 @@ pub enum Resource {
 }
 
 impl Resource {
-    pub fn from_file(file_name: &str) -> Resource {
-        let mut file = File::open(file_name).expect(&format!("Unable to open {}", file_name));
+    pub fn from_file(file_name: &str) -> Result<Resource, LoadError> {
+        let mut file = File::open(file_name).map_err(|e| LoadError::new(file_name, "", LoadErrorKind::Io(e.to_string())))?;
         let mut contents = String::new();
-        file.read_to_string(&mut contents).expect(&format!("Unable to load {}", file_name));
-        let yaml_vec = yaml_rust::YamlLoader::load_from_str(&contents).unwrap();
-        let yaml = yaml_vec[0].clone();
-        
-        Resource::rec(yaml)
+        file.read_to_string(&mut contents).map_err(|e| LoadError::new(file_name, "", LoadErrorKind::Io(e.to_string())))?;
+
+        Resource::parse(file_name, &contents)
+    }
+
+    pub fn from_string(data: &str) -> Result<Resource, LoadError> {
+        Resource::parse("<string>", data)
     }
 
-    pub fn from_string(data: &str) -> Resource {
-        let yaml_vec = yaml_rust::YamlLoader::load_from_str(data).unwrap();
-        let yaml = yaml_vec[0].clone();
+    fn parse(file_name: &str, data: &str) -> Result<Resource, LoadError> {
+        let yaml_vec = yaml_rust::YamlLoader::load_from_str(data).map_err(|e| LoadError::new(file_name, "", LoadErrorKind::Parse(e.to_string())))?;
+        let yaml = match yaml_vec.into_iter().next() {
+            Some(yaml) => yaml,
+            None => return Err(LoadError::new(file_name, "", LoadErrorKind::Parse("empty document".to_string()))),
+        };
         
-        Resource::rec(yaml)
+        Ok(Resource::rec(yaml))
+    }
+
+    pub fn node<'a>(&'a self, file_name: &'a str) -> Node<'a> {
+        Node {
+            res: self,
+            file: file_name,
+            path: String::new(),
+        }
     }
 
     pub fn to_file(&self, file_name: &str) {
@@ -75,7 +92,9 @@ impl Resource {
     }
 
     fn rec(yaml: yaml_rust::Yaml) -> Resource {
-        if yaml.as_i64().is_some() {
+        if yaml.as_bool().is_some() {
+            return Resource::Bool(yaml.as_bool().unwrap());
+        } else if yaml.as_i64().is_some() {
             return Resource::I64(yaml.as_i64().unwrap());
         } else if yaml.as_f64().is_some() {
             return Resource::F64(yaml.as_f64().unwrap());
@@ -97,7 +116,11 @@ impl Resource {
             let mut children = HashMap::new();
 
             for (k,v) in v {
-                children.insert(k.as_str().unwrap().to_string(), Resource::rec(v.clone()));
+                let key = match k.as_i64() {
+                    Some(num) => num.to_string(),
+                    None => k.as_str().unwrap_or_default().to_string(),
+                };
+                children.insert(key, Resource::rec(v.clone()));
             }
 
             return Resource::Map(children);
@@ -110,6 +133,9 @@ impl Resource {
             Resource::None => {
                 return yaml_rust::Yaml::Null
             },
+            Resource::Bool(b) => {
+                return yaml_rust::Yaml::Boolean(*b)
+            },
             Resource::F64(num) => {
                 return yaml_rust::Yaml::Real(num.to_string())
             },
@@ -152,6 +178,17 @@ impl Resource {
         }
     }
 
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Resource::Bool(b) => {
+                Some(*b)
+            },
+            _ => {
+                None
+            }
+        }
+    }
+
     pub fn as_i64(&self) -> Option<i64> {
         match self {
             Resource::I64(num) => {
@@ -223,11 +260,14 @@ impl Resource {
         }
     }
 
-    fn get_type_error(&self) -> &str {
+    fn get_type_error(&self) -> &'static str {
         match self {
             Resource::None => {
                 "None"
             },
+            Resource::Bool(_) => {
+                "Bool"
+            },
             Resource::F64(_) => {
                 "F64"
             },
@@ -245,4 +285,165 @@ impl Resource {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// A resource together with where it came from, so lookups can fail with the file and yaml path instead of panicking
+#[derive(Clone)]
+pub struct Node<'a> {
+    res: &'a Resource,
+    file: &'a str,
+    path: String,
+}
+
+impl<'a> Node<'a> {
+    pub fn resource(&self) -> &'a Resource {
+        self.res
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn error(&self, message: &str) -> LoadError {
+        LoadError::new(self.file, &self.path, LoadErrorKind::Invalid(message.to_string()))
+    }
+
+    fn type_error(&self, expected: &'static str) -> LoadError {
+        LoadError::new(self.file, &self.path, LoadErrorKind::WrongType { expected, found: self.res.get_type_error() })
+    }
+
+    fn child(&self, res: &'a Resource, path: String) -> Node<'a> {
+        Node {
+            res,
+            file: self.file,
+            path,
+        }
+    }
+
+    fn key_path(&self, name: &str) -> String {
+        if self.path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.path, name)
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Node<'a>, LoadError> {
+        let map = self.res.as_map().ok_or_else(|| self.type_error("Map"))?;
+
+        match map.get(name) {
+            Some(res) => Ok(self.child(res, self.key_path(name))),
+            None => Err(LoadError::new(self.file, &self.key_path(name), LoadErrorKind::Missing)),
+        }
+    }
+
+    pub fn get_optional(&self, name: &str) -> Option<Node<'a>> {
+        match self.res.get(name) {
+            Some(Resource::None) | None => None,
+            Some(res) => Some(self.child(res, self.key_path(name))),
+        }
+    }
+
+    pub fn get_index(&self, index: usize) -> Result<Node<'a>, LoadError> {
+        let vec = self.res.as_vec().ok_or_else(|| self.type_error("Vec"))?;
+
+        match vec.get(index) {
+            Some(res) => Ok(self.child(res, format!("{}[{}]", self.path, index))),
+            None => Err(LoadError::new(self.file, &format!("{}[{}]", self.path, index), LoadErrorKind::Missing)),
+        }
+    }
+
+    pub fn as_vec(&self) -> Result<Vec<Node<'a>>, LoadError> {
+        let vec = self.res.as_vec().ok_or_else(|| self.type_error("Vec"))?;
+
+        Ok(vec.iter().enumerate().map(|(i, res)| self.child(res, format!("{}[{}]", self.path, i))).collect())
+    }
+
+    pub fn as_map(&self) -> Result<Vec<(&'a str, Node<'a>)>, LoadError> {
+        let map = self.res.as_map().ok_or_else(|| self.type_error("Map"))?;
+
+        let mut children: Vec<(&'a str, Node<'a>)> = map.iter().map(|(k, res)| (k.as_str(), self.child(res, self.key_path(k)))).collect();
+        children.sort_by(|a, b| a.0.cmp(b.0));
+        Ok(children)
+    }
+
+    pub fn as_str(&self) -> Result<&'a str, LoadError> {
+        self.res.as_str().ok_or_else(|| self.type_error("String"))
+    }
+
+    pub fn as_bool(&self) -> Result<bool, LoadError> {
+        self.res.as_bool().ok_or_else(|| self.type_error("Bool"))
+    }
+
+    pub fn as_i64(&self) -> Result<i64, LoadError> {
+        self.res.as_i64().ok_or_else(|| self.type_error("I64"))
+    }
+
+    // Hand written files often say 1 instead of 1.0 so integers are accepted as well
+    pub fn as_f64(&self) -> Result<f64, LoadError> {
+        match self.res {
+            Resource::F64(num) => Ok(*num),
+            Resource::I64(num) => Ok(*num as f64),
+            _ => Err(self.type_error("F64")),
+        }
+    }
+
+    pub fn as_u32(&self) -> Result<u32, LoadError> {
+        let num = self.as_i64()?;
+        if num < 0 || num > u32::MAX as i64 {
+            return Err(self.error(&format!("{} is out of range", num)));
+        }
+        Ok(num as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = "
+layers:
+  - width: 2
+    data: [0, 1, -3, x]
+name: Test
+";
+
+    #[test]
+    fn errors_point_at_the_value() {
+        let res = Resource::from_string(DATA).unwrap();
+        let root = res.node("test.yaml");
+        let data = root.get("layers").unwrap().get_index(0).unwrap().get("data").unwrap();
+
+        let error = data.get_index(3).unwrap().as_i64().unwrap_err();
+        assert_eq!(error.path, "layers[0].data[3]");
+        assert_eq!(error.to_string(), "test.yaml: layers[0].data[3]: expected I64 but found String");
+
+        let error = data.get_index(2).unwrap().as_u32().unwrap_err();
+        assert_eq!(error.to_string(), "test.yaml: layers[0].data[2]: -3 is out of range");
+
+        let error = root.get("layers").unwrap().get_index(0).unwrap().get("height").err().unwrap();
+        assert_eq!(error.to_string(), "test.yaml: layers[0].height: missing value");
+        assert!(data.get_index(4).is_err());
+        assert!(root.get("name").unwrap().as_vec().is_err());
+        assert!(root.get_optional("author").is_none());
+    }
+
+    #[test]
+    fn whole_numbers_are_read_as_floats() {
+        let res = Resource::from_string("{scale: 2, ratio: 0.5, name: x}").unwrap();
+        let root = res.node("test");
+        assert_eq!(root.get("scale").unwrap().as_f64().unwrap(), 2.0);
+        assert_eq!(root.get("ratio").unwrap().as_f64().unwrap(), 0.5);
+        assert!(root.get("name").unwrap().as_f64().is_err());
+    }
+
+    #[test]
+    fn broken_files_are_errors() {
+        assert!(matches!(Resource::from_string("a: [1, 2").err().unwrap().kind, LoadErrorKind::Parse(_)));
+        assert!(matches!(Resource::from_string("").err().unwrap().kind, LoadErrorKind::Parse(_)));
+
+        let error = Resource::from_file("res/does_not_exist.yaml").err().unwrap();
+        assert!(matches!(error.kind, LoadErrorKind::Io(_)));
+        assert!(error.to_string().starts_with("res/does_not_exist.yaml: unable to read file"));
+    }
+}
@@ -30,15 +30,34 @@ extern crate lazy_static;
 
 mod net;
 mod map;
+mod map_info;
+mod map_object;
+mod autotile;
 mod scenes;
 mod io;
 mod camera;
+mod grid;
+mod terrain;
+mod pathfinding;
 mod entities;
+mod generation;
+mod tiled;
+mod image;
+mod preview;
+mod elevation;
+mod analysis;
+#[cfg(test)]
+mod testing;
 
-use engine::{core::{input::{Input, Key, Mouse}, window::{Action, Window}}, game::{Game, GameContainer, GameData}, renderer::{color::BLACK, graphics::Graphics, renderer::{init_gl, std_renderer::{BlendMode, Capability, blend_func, enable}}}, scene::{SceneManager}};
+use engine::{core::{error_log, info_log, input::{Input, Key, Mouse}, window::{Action, Window}}, game::{Game, GameContainer, GameData}, renderer::{color::BLACK, graphics::Graphics, renderer::{init_gl, std_renderer::{BlendMode, Capability, blend_func, enable}}}, scene::{SceneManager}};
 
 use scenes::{editor::*, game::GameScene, main_menu::MainMenuScene};
 use net::{client::{Client, ClientHandler}, packet::*, server::{Server, ServerManager}};
+use io::error::{LoadError, LoadErrorKind};
+use map::{Map, TileSet};
+use analysis::Analysis;
+use generation::symmetry;
+use pathfinding::PathOptions;
 
 
 type KeyEventDispatcher                 = bus::BusReader::<(Key, Action)>;
@@ -80,7 +99,10 @@ impl Game for TurnBasedStrategy {
         self.timer += gd.delta_time();
         if self.timer > 1.0 {
             self.timer -= 1.0;
-            self.win.set_title(&gd.frame_rate().to_string());
+            match self.client.map_status() {
+                Some(status) => self.win.set_title(&format!("{} - {}", gd.frame_rate(), status)),
+                None => self.win.set_title(&gd.frame_rate().to_string()),
+            }
         }
         self.win.poll_events();
         self.win.swap_buffers();
@@ -93,7 +115,86 @@ impl Game for TurnBasedStrategy {
     }
 }
 
+// Commands that work on map files without opening a window:
+//   import <tiled map.tmx|.json> <data file> <map file>
+//   export <data file> <map file> <tiled map.tmx|.json>
+//   render <data file> <map file> <image.png> [scale]
+//   thumbnail <data file> <map file> <image.png>
+//   check <data file> <map file> [movement class]
+//   convert <map file> <map file>, .tbm files are binary and everything else yaml
+fn run_command(args: &[String]) -> Result<(), LoadError> {
+    let usage = |usage: &str| LoadError::new("", "", LoadErrorKind::Invalid(format!("usage: {}", usage)));
+
+    match args[0].as_str() {
+        "import" => {
+            if args.len() != 4 {
+                return Err(usage("import <tiled map.tmx|.json> <data file> <map file>"));
+            }
+            tiled::import(&args[1], &args[2], &args[3])?;
+        },
+        "export" | "render" | "thumbnail" => {
+            if args.len() < 4 {
+                return Err(usage(&format!("{} <data file> <map file> <output>", args[0])));
+            }
+            let map = Map::from_file(&args[2], TileSet::from_file_headless(&args[1])?)?;
+
+            match args[0].as_str() {
+                "export" => tiled::export(&map, &args[3])?,
+                "render" => {
+                    let scale = match args.get(4) {
+                        Some(scale) => scale.parse().map_err(|_| usage("render <data file> <map file> <image.png> [scale]"))?,
+                        None => 1.0,
+                    };
+                    preview::render(&map, scale)?.to_png(&args[3])?;
+                },
+                _ => preview::thumbnail(&map)?.to_png(&args[3])?,
+            }
+        },
+        // Fails when some starts can't reach each other, so it can gate map changes
+        "check" => {
+            if args.len() < 3 || args.len() > 4 {
+                return Err(usage("check <data file> <map file> [movement class]"));
+            }
+            let map = Map::from_file(&args[2], TileSet::from_file_headless(&args[1])?)?;
+            let movement_class = args.get(3).map(|class| class.as_str()).unwrap_or("default");
+
+            let analysis = Analysis::new(&map, movement_class, &PathOptions::new());
+            info_log!("{}: {}", args[2], analysis);
+            let (symmetry, score) = symmetry::detect(&map);
+            info_log!("closest symmetry is {} at {}", symmetry, score);
+            info_log!("content hash {:016x}", map.content_hash());
+            if !analysis.is_connected() {
+                return Err(LoadError::new(&args[2], "", LoadErrorKind::Invalid("not every start can reach every other start".to_string())));
+            }
+            return Ok(());
+        },
+        // Maps list their data files, so no data file is needed
+        "convert" => {
+            if args.len() != 3 {
+                return Err(usage("convert <map file> <map file>"));
+            }
+            let map = Map::from_file(&args[1], TileSet::without_images())?;
+            map.to_file(&args[2]).map_err(|e| LoadError::new(&args[2], "", LoadErrorKind::Invalid(e)))?;
+            info_log!("Wrote {}", args[2]);
+            return Ok(());
+        },
+        other => return Err(usage(&format!("unknown command \"{}\", expected import, export, render, thumbnail, check or convert", other))),
+    }
+
+    info_log!("Wrote {}", args[3]);
+    Ok(())
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = run_command(&args) {
+            error_log!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut win = Window::new(600, 400, "title: &str").unwrap();
     win.make_current();
 
@@ -131,3 +232,31 @@ fn main() {
 
     GameContainer::new().run(game);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{DATA, TempDir};
+
+    #[test]
+    fn convert_round_trips_through_binary() {
+        let dir = TempDir::new("convert");
+        let path = |name: &str| dir.file(name);
+
+        std::fs::write(path("data.yaml"), DATA).unwrap();
+        let mut map = Map::with_tile_set(testing::data_tile_set(DATA, &path("data.yaml")).unwrap());
+        let mut layer = map::Layer::new(3, 2);
+        layer.set(1, 0, map.tile_set().find("water").unwrap());
+        map.layers.push(layer);
+        map.to_file(&path("map.yaml")).unwrap();
+
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        run_command(&args(&["convert", &path("map.yaml"), &path("map.tbm")])).unwrap();
+        run_command(&args(&["convert", &path("map.tbm"), &path("back.yaml")])).unwrap();
+
+        assert!(std::fs::read(path("map.tbm")).unwrap().starts_with(b"TBSM"));
+        let back = Map::from_file(&path("back.yaml"), TileSet::without_images()).unwrap();
+        assert_eq!(back.content_hash(), map.content_hash());
+        assert!(run_command(&args(&["convert", &path("map.yaml")])).is_err());
+    }
+}
@@ -21,10 +21,10 @@
  *   SOFTWARE.
  */
 
-use std::collections::HashMap;
-use engine::{core::info_log, renderer::{color::{self, Color, WHITE}, graphics::Graphics, texture::{Texture, TextureRegion}}};
+use std::{collections::{HashMap, HashSet}, convert::TryFrom, rc::Rc};
+use engine::{core::{error_log, info_log}, renderer::{color::{self, Color, WHITE}, graphics::Graphics, texture::{Texture, TextureRegion}}};
 
-use crate::io::resource::Resource;
+use crate::{autotile::Autotile, generation::cellular::CellularConfig, grid::{self, Coord, Edge, HexCoordinates, HexOrientation, Topology, Wrap}, image::Image, io::{bytes::{ByteReader, ByteWriter}, encoding::LayerEncoding, error::{LoadError, LoadErrorKind}, hash::ContentHasher, resource::{Node, Resource}}, map_info::MapInfo, map_object::{MapObject, ObjectKind, ObjectLayer}, elevation::Heights, pathfinding::{self, Path, PathOptions, Reachable}, terrain::Terrain};
 
 #[derive(Clone, Copy, PartialEq)]
 pub struct TileIndex(usize);
@@ -33,60 +33,138 @@ impl TileIndex {
     pub unsafe fn new(index: usize) -> TileIndex {
         TileIndex(index)
     }
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+// A named region of an atlas as declared under textures in a data file. Bounds are in pixels from the
+// top left of the atlas image.
+#[derive(Clone)]
+pub struct AtlasTexture {
+    pub region: TextureRegion,
+    pub atlas: usize,
+    pub bounds: (u32, u32, u32, u32),
 }
 
 #[derive(Clone)]
 pub struct Tile {
+    pub name: String,
     pub color: Color,
     pub texture: TextureRegion,
     pub x_off: f32,
     pub y_off: f32,
     pub x_scl: f32,
     pub y_scl: f32,
+    pub terrain: Terrain,
+    pub autotile: Option<Autotile>,
+    pub frames: Vec<(TextureRegion, f32)>,
+    pub atlas: usize,
+    pub bounds: (u32, u32, u32, u32),
 }
 
 impl Tile {
     pub fn new(color: Color, texture: TextureRegion) -> Tile {
         Tile {
+            name: String::new(),
             color,
             texture,
             x_off: 0.0,
             y_off: 0.0,
             x_scl: 0.0,
             y_scl: 0.0,
+            terrain: Terrain::new(),
+            autotile: None,
+            frames: Vec::new(),
+            atlas: 0,
+            bounds: (0, 0, 0, 0),
         }
     }
 
     pub fn new_invalid() -> Tile {
         Tile {
+            name: String::new(),
             color: WHITE,
             texture: TextureRegion::new_invalid(),
             x_off: 0.0,
             y_off: 0.0,
             x_scl: 0.0,
             y_scl: 0.0,
+            terrain: Terrain::new(),
+            autotile: None,
+            frames: Vec::new(),
+            atlas: 0,
+            bounds: (0, 0, 0, 0),
         }
     }
 
     pub fn from_color(color: Color, id: TileIndex) -> Tile {
         Tile {
+            name: String::new(),
             color: color,
             texture: TextureRegion::new_invalid(),
             x_off: 0.0,
             y_off: 0.0,
             x_scl: 0.0,
             y_scl: 0.0,
+            terrain: Terrain::new(),
+            autotile: None,
+            frames: Vec::new(),
+            atlas: 0,
+            bounds: (0, 0, 0, 0),
+        }
+    }
+
+    // Animated tiles loop through their frames, each shown for its duration in seconds
+    pub fn texture_at(&self, time: f32) -> &TextureRegion {
+        let length: f32 = self.frames.iter().map(|(_, duration)| duration).sum();
+        if length <= 0.0 {
+            return &self.texture;
         }
+
+        let mut time = time.rem_euclid(length);
+        for (texture, duration) in &self.frames {
+            if time < *duration {
+                return texture;
+            }
+            time -= duration;
+        }
+
+        &self.frames[self.frames.len() - 1].0
     }
 }
 
+// A data file merged into a tile set, its tiles take up the indices first..first + len like Tiled's firstgid
+#[derive(Clone)]
+pub struct TileSource {
+    pub path: String,
+    pub first: usize,
+    pub len: usize,
+}
+
+// How much of the atlases a tile set opened, data files pulled in later by a map are loaded the same way
+#[derive(Clone, Copy, PartialEq)]
+enum AtlasLoading {
+    Textures,
+    Sizes,
+    Nothing,
+}
+
+#[derive(Clone)]
 pub struct TileSet {
     tiles: Vec<Tile>,
+    object_kinds: Vec<ObjectKind>,
+    atlases: Vec<Rc<Texture>>,
+    atlas_paths: Vec<String>,
+    atlas_sizes: Vec<(u32, u32)>,
+    sources: Vec<TileSource>,
+    loading: AtlasLoading,
 }
 
 impl TileSet {
     pub fn new() -> TileSet {
-        TileSet { tiles: vec![ Tile::new_invalid() ], }
+        TileSet { tiles: vec![ Tile::new_invalid() ], object_kinds: Vec::new(), atlases: Vec::new(), atlas_paths: Vec::new(), atlas_sizes: Vec::new(), sources: Vec::new(), loading: AtlasLoading::Textures, }
     }
 
     pub fn add_tile(&mut self, tile: Tile) -> TileIndex {
@@ -94,6 +172,314 @@ impl TileSet {
         TileIndex(self.tiles.len()-1)
     }
 
+    fn atlas_paths_from_resource(data: &Node, must_exist: bool) -> Result<Vec<String>, LoadError> {
+        let mut atlas_paths = Vec::new();
+        for atlas_d in data.get("atlas")?.as_vec()? {
+            let atlas_path = atlas_d.get("path")?;
+            if must_exist && !std::path::Path::new(atlas_path.as_str()?).exists() {
+                return Err(atlas_path.error(&format!("atlas \"{}\" does not exist", atlas_path.as_str()?)));
+            }
+            atlas_paths.push(atlas_path.as_str()?.to_string());
+        }
+        Ok(atlas_paths)
+    }
+
+    // Loads the atlases listed in the data file together with its tiles
+    pub fn from_file(path: &str) -> Result<TileSet, LoadError> {
+        let data = Resource::from_file(path)?;
+        let data = data.node(path);
+
+        let atlas_paths = TileSet::atlas_paths_from_resource(&data, true)?;
+        let atlases: Vec<Rc<Texture>> = atlas_paths.iter().map(|atlas_path| Texture::from_file(atlas_path)).collect();
+
+        let mut tiles = TileSet::from_resource(&data, &atlases)?;
+        tiles.atlas_paths = atlas_paths;
+        tiles.sources.push(TileSource { path: path.to_string(), first: 1, len: tiles.len() - 1 });
+
+        Ok(tiles)
+    }
+
+    // Loads a data file without an OpenGL context, for tools that don't open a window. Textures are left
+    // invalid, tiles still know the atlas and bounds they come from.
+    pub fn from_file_headless(path: &str) -> Result<TileSet, LoadError> {
+        let data = Resource::from_file(path)?;
+        TileSet::from_resource_headless(&data.node(path), path)
+    }
+
+    // Like from_file_headless for data that didn't come from a file, path is what maps listing it under tilesets
+    // call it
+    pub fn from_resource_headless(data: &Node, path: &str) -> Result<TileSet, LoadError> {
+        let atlas_paths = TileSet::atlas_paths_from_resource(data, true)?;
+        let mut sizes = Vec::new();
+        for atlas_path in &atlas_paths {
+            sizes.push(Image::png_size(atlas_path)?);
+        }
+
+        let known: Vec<Option<(u32, u32)>> = sizes.iter().copied().map(Some).collect();
+        let mut tiles = TileSet::parse(data, &known, |_, _, _, _, _| TextureRegion::new_invalid())?;
+        tiles.atlas_paths = atlas_paths;
+        tiles.atlas_sizes = sizes;
+        tiles.loading = AtlasLoading::Sizes;
+        tiles.sources.push(TileSource { path: path.to_string(), first: 1, len: tiles.len() - 1 });
+
+        Ok(tiles)
+    }
+
+    // Loads a data file without opening any images, texture bounds aren't checked against their atlases. Enough for
+    // looking at names, terrain and objects, like when hashing a map, not for drawing anything.
+    // Empty, maps loaded with it bring the data files they list under tilesets without their images
+    pub fn without_images() -> TileSet {
+        let mut tiles = TileSet::new();
+        tiles.loading = AtlasLoading::Nothing;
+        tiles
+    }
+
+    pub fn from_file_without_images(path: &str) -> Result<TileSet, LoadError> {
+        let data = Resource::from_file(path)?;
+        TileSet::from_resource_without_images(&data.node(path), path)
+    }
+
+    pub fn from_resource_without_images(data: &Node, path: &str) -> Result<TileSet, LoadError> {
+        let atlas_paths = TileSet::atlas_paths_from_resource(data, false)?;
+        let sizes = vec![None; atlas_paths.len()];
+
+        let mut tiles = TileSet::parse(data, &sizes, |_, _, _, _, _| TextureRegion::new_invalid())?;
+        tiles.atlas_paths = atlas_paths;
+        tiles.loading = AtlasLoading::Nothing;
+        tiles.sources.push(TileSource { path: path.to_string(), first: 1, len: tiles.len() - 1 });
+
+        Ok(tiles)
+    }
+
+    // Textures pick their atlas with an index into atlases, 0 when left out
+    pub fn from_resource(data: &Node, atlases: &[Rc<Texture>]) -> Result<TileSet, LoadError> {
+        let sizes: Vec<Option<(u32, u32)>> = atlases.iter().map(|atlas| Some((atlas.width(), atlas.height()))).collect();
+        let mut tiles = TileSet::parse(data, &sizes, |atlas, x, y, w, h| TextureRegion::new(x, atlases[atlas].height() - y - h, w, h, &atlases[atlas]))?;
+        tiles.atlases = atlases.to_vec();
+        tiles.atlas_sizes = sizes.into_iter().flatten().collect();
+        Ok(tiles)
+    }
+
+    // Bounds are checked against the atlases whose size is known
+    fn parse<F>(data: &Node, atlas_sizes: &[Option<(u32, u32)>], region: F) -> Result<TileSet, LoadError>
+    where F: Fn(usize, u32, u32, u32, u32) -> TextureRegion {
+        let mut textures = HashMap::new();
+
+        for texture in data.get("textures")?.as_vec()? {
+            let texture_name = texture.get("name")?.as_str()?;
+            let bounds = texture.get("bounds")?;
+
+            let atlas_index = match texture.get_optional("atlas") {Some(x) => x.as_u32()? as usize, None => 0};
+            let atlas_size = match atlas_sizes.get(atlas_index) {
+                Some(size) => *size,
+                None => return Err(texture.error(&format!("atlas {} does not exist, there are {} atlases", atlas_index, atlas_sizes.len()))),
+            };
+            
+            let w = bounds.get_index(2)?.as_u32()?;
+            let h = bounds.get_index(3)?.as_u32()?;
+            let x = bounds.get_index(0)?.as_u32()?;
+            let y = bounds.get_index(1)?.as_u32()?;
+
+            if let Some((atlas_w, atlas_h)) = atlas_size {
+                if x as u64 + w as u64 > atlas_w as u64 || y as u64 + h as u64 > atlas_h as u64 {
+                    return Err(bounds.error(&format!("bounds reach outside of the {}x{} atlas", atlas_w, atlas_h)));
+                }
+            }
+
+            textures.insert(texture_name, AtlasTexture {
+                region: region(atlas_index, x, y, w, h),
+                atlas: atlas_index,
+                bounds: (x, y, w, h),
+            });
+        }
+
+        let mut tiles = TileSet::new();
+
+        for tile_d in data.get("tiles")?.as_vec()? {
+            let r = match tile_d.get_optional("r") {Some(x) => x.as_f64()? as f32, None => 1.0};
+            let g = match tile_d.get_optional("g") {Some(x) => x.as_f64()? as f32, None => 1.0};
+            let b = match tile_d.get_optional("b") {Some(x) => x.as_f64()? as f32, None => 1.0};
+            let texture_d = tile_d.get("texture")?;
+            let texture = match textures.get(texture_d.as_str()?) {
+                Some(texture) => texture,
+                None => return Err(texture_d.error(&format!("unknown texture \"{}\"", texture_d.as_str()?))),
+            };
+            let mut tile = Tile::new(Color::from((r, g, b, 1.0)), texture.region.clone());
+            tile.name = match tile_d.get_optional("name") {Some(x) => x.as_str()?.to_string(), None => texture_d.as_str()?.to_string()};
+            if tiles.find(&tile.name).is_some() {
+                return Err(tile_d.error(&format!("tile name \"{}\" is already used, tile names have to be unique", tile.name)));
+            }
+            tile.x_off = match tile_d.get_optional("x_off") {Some(x) => x.as_f64()? as f32, None => 0.0};
+            tile.y_off = match tile_d.get_optional("y_off") {Some(x) => x.as_f64()? as f32, None => 0.0};
+            tile.x_scl = match tile_d.get_optional("x_scl") {Some(x) => x.as_f64()? as f32, None => 1.0};
+            tile.y_scl = match tile_d.get_optional("y_scl") {Some(x) => x.as_f64()? as f32, None => 1.0};
+            tile.atlas = texture.atlas;
+            tile.bounds = texture.bounds;
+            tile.terrain = match tile_d.get_optional("terrain") {Some(x) => Terrain::from_resource(&x)?, None => Terrain::new()};
+            if let Some(frames) = tile_d.get_optional("frames") {
+                for frame_d in frames.as_vec()? {
+                    let frame_texture_d = frame_d.get("texture")?;
+                    let frame_texture = match textures.get(frame_texture_d.as_str()?) {
+                        Some(texture) => texture,
+                        None => return Err(frame_texture_d.error(&format!("unknown texture \"{}\"", frame_texture_d.as_str()?))),
+                    };
+                    let duration = frame_d.get("duration")?;
+                    if duration.as_f64()? <= 0.0 {
+                        return Err(duration.error("frame duration has to be positive"));
+                    }
+                    tile.frames.push((frame_texture.region.clone(), duration.as_f64()? as f32));
+                }
+            }
+            tiles.add_tile(tile);
+        }
+
+        if let Some(autotiles) = data.get_optional("autotiles") {
+            for autotile_d in autotiles.as_vec()? {
+                let name_d = autotile_d.get("tile")?;
+                let index = match tiles.find(name_d.as_str()?) {
+                    Some(index) => index,
+                    None => return Err(name_d.error(&format!("unknown tile \"{}\"", name_d.as_str()?))),
+                };
+                let autotile = Autotile::from_resource(&autotile_d, &textures, &tiles, index)?;
+                tiles.tile_mut(index).autotile = Some(autotile);
+            }
+        }
+
+        if let Some(object_kinds) = data.get_optional("objects") {
+            for kind_d in object_kinds.as_vec()? {
+                let texture_d = kind_d.get("texture")?;
+                let texture = match textures.get(texture_d.as_str()?) {
+                    Some(texture) => texture,
+                    None => return Err(texture_d.error(&format!("unknown texture \"{}\"", texture_d.as_str()?))),
+                };
+
+                let mut properties = HashMap::new();
+                if let Some(properties_d) = kind_d.get_optional("properties") {
+                    for (name, value) in properties_d.as_map()? {
+                        properties.insert(name.to_string(), value.resource().clone());
+                    }
+                }
+
+                tiles.object_kinds.push(ObjectKind {
+                    kind: kind_d.get("kind")?.as_str()?.to_string(),
+                    texture: texture.region.clone(),
+                    atlas: texture.atlas,
+                    bounds: texture.bounds,
+                    properties,
+                });
+            }
+        }
+
+        Ok(tiles)
+    }
+
+    // Adds the tiles and object kinds of another tile set after the current ones, returning the index of its first tile
+    pub fn append(&mut self, other: TileSet) -> Result<usize, String> {
+        for tile in other.tiles.iter().skip(1) {
+            if self.find(&tile.name).is_some() {
+                return Err(format!("tile name \"{}\" is used by more than one tile set", tile.name));
+            }
+        }
+
+        let first = self.tiles.len();
+
+        for source in other.sources {
+            self.sources.push(TileSource { path: source.path, first: source.first - 1 + first, len: source.len });
+        }
+
+        // Headless tile sets only know their atlases by path
+        let atlas_offset = self.atlases.len().max(self.atlas_paths.len());
+        self.tiles.extend(other.tiles.into_iter().skip(1));
+        for tile in &mut self.tiles[first..] {
+            tile.atlas += atlas_offset;
+            for variant in tile.autotile.iter_mut().flat_map(|autotile| autotile.variants.iter_mut()) {
+                variant.atlas += atlas_offset;
+            }
+        }
+        let kinds = self.object_kinds.len();
+        self.object_kinds.extend(other.object_kinds);
+        for kind in &mut self.object_kinds[kinds..] {
+            kind.atlas += atlas_offset;
+        }
+        self.atlases.extend(other.atlases);
+        self.atlas_paths.extend(other.atlas_paths);
+        self.atlas_sizes.extend(other.atlas_sizes);
+
+        Ok(first)
+    }
+
+    pub fn sources(&self) -> &Vec<TileSource> {
+        &self.sources
+    }
+
+    pub fn source_of(&self, index: TileIndex) -> Option<&TileSource> {
+        self.sources.iter().find(|source| index.0 >= source.first && index.0 < source.first + source.len)
+    }
+
+    pub fn has_source(&self, path: &str) -> bool {
+        self.sources.iter().any(|source| source.path == path)
+    }
+
+    pub fn atlases(&self) -> &Vec<Rc<Texture>> {
+        &self.atlases
+    }
+
+    // Paths of the atlases, empty when the tile set wasn't loaded from a file
+    pub fn atlas_paths(&self) -> &Vec<String> {
+        &self.atlas_paths
+    }
+
+    // Known unless the tile set was loaded without images
+    pub fn atlas_size(&self, atlas: usize) -> Option<(u32, u32)> {
+        self.atlas_sizes.get(atlas).copied()
+    }
+
+    pub fn add_source(&mut self, source: TileSource) {
+        self.sources.push(source);
+    }
+
+    pub fn find(&self, name: &str) -> Option<TileIndex> {
+        self.tiles.iter().skip(1).position(|tile| tile.name == name).map(|i| TileIndex(i + 1))
+    }
+
+    // Map files store tiles as positions in a list of tile names, where 0 is no tile and 1 the first name.
+    // This keeps maps valid when tiles are reordered or added to the data file.
+    pub fn names(&self) -> Vec<&str> {
+        self.tiles.iter().skip(1).map(|tile| tile.name.as_str()).collect()
+    }
+
+    // Maps a tile name list to tile indices, the error holds the first unknown name
+    pub fn palette(&self, names: &[&str]) -> Result<Vec<TileIndex>, String> {
+        let mut palette = vec![TileIndex(0)];
+        for name in names {
+            palette.push(self.find(name).ok_or_else(|| name.to_string())?);
+        }
+        Ok(palette)
+    }
+
+    // Maps from before tile name lists index the tile set directly
+    pub fn identity_palette(&self) -> Vec<TileIndex> {
+        (0..self.tiles.len()).map(TileIndex).collect()
+    }
+
+    pub fn palette_from_resource(&self, res: Option<Node>) -> Result<Vec<TileIndex>, LoadError> {
+        let res = match res {
+            Some(res) => res,
+            None => return Ok(self.identity_palette()),
+        };
+
+        let names = res.as_vec()?;
+        let mut palette = vec![TileIndex(0)];
+        for name_d in names {
+            let name = name_d.as_str()?;
+            match self.find(name) {
+                Some(index) => palette.push(index),
+                None => return Err(name_d.error(&format!("unknown tile \"{}\"", name))),
+            }
+        }
+        Ok(palette)
+    }
+
     pub fn tile(&self, index: TileIndex) -> &Tile {
         &self.tiles[index.0]
     }
@@ -102,6 +488,10 @@ impl TileSet {
         &mut self.tiles[index.0]
     }
 
+    pub fn terrain(&self, index: TileIndex) -> &Terrain {
+        &self.tiles[index.0].terrain
+    }
+
     pub fn len(&self) -> usize {
         self.tiles.len()
     }
@@ -109,6 +499,106 @@ impl TileSet {
     pub fn tiles(&self) -> &Vec<Tile> {
         &self.tiles
     }
+
+    pub fn object_kinds(&self) -> &Vec<ObjectKind> {
+        &self.object_kinds
+    }
+
+    pub fn object_kind(&self, kind: &str) -> Option<&ObjectKind> {
+        self.object_kinds.iter().find(|object_kind| object_kind.kind == kind)
+    }
+
+    // The place of every picked tile in name order, 0 stays no tile. Hashes go through this so that reordering the
+    // data file doesn't change them, like the tile names in map files.
+    fn name_ranks<T: Fn(TileIndex) -> bool>(&self, tile: T) -> Vec<u32> {
+        let mut order: Vec<usize> = (1..self.tiles.len()).filter(|&i| tile(TileIndex(i))).collect();
+        order.sort_by(|&a, &b| self.tiles[a].name.cmp(&self.tiles[b].name));
+
+        let mut ranks = vec![0; self.tiles.len()];
+        for (rank, &index) in order.iter().enumerate() {
+            ranks[index] = rank as u32 + 1;
+        }
+        ranks
+    }
+
+    // The tile definitions the game plays by: names, terrain and object kinds, in name order. Textures, colors and
+    // autotiles only change how things look and are left out.
+    pub fn hash_content(&self, hasher: &mut ContentHasher) {
+        self.hash_some(hasher, |_| true, |_| true);
+    }
+
+    // Like hash_content, but only for the tiles and object kinds picked
+    pub fn hash_some<T: Fn(TileIndex) -> bool, K: Fn(&str) -> bool>(&self, hasher: &mut ContentHasher, tile: T, kind: K) {
+        let mut tiles: Vec<&Tile> = self.tiles.iter().enumerate().skip(1).filter(|(i, _)| tile(TileIndex(*i))).map(|(_, tile)| tile).collect();
+        tiles.sort_by(|a, b| a.name.cmp(&b.name));
+        hasher.write_u64(tiles.len() as u64);
+        for tile in tiles {
+            hasher.write_str(&tile.name);
+            tile.terrain.hash_content(hasher);
+        }
+
+        let mut kinds: Vec<&ObjectKind> = self.object_kinds.iter().filter(|object_kind| kind(&object_kind.kind)).collect();
+        kinds.sort_by(|a, b| a.kind.cmp(&b.kind));
+        hasher.write_u64(kinds.len() as u64);
+        for kind in kinds {
+            kind.hash_content(hasher);
+        }
+    }
+
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = ContentHasher::new();
+        self.hash_content(&mut hasher);
+        hasher.finish()
+    }
+}
+
+// Which side of a layer stays in place when it is resized
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    // How far the old tiles move when going from the old size to the new one
+    pub fn offset(&self, old: (u32, u32), new: (u32, u32)) -> (i32, i32) {
+        let dw = new.0 as i32 - old.0 as i32;
+        let dh = new.1 as i32 - old.1 as i32;
+
+        let dx = match self {
+            Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => 0,
+            Anchor::Top | Anchor::Center | Anchor::Bottom => dw / 2,
+            Anchor::TopRight | Anchor::Right | Anchor::BottomRight => dw,
+        };
+        let dy = match self {
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => 0,
+            Anchor::Left | Anchor::Center | Anchor::Right => dh / 2,
+            Anchor::TopLeft | Anchor::Top | Anchor::TopRight => dh,
+        };
+
+        (dx, dy)
+    }
+}
+
+// Everything from the map besides its tiles that decides how a layer is drawn
+#[derive(Clone, Copy)]
+pub struct RenderContext<'a> {
+    pub tiles: &'a TileSet,
+    // Autotiles look at the neighbors on every layer
+    pub layers: &'a [Layer],
+    pub topology: Topology,
+    pub wrap: Wrap,
+    pub heights: Option<&'a Heights>,
+    pub time: f32,
+    // Added to every position drawn, the copies of a wrapping map are drawn side by side with it
+    pub offset: (f32, f32),
 }
 
 #[derive(Clone)]
@@ -116,220 +606,1618 @@ pub struct Layer {
     tiles: Vec<TileIndex>,
     pub width: u32,
     pub height: u32,
+    pub encoding: LayerEncoding,
 }
 
 impl Layer {
     pub fn new(width: u32, height: u32) -> Layer {
         let mut tiles = Vec::new();
-        tiles.resize((width*height) as usize, TileIndex(0));
+        tiles.resize(width as usize * height as usize, TileIndex(0));
         Layer {
             tiles,
             width,
             height,
+            encoding: LayerEncoding::List,
         }
     }
 
-    pub fn from_file(path: &str, tile_set: &TileSet) -> Layer {
-        let layer_data = Resource::from_file(path);
+    pub fn from_file(path: &str, tile_set: &TileSet) -> Result<Layer, LoadError> {
+        let layer_data = Resource::from_file(path)?;
+        let layer_data = layer_data.node(path);
+        let palette = tile_set.palette_from_resource(layer_data.get_optional("tiles"))?;
+
+        Layer::from_resource(&layer_data, &palette)
+    }
 
+    // Tiles in the data are positions in the palette, see TileSet::names
+    pub fn from_resource(layer_data: &Node, palette: &[TileIndex]) -> Result<Layer, LoadError> {
+        let width = layer_data.get("width")?.as_u32()?;
+        let height = layer_data.get("height")?.as_u32()?;
+        let size = grid::tile_count(width, height).map_err(|e| layer_data.error(&e))?;
+        let encoding = LayerEncoding::from_resource(layer_data.get_optional("encoding"))?;
+        let map_data = encoding.decode(&layer_data.get("data")?, width, height)?;
 
-        let width = layer_data.get("width").unwrap().as_i64().unwrap() as u32;
-        let height = layer_data.get("height").unwrap().as_i64().unwrap() as u32;
-        let index = layer_data.get("index").unwrap().as_i64().unwrap() as usize;
-        let map_data = layer_data.get("data").unwrap().as_vec().unwrap();
+        if map_data.len() != size {
+            return Err(layer_data.get("data")?.error(&format!("expected {} tiles for a {}x{} layer but found {}", size, width, height, map_data.len())));
+        }
         
         let mut layer = Layer::new(width, height);
+        layer.encoding = encoding;
         
         let mut tile_index = 0;
         for tile in map_data {
             let x = tile_index % width;
             let y = height - 1 - (tile_index - x) / width;
-            layer.set(x, y, TileIndex(tile.as_i64().unwrap() as usize % tile_set.len()));
+            match palette.get(tile as usize) {
+                Some(tile) => layer.set(x, y, *tile),
+                None => return Err(layer_data.get("data")?.error(&format!("tile {} is not in the tile list of {} names", tile, palette.len() - 1))),
+            }
             tile_index += 1;
         }
 
-        layer
+        Ok(layer)
     }
 
+    // The classic B5678/S45678 cave rule with wrapping borders, see CellularConfig for anything else
     pub fn cellular_automata(width: u32, height: u32, iterations: u32, tile_1: TileIndex, tile_2: TileIndex) -> Layer {
-        let mut map = Layer::new(width, height);
-        let mut swap_map = Layer::new(width, height);
-    
-        for i in 0..map.width {
-            for j in 0..map.height {
-                if rand::random() {
-                    map.set(i, j, tile_1);
-                }
-                else {
-                    map.set(i, j, tile_2);
-                }
+        let mut config = CellularConfig::new(width, height, iterations, tile_1, tile_2);
+        config.seed = rand::random();
+        config.generate()
+    }
+
+    pub fn render(&self, gfx: &mut Graphics, context: &RenderContext) {
+        for y in (0..self.height).rev() { 
+            self.render_row(gfx, context, y);
+        }
+    }
+
+    // Raised tiles are lifted by their height and stretched back down to the ground so they don't float
+    pub fn render_row(&self, gfx: &mut Graphics, context: &RenderContext, y: u32) {
+        let (tile_w, tile_h) = context.topology.tile_size();
+
+        for x in 0..self.width {
+            let index = self.get(x, y);
+            let tile = context.tiles.tile(index);
+
+            if index == TileIndex(0) {
+                continue;
             }
+
+            let texture = match &tile.autotile {
+                Some(autotile) => autotile.texture(autotile.mask(context.layers, x, y, context.topology, context.wrap.edge()), context.topology).unwrap_or_else(|| tile.texture_at(context.time)),
+                None => tile.texture_at(context.time),
+            };
+
+            let (tile_x, tile_y) = context.topology.tile_origin(x as i32, y as i32);
+            let x_off = tile.x_off / texture.width as f32 * tile_w + context.offset.0;
+            let y_off = tile.y_off / texture.height as f32 * tile_h + context.offset.1;
+            let lift = context.heights.map_or(0.0, |heights| heights.get(x, y) as f32 * heights.step * tile_h);
+
+            gfx.texture(texture.clone());
+            gfx.set_color(tile.color);
+            gfx.fill_rect(tile_x + x_off, tile_y + y_off, tile.x_scl * tile_w, tile.y_scl * tile_h + lift);
         }
-    
-        for _ in 0..iterations {
-            for x in 0..map.width {
-                for y in 0..map.height {
-                    let mut n = 0;
-                    for i in 0..3 {
-                        for j in 0..3 {
-                            let mut real_x = (x + i) as i32 - 1;
-                            let mut real_y = (y + j) as i32 - 1;
-    
-                            if real_x < 0 {
-                                real_x = map.width as i32 - 1;
-                            }
-                            if real_y < 0 {
-                                real_y = map.height as i32 - 1;
-                            }
-    
-                            if real_x >= map.width as i32 {
-                                real_x = 0;
-                            }
-                            if real_y >= map.height as i32 {
-                                real_y = 0;
-                            }
-    
-                            let real_x = real_x as u32;
-                            let real_y = real_y as u32;
-    
-                            if map.get(real_x, real_y) == tile_1 {
-                            n += 1;
-                        }         
-                    }
-                    }
-                    if map.get(x, y) == tile_1 {
-                        n -= 1;
-                    }
-    
-                    if n < 4 {
-                        swap_map.set(x, y, tile_2);
-                    } else if n > 4 {
-                        swap_map.set(x, y, tile_1);
-                    }
-                    else {
-                        swap_map.set(x, y, map.get(x, y));
-                    }
-                }
+    }
+
+    pub fn find_path<F>(&self, start: (u32, u32), goal: (u32, u32), options: &PathOptions, cost: F) -> Option<Path>
+    where F: Fn(TileIndex) -> Option<u32> {
+        pathfinding::find_path(self.width, self.height, start, goal, options, |_, (x, y)| cost(self.get(x, y)))
+    }
+
+    // Changes the size of the layer with every tile moved by dx, dy. Tiles that end up outside are
+    // dropped and uncovered tiles are left empty.
+    pub fn reframe(&mut self, width: u32, height: u32, dx: i32, dy: i32) {
+        let mut layer = Layer::new(width, height);
+        layer.encoding = self.encoding;
+
+        for coord in self.coords() {
+            if let Some(tile) = self.try_get(coord) {
+                let _ = layer.try_set(coord.offset(dx, dy), tile);
             }
-            for x in 0..map.width {
-                for y in 0..map.height {
-                    map.set(x, y, swap_map.get(x, y));
-                }
+        }
+
+        *self = layer;
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32, anchor: Anchor) {
+        let (dx, dy) = anchor.offset((self.width, self.height), (width, height));
+        self.reframe(width, height, dx, dy);
+    }
+
+    // Keeps only the given rectangle, clipped to the layer
+    pub fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let width = width.min(self.width.saturating_sub(x));
+        let height = height.min(self.height.saturating_sub(y));
+        self.reframe(width, height, -(x as i32), -(y as i32));
+    }
+
+    // Moves every tile by dx, dy. With wrap tiles pushed over one edge come back in on the other side,
+    // otherwise they are dropped.
+    pub fn shift(&mut self, dx: i32, dy: i32, wrap: bool) {
+        if !wrap {
+            self.reframe(self.width, self.height, dx, dy);
+            return;
+        }
+
+        let mut layer = Layer::new(self.width, self.height);
+        layer.encoding = self.encoding;
+        for coord in self.coords() {
+            if let (Some(tile), Some(to)) = (self.try_get(coord), Edge::Wrap.apply(coord.offset(dx, dy), self.width, self.height)) {
+                let _ = layer.try_set(to, tile);
             }
         }
-    
-        map
+        *self = layer;
     }
 
-    pub fn render(&self, gfx: &mut Graphics, tiles: &TileSet) {
-        for y in (0..self.height).rev() { 
-            for x in 0..self.width {
+    pub fn flip_horizontal(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width / 2 {
+                let tile = self.get(x, y);
+                self.set(x, y, self.get(self.width - 1 - x, y));
+                self.set(self.width - 1 - x, y, tile);
+            }
+        }
+    }
 
-                let index = self.get(x, y);
-                let tile = tiles.tile(index);
+    pub fn flip_vertical(&mut self) {
+        for y in 0..self.height / 2 {
+            for x in 0..self.width {
+                let tile = self.get(x, y);
+                self.set(x, y, self.get(x, self.height - 1 - y));
+                self.set(x, self.height - 1 - y, tile);
+            }
+        }
+    }
 
-                if index == TileIndex(0) {
-                    continue;
-                }
+    pub fn rotate_clockwise(&mut self) {
+        let mut layer = Layer::new(self.height, self.width);
+        layer.encoding = self.encoding;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                layer.set(y, self.width - 1 - x, self.get(x, y));
+            }
+        }
+        *self = layer;
+    }
 
-                let x_off = tile.x_off / tile.texture.width as f32;
-                let y_off = tile.y_off / tile.texture.height as f32;
-                
-                gfx.texture(tile.texture.clone());
-                gfx.set_color(tile.color);
-                gfx.fill_rect(x as f32 + x_off, y as f32 + y_off, tile.x_scl, tile.y_scl);
+    pub fn rotate_counter_clockwise(&mut self) {
+        let mut layer = Layer::new(self.height, self.width);
+        layer.encoding = self.encoding;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                layer.set(self.height - 1 - y, x, self.get(x, y));
             }
         }
+        *self = layer;
+    }
+
+    pub fn contains<C: Into<Coord>>(&self, coord: C) -> bool {
+        coord.into().inside(self.width, self.height)
+    }
+
+    pub fn try_get<C: Into<Coord>>(&self, coord: C) -> Option<TileIndex> {
+        coord.into().index(self.width, self.height).map(|i| self.tiles[i])
+    }
+
+    pub fn try_set<C: Into<Coord>>(&mut self, coord: C, tile_id: TileIndex) -> Result<(), String> {
+        let coord = coord.into();
+        match coord.index(self.width, self.height) {
+            Some(i) => {
+                self.tiles[i] = tile_id;
+                Ok(())
+            },
+            None => Err(format!("tile {}, {} is outside the {}x{} layer", coord.x, coord.y, self.width, self.height)),
+        }
     }
 
+    // Outside the layer reads give the empty tile and writes are dropped, both with an error in the log
     pub fn get(&self, x: u32, y: u32) -> TileIndex {
-        /*if x >= self.width || y >= self.height {
-            error_log!("Tried to get tile outside map range! Map size: {}, {} Tile pos: {}, {}", self.width, self.height, x, y);
-            return 0;
-        }*/
-        self.tiles[(x + y * self.width) as usize]
+        match self.try_get((x, y)) {
+            Some(tile) => tile,
+            None => {
+                error_log!("Tried to get tile outside map range! Map size: {}, {} Tile pos: {}, {}", self.width, self.height, x, y);
+                TileIndex(0)
+            },
+        }
     }
-    
+
     pub fn set(&mut self, x: u32, y: u32, tile_id: TileIndex) {
-        /*if x >= self.width || y >= self.height {
-            error_log!("Tried to set tile outside map range! Map size: {}, {} Tile pos: {}, {}", self.width, self.height, x, y);
-            return;
-        }*/
-        self.tiles[(x + y * self.width) as usize] = tile_id;
+        if let Err(e) = self.try_set((x, y), tile_id) {
+            error_log!("Tried to set tile outside map range! {}", e);
+        }
+    }
+
+    pub fn neighbors<C: Into<Coord>>(&self, coord: C, topology: Topology, diagonal: bool, edge: Edge) -> impl Iterator<Item = Coord> {
+        topology.neighbors_in(coord.into(), self.width, self.height, diagonal, edge)
+    }
+
+    // Every position of the layer, bottom row first
+    pub fn coords(&self) -> impl Iterator<Item = Coord> {
+        grid::region(Coord::new(0, 0), self.width, self.height)
+    }
+
+    // The positions of a rectangle that lie inside the layer
+    pub fn region<C: Into<Coord>>(&self, origin: C, width: u32, height: u32) -> impl Iterator<Item = Coord> {
+        let (layer_width, layer_height) = (self.width, self.height);
+        grid::region(origin.into(), width, height).filter(move |coord| coord.inside(layer_width, layer_height))
+    }
+
+    // Tiles go in as their place in TileSet::name_ranks, bottom row first
+    fn hash_content(&self, hasher: &mut ContentHasher, ranks: &[u32]) {
+        hasher.write_u32(self.width);
+        hasher.write_u32(self.height);
+        for tile in &self.tiles {
+            hasher.write_u32(ranks[tile.0]);
+        }
     }
 }
+// Binary map layout, all numbers little endian:
+//   magic "TBSM", version u8, flags u8, topology u8, wrap u8, map info
+//   tile set path count as LEB128 and paths, tile name count as LEB128 and tile names
+//   layer count u16
+//   per layer: width u32, height u32, index width u8 (1, 2 or 4 bytes), tile data
+//   object layer count u16 and object layers
+//   heights flag u8 and heights, see Heights::write_bytes
+//   file hash u64, see Map::file_hash
+// Tile data is stored bottom row first. With FLAG_RLE it is a list of (run length as LEB128, tile index)
+// pairs, otherwise one tile index per tile. Tile indices are positions in the tile names.
+// Bump the version whenever the layout changes, files of other versions are rejected.
+const MAP_MAGIC: &[u8; 4] = b"TBSM";
+const MAP_VERSION: u8 = 1;
+const MAP_FLAG_RLE: u8 = 1;
+
 pub struct Map {
+    pub info: MapInfo,
     pub layers: Vec<Layer>,
+    pub object_layers: Vec<ObjectLayer>,
+    pub topology: Topology,
+    pub wrap: Wrap,
+    pub heights: Option<Heights>,
     tile_set: TileSet,
 }
 
 impl Map {
     pub fn new() -> Map {
         Map {
+            info: MapInfo::new(),
             layers: Vec::new(),
+            object_layers: Vec::new(),
+            topology: Topology::Square,
+            wrap: Wrap::None,
+            heights: None,
             tile_set: TileSet::new(),
         }
     }
 
-    pub fn from_file(path: &str, tile_set: TileSet) -> Map {
-        let res = Resource::from_file(path);
+    // Merges the data files a map lists under tilesets into the tile set, skipping ones it already has
+    fn load_tile_sets(tile_set: &mut TileSet, paths: &[String]) -> Result<(), String> {
+        for path in paths {
+            if tile_set.has_source(path) {
+                continue;
+            }
+            let other = match tile_set.loading {
+                AtlasLoading::Textures => TileSet::from_file(path),
+                AtlasLoading::Sizes => TileSet::from_file_headless(path),
+                AtlasLoading::Nothing => TileSet::from_file_without_images(path),
+            };
+            let other = other.map_err(|e| e.to_string())?;
+            tile_set.append(other)?;
+        }
+        Ok(())
+    }
 
+    pub fn with_tile_set(tile_set: TileSet) -> Map {
         let mut map = Map::new();
+        map.tile_set = tile_set;
+        map
+    }
 
-        for layer_data in res.get("layers").unwrap().as_vec().unwrap() {
-            let width = layer_data.get("width").unwrap().as_i64().unwrap() as u32;
-            let height = layer_data.get("height").unwrap().as_i64().unwrap() as u32;
-            let index = layer_data.get("index").unwrap().as_i64().unwrap() as usize;
-            let map_data = layer_data.get("data").unwrap().as_vec().unwrap();
-            
-            let mut layer = Layer::new(width, height);
-            
-            let mut tile_index = 0;
-            for tile in map_data {
-                let x = tile_index % width;
-                let y = height - 1 - (tile_index - x) / width;
-                layer.set(x, y, TileIndex(tile.as_i64().unwrap() as usize % tile_set.len()));
-                tile_index += 1;
-            }
-            map.layers.resize_with(index + 1, || Layer::new(0, 0));
-            map.layers[index] = layer;
+    pub fn from_file(path: &str, tile_set: TileSet) -> Result<Map, LoadError> {
+        if path.ends_with(".tbm") {
+            let bytes = std::fs::read(path).map_err(|e| LoadError::new(path, "", LoadErrorKind::Io(e.to_string())))?;
+            return Map::read_bytes(&mut ByteReader::new(&bytes, path), tile_set);
         }
 
-        map.tile_set = tile_set;
-
-        map
+        let res = Resource::from_file(path)?;
+        Map::from_resource(&res.node(path), tile_set)
     }
 
-    pub fn to_file(&self, path: &str) {
-        let mut layers = Vec::new();
+    pub fn from_resource(res: &Node, mut tile_set: TileSet) -> Result<Map, LoadError> {
+        let mut map = Map::new();
 
-        for layer in &self.layers {
-            let mut map_data = HashMap::new();
-            map_data.insert("width".to_string(), Resource::I64(layer.width as i64));
-            map_data.insert("height".to_string(), Resource::I64(layer.height as i64));
+        map.topology = Topology::from_resource(res.get_optional("grid"))?;
+        map.wrap = Wrap::from_resource(res.get_optional("grid"))?;
 
-            let mut tiles = Vec::new(); 
+        if let Some(info) = res.get_optional("info") {
+            map.info = MapInfo::from_resource(&info)?;
+        }
 
-            for y in 0..layer.height {
-                for x in 0..layer.width {
-                    tiles.push(Resource::I64(layer.get(x, layer.height - 1 - y).0 as i64));
-                }
+        if let Some(tile_sets) = res.get_optional("tilesets") {
+            let mut paths = Vec::new();
+            for path in tile_sets.as_vec()? {
+                paths.push(path.as_str()?.to_string());
             }
+            Map::load_tile_sets(&mut tile_set, &paths).map_err(|e| tile_sets.error(&e))?;
+        }
 
-            map_data.insert("data".to_string(), Resource::Vec(tiles));
-            layers.push(Resource::Map(map_data));
+        let palette = tile_set.palette_from_resource(res.get_optional("tiles"))?;
+
+        for (i, layer_data) in res.get("layers")?.as_vec()?.iter().enumerate() {
+            let index = match layer_data.get_optional("index") {Some(x) => x.as_u32()? as usize, None => i};
+            let layer = Layer::from_resource(layer_data, &palette)?;
+
+            map.layers.resize_with(map.layers.len().max(index + 1), || Layer::new(0, 0));
+            map.layers[index] = layer;
         }
 
-        Resource::Vec(layers).to_file(path);
-    }
+        if let Some(object_layers) = res.get_optional("objects") {
+            for (i, layer_data) in object_layers.as_vec()?.iter().enumerate() {
+                let layer = ObjectLayer::from_resource(layer_data)?;
 
-    pub fn render(&self, gfx: &mut Graphics) {
-        for layer in &self.layers {
-            layer.render(gfx, &self.tile_set);
+                for (j, object) in layer.objects.iter().enumerate() {
+                    if object.x >= map.width() || object.y >= map.height() {
+                        return Err(object_layers.get_index(i)?.get("objects")?.get_index(j)?.error(&format!("object is outside the {}x{} map", map.width(), map.height())));
+                    }
+                }
+
+                map.object_layers.push(layer);
+            }
         }
-    }
+
+        if let Some(heights) = res.get_optional("heights") {
+            map.heights = Some(Heights::from_resource(&heights, map.width(), map.height())?);
+        }
+
+        if let Some(grid) = res.get_optional("grid") {
+            map.wrap.check(map.topology, map.width(), map.height()).map_err(|e| grid.error(&e))?;
+        }
+
+        for (player, (x, y)) in map.info.starts.iter().enumerate() {
+            if *x >= map.width() || *y >= map.height() {
+                return Err(res.get("info")?.get("starts")?.get_index(player)?.error(&format!("start position is outside the {}x{} map", map.width(), map.height())));
+            }
+        }
+
+        map.tile_set = tile_set;
+
+        if let Some(hash_d) = res.get_optional("hash") {
+            let hash = u64::from_str_radix(hash_d.as_str()?, 16).map_err(|_| hash_d.error("hash has to be a hexadecimal number"))?;
+            if let Err(e) = map.check_hash(hash) {
+                error_log!("{}", hash_d.error(&e));
+            }
+        }
+
+        Ok(map)
+    }
+
+    pub fn to_file(&self, path: &str) -> Result<(), String> {
+        if path.ends_with(".tbm") {
+            return std::fs::write(path, self.to_bytes(true)?).map_err(|e| format!("Unable to write {}: {}", path, e));
+        }
+
+        self.to_resource().to_file(path);
+        Ok(())
+    }
+
+    pub fn to_resource(&self) -> Resource {
+        let mut layers = Vec::new();
+
+        for (index, layer) in self.layers.iter().enumerate() {
+            let mut map_data = HashMap::new();
+            map_data.insert("index".to_string(), Resource::I64(index as i64));
+            map_data.insert("width".to_string(), Resource::I64(layer.width as i64));
+            map_data.insert("height".to_string(), Resource::I64(layer.height as i64));
+
+            let mut tiles = Vec::new(); 
+
+            for y in 0..layer.height {
+                for x in 0..layer.width {
+                    tiles.push(layer.get(x, layer.height - 1 - y).0 as u32);
+                }
+            }
+
+            if layer.encoding != LayerEncoding::List {
+                map_data.insert("encoding".to_string(), Resource::Str(layer.encoding.name().to_string()));
+            }
+            map_data.insert("data".to_string(), layer.encoding.encode(&tiles, layer.width));
+            layers.push(Resource::Map(map_data));
+        }
+
+        let mut map = HashMap::new();
+        map.insert("info".to_string(), self.info.to_resource());
+        let mut grid = self.topology.to_resource();
+        if let Resource::Map(grid) = &mut grid {
+            if self.wrap != Wrap::None {
+                grid.insert("wrap".to_string(), Resource::Str(self.wrap.name().to_string()));
+            }
+        }
+        map.insert("grid".to_string(), grid);
+        map.insert("tilesets".to_string(), Resource::Vec(self.tile_set.sources().iter().map(|source| Resource::Str(source.path.clone())).collect()));
+        map.insert("tiles".to_string(), Resource::Vec(self.tile_set.names().iter().map(|name| Resource::Str(name.to_string())).collect()));
+        map.insert("layers".to_string(), Resource::Vec(layers));
+        if let Some(heights) = &self.heights {
+            map.insert("heights".to_string(), heights.to_resource());
+        }
+        if !self.object_layers.is_empty() {
+            map.insert("objects".to_string(), Resource::Vec(self.object_layers.iter().map(|layer| layer.to_resource()).collect()));
+        }
+        map.insert("hash".to_string(), Resource::Str(format!("{:016x}", self.file_hash())));
+
+        Resource::Map(map)
+    }
+
+    // Fails when the map has more layers or object layers than the u16 counts can hold
+    pub fn to_bytes(&self, compress: bool) -> Result<Vec<u8>, String> {
+        let layer_count = u16::try_from(self.layers.len()).map_err(|_| format!("{} layers don't fit in a map file, at most {} do", self.layers.len(), u16::MAX))?;
+        let object_layer_count = u16::try_from(self.object_layers.len()).map_err(|_| format!("{} object layers don't fit in a map file, at most {} do", self.object_layers.len(), u16::MAX))?;
+        let mut out = ByteWriter::new();
+
+        out.write_bytes(MAP_MAGIC);
+        out.write_u8(MAP_VERSION);
+        out.write_u8(if compress { MAP_FLAG_RLE } else { 0 });
+        out.write_u8(self.topology.to_byte());
+        out.write_u8(self.wrap.to_byte());
+        self.info.write_bytes(&mut out);
+
+        out.write_var(self.tile_set.sources().len() as u64);
+        for source in self.tile_set.sources() {
+            out.write_str(&source.path);
+        }
+
+        let names = self.tile_set.names();
+        out.write_var(names.len() as u64);
+        for name in names {
+            out.write_str(name);
+        }
+
+        out.write_u16(layer_count);
+
+        for layer in &self.layers {
+            out.write_u32(layer.width);
+            out.write_u32(layer.height);
+
+            let largest = layer.tiles.iter().map(|tile| tile.0).max().unwrap_or(0);
+            let index_width = if largest <= u8::MAX as usize { 1 } else if largest <= u16::MAX as usize { 2 } else { 4 };
+            out.write_u8(index_width);
+
+            if compress {
+                let mut i = 0;
+                while i < layer.tiles.len() {
+                    let tile = layer.tiles[i];
+                    let mut run = 1;
+                    while i + run < layer.tiles.len() && layer.tiles[i + run] == tile {
+                        run += 1;
+                    }
+                    out.write_var(run as u64);
+                    out.write_sized(tile.0 as u32, index_width);
+                    i += run;
+                }
+            } else {
+                for tile in &layer.tiles {
+                    out.write_sized(tile.0 as u32, index_width);
+                }
+            }
+        }
+
+        out.write_u16(object_layer_count);
+        for object_layer in &self.object_layers {
+            object_layer.write_bytes(&mut out);
+        }
+
+        match &self.heights {
+            Some(heights) => {
+                out.write_u8(1);
+                heights.write_bytes(&mut out);
+            },
+            None => out.write_u8(0),
+        }
+
+        out.write_u64(self.file_hash());
+
+        Ok(out.data)
+    }
+
+    pub fn from_bytes(bytes: &[u8], tile_set: TileSet) -> Result<Map, LoadError> {
+        Map::read_bytes(&mut ByteReader::new(bytes, "<bytes>"), tile_set)
+    }
+
+    fn read_bytes(input: &mut ByteReader, mut tile_set: TileSet) -> Result<Map, LoadError> {
+        if input.read_bytes(4)? != MAP_MAGIC {
+            return Err(input.error("not a binary map file"));
+        }
+
+        let version = input.read_u8()?;
+        if version != MAP_VERSION {
+            return Err(input.error(&format!("unsupported map version {}, expected {}", version, MAP_VERSION)));
+        }
+
+        let flags = input.read_u8()?;
+        let topology = input.read_u8()?;
+
+        let mut map = Map::new();
+        map.topology = Topology::from_byte(topology).ok_or_else(|| input.error(&format!("unknown topology {}", topology)))?;
+
+        let wrap = input.read_u8()?;
+        map.wrap = Wrap::from_byte(wrap).ok_or_else(|| input.error(&format!("unknown wrap {}", wrap)))?;
+
+        map.info = MapInfo::read_bytes(input)?;
+
+        let count = input.read_var()?;
+        let mut paths = Vec::new();
+        for _ in 0..count {
+            paths.push(input.read_str()?);
+        }
+        Map::load_tile_sets(&mut tile_set, &paths).map_err(|e| input.error(&e))?;
+
+        let count = input.read_var()?;
+        let mut names = Vec::new();
+        for _ in 0..count {
+            names.push(input.read_str()?);
+        }
+        let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+        let palette = tile_set.palette(&names).map_err(|name| input.error(&format!("unknown tile \"{}\"", name)))?;
+
+        let layer_count = input.read_u16()?;
+        for _ in 0..layer_count {
+            let width = input.read_u32()?;
+            let height = input.read_u32()?;
+            let index_width = input.read_u8()?;
+
+            let size = grid::tile_count(width, height).map_err(|e| input.error(&e))?;
+
+            let mut layer = Layer::new(width, height);
+
+            let mut i = 0;
+            while i < size {
+                let run = if flags & MAP_FLAG_RLE != 0 { input.read_var()? as usize } else { 1 };
+                let tile = input.read_sized(index_width)? as usize;
+
+                if run == 0 || run > size - i {
+                    return Err(input.error(&format!("tile run of {} doesn't fit in the layer", run)));
+                }
+                let tile = match palette.get(tile) {
+                    Some(tile) => *tile,
+                    None => return Err(input.error(&format!("tile {} is not in the tile list of {} names", tile, palette.len() - 1))),
+                };
+
+                for tile_id in &mut layer.tiles[i..i + run] {
+                    *tile_id = tile;
+                }
+                i += run;
+            }
+
+            map.layers.push(layer);
+        }
+
+        let object_layer_count = input.read_u16()?;
+        for _ in 0..object_layer_count {
+            let object_layer = ObjectLayer::read_bytes(input)?;
+            for object in &object_layer.objects {
+                if object.x >= map.width() || object.y >= map.height() {
+                    return Err(input.error(&format!("object at {}, {} is outside the {}x{} map", object.x, object.y, map.width(), map.height())));
+                }
+            }
+            map.object_layers.push(object_layer);
+        }
+
+        if input.read_u8()? != 0 {
+            map.heights = Some(Heights::read_bytes(input, map.width(), map.height())?);
+        }
+
+        map.wrap.check(map.topology, map.width(), map.height()).map_err(|e| input.error(&e))?;
+
+        for (x, y) in &map.info.starts {
+            if *x >= map.width() || *y >= map.height() {
+                return Err(input.error(&format!("start position {}, {} is outside the {}x{} map", x, y, map.width(), map.height())));
+            }
+        }
+
+        map.tile_set = tile_set;
+
+        let hash = input.read_u64()?;
+        if let Err(e) = map.check_hash(hash) {
+            error_log!("{}", input.error(&e));
+        }
+
+        Ok(map)
+    }
+
+    // Saved maps carry the file hash they had when saved, a different one now means the map or the tiles it uses
+    // changed. That is only worth a warning when loading, games compare the full content hash instead.
+    fn check_hash(&self, saved: u64) -> Result<(), String> {
+        let hash = self.file_hash();
+        if hash != saved {
+            return Err(format!("map was saved with hash {:016x} but loads as {:016x}, it or the tiles it uses changed since", saved, hash));
+        }
+        Ok(())
+    }
+
+    pub fn render(&self, gfx: &mut Graphics, time: f32) {
+        self.render_copy(gfx, time, (0.0, 0.0));
+    }
+
+    // Draws the map under the view rectangle (x, y, width, height in world units). A wrapping map is drawn once for
+    // every copy of it the view overlaps, so panning across the seam shows the other side of the map.
+    pub fn render_view(&self, gfx: &mut Graphics, time: f32, view: (f32, f32, f32, f32)) {
+        let (horizontal, vertical) = self.periods();
+        if horizontal.is_none() && vertical.is_none() {
+            return self.render_copy(gfx, time, (0.0, 0.0));
+        }
+
+        // Corners of the tile centers, grown by a couple of tiles for tall and raised tiles
+        let (tile_w, tile_h) = self.topology.tile_size();
+        let (w, h) = (self.width() as i32 - 1, self.height() as i32 - 1);
+        let corners = [(0, 0), (w, 0), (0, h), (w, h)].iter().map(|&(x, y)| self.topology.tile_center(x, y)).collect::<Vec<_>>();
+        let min_x = corners.iter().map(|c| c.0).fold(f32::MAX, f32::min) - tile_w * 2.0;
+        let max_x = corners.iter().map(|c| c.0).fold(f32::MIN, f32::max) + tile_w * 2.0;
+        let min_y = corners.iter().map(|c| c.1).fold(f32::MAX, f32::min) - tile_h * 2.0;
+        let max_y = corners.iter().map(|c| c.1).fold(f32::MIN, f32::max) + tile_h * 2.0;
+
+        // How many periods p the map has to move so that [min, max] overlaps [from, from + size]
+        let copies = |from: f32, size: f32, min: f32, max: f32, p: f32| ((from - max) / p).floor() as i32..=((from + size - min) / p).ceil() as i32;
+
+        let (view_x, view_y, view_w, view_h) = view;
+        let rows = match vertical {
+            Some((_, py)) => copies(view_y, view_h, min_y, max_y, py),
+            None => 0..=0,
+        };
+
+        // Copies further up are further back, so they go first
+        for ky in rows.rev() {
+            let (base_x, base_y) = vertical.map_or((0.0, 0.0), |(px, py)| (px * ky as f32, py * ky as f32));
+            let columns = match horizontal {
+                Some((px, _)) => copies(view_x - base_x, view_w, min_x, max_x, px),
+                None => 0..=0,
+            };
+
+            for kx in columns {
+                let (px, py) = horizontal.unwrap_or((0.0, 0.0));
+                self.render_copy(gfx, time, (base_x + px * kx as f32, base_y + py * kx as f32));
+            }
+        }
+    }
+
+    // With heights the layers are drawn row by row from the back, so a raised tile covers everything behind it
+    fn render_copy(&self, gfx: &mut Graphics, time: f32, offset: (f32, f32)) {
+        let context = RenderContext {
+            tiles: &self.tile_set,
+            layers: &self.layers,
+            topology: self.topology,
+            wrap: self.wrap,
+            heights: self.heights.as_ref(),
+            time,
+            offset,
+        };
+
+        match &self.heights {
+            Some(_) => {
+                for y in (0..self.height()).rev() {
+                    for layer in self.layers.iter().filter(|layer| y < layer.height) {
+                        layer.render_row(gfx, &context, y);
+                    }
+                }
+            },
+            None => {
+                for layer in &self.layers {
+                    layer.render(gfx, &context);
+                }
+            },
+        }
+
+        let (tile_w, tile_h) = self.topology.tile_size();
+        for object in self.objects() {
+            if let Some(kind) = self.tile_set.object_kind(&object.kind) {
+                let (x, y) = self.topology.tile_origin(object.x as i32, object.y as i32);
+                let lift = self.height_at(object.x, object.y) as f32 * self.heights.as_ref().map_or(0.0, |heights| heights.step) * tile_h;
+                gfx.texture(kind.texture.clone());
+                gfx.set_color(WHITE);
+                gfx.fill_rect(x + offset.0, y + offset.1 + lift, tile_w, tile_h);
+            }
+        }
+    }
+
+    // How far apart in world units the copies of a wrapping map are drawn, left to right and bottom to top. None
+    // along the directions the map doesn't wrap.
+    pub fn periods(&self) -> (Option<(f32, f32)>, Option<(f32, f32)>) {
+        let (width, height) = (self.width() as i32, self.height() as i32);
+        if width == 0 || height == 0 {
+            return (None, None);
+        }
+
+        let (ox, oy) = self.topology.tile_center(0, 0);
+        let period = |(x, y): (f32, f32)| (x - ox, y - oy);
+        let horizontal = if self.wrap.horizontal() { Some(period(self.topology.tile_center(width, 0))) } else { None };
+        let vertical = if self.wrap.vertical() { Some(period(self.topology.tile_center(0, height))) } else { None };
+        (horizontal, vertical)
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = &MapObject> {
+        self.object_layers.iter().flat_map(|layer| layer.objects.iter())
+    }
+
+    pub fn object_at(&self, x: u32, y: u32) -> Option<&MapObject> {
+        self.object_layers.iter().rev().find_map(|layer| layer.object_at(x, y))
+    }
+
+    pub fn object_at_mut(&mut self, x: u32, y: u32) -> Option<&mut MapObject> {
+        self.object_layers.iter_mut().rev().find_map(|layer| layer.object_at_mut(x, y))
+    }
 
     pub fn tile_set(&self) -> &TileSet {
         &self.tile_set
     }
-}
\ No newline at end of file
+
+    // Hash of everything a game on the map depends on, the tile definitions included. Comes out the same wherever
+    // the same content is loaded, whatever file format, layer encoding or tile order it was stored with, so two
+    // machines can compare maps by it. The game doesn't record replays yet, once it does they should keep this
+    // hash with the moves and refuse to play them back on a map that hashes differently.
+    pub fn content_hash(&self) -> u64 {
+        self.hash_with(|_| true, |_| true)
+    }
+
+    // Like content_hash, but only the tiles and object kinds the map uses count. This is the one saved with the
+    // map, so adding to or changing other parts of the data file doesn't make every map warn when it loads.
+    pub fn file_hash(&self) -> u64 {
+        let mut used = vec![false; self.tile_set.len()];
+        for layer in &self.layers {
+            for tile in &layer.tiles {
+                used[tile.0] = true;
+            }
+        }
+        let kinds: HashSet<&str> = self.objects().map(|object| object.kind.as_str()).collect();
+
+        self.hash_with(|tile| used[tile.0], |kind| kinds.contains(kind))
+    }
+
+    fn hash_with<T: Fn(TileIndex) -> bool, K: Fn(&str) -> bool>(&self, tile: T, kind: K) -> u64 {
+        let mut hasher = ContentHasher::new();
+
+        hasher.write_u8(self.topology.to_byte());
+        hasher.write_u8(self.wrap.to_byte());
+        self.info.hash_content(&mut hasher);
+        self.tile_set.hash_some(&mut hasher, &tile, kind);
+
+        let ranks = self.tile_set.name_ranks(tile);
+        hasher.write_u64(self.layers.len() as u64);
+        for layer in &self.layers {
+            layer.hash_content(&mut hasher, &ranks);
+        }
+
+        hasher.write_u64(self.object_layers.len() as u64);
+        for object_layer in &self.object_layers {
+            object_layer.hash_content(&mut hasher);
+        }
+
+        match &self.heights {
+            Some(heights) => {
+                hasher.write_u8(1);
+                heights.hash_content(&mut hasher);
+            },
+            None => hasher.write_u8(0),
+        }
+
+        hasher.finish()
+    }
+
+    // The topmost non empty tile decides the terrain, so a castle on layer 1 overrides the grass below it
+    pub fn terrain(&self, x: u32, y: u32) -> &Terrain {
+        for layer in self.layers.iter().rev() {
+            match layer.try_get((x, y)) {
+                Some(index) if index != TileIndex(0) => return self.tile_set.terrain(index),
+                _ => (),
+            }
+        }
+
+        self.tile_set.terrain(TileIndex(0))
+    }
+
+    // The position a coordinate stands for, taken around the seam on wrapping maps. None when it's off the map.
+    pub fn wrap_coord<C: Into<Coord>>(&self, coord: C) -> Option<Coord> {
+        self.wrap.edge().apply(coord.into(), self.width(), self.height())
+    }
+
+    pub fn neighbors(&self, x: u32, y: u32, diagonal: bool) -> impl Iterator<Item = Coord> {
+        self.topology.neighbors_in(Coord::from((x, y)), self.width(), self.height(), diagonal, self.wrap.edge())
+    }
+
+    // Steps between two tiles, the short way around on wrapping maps
+    pub fn distance(&self, a: (u32, u32), b: (u32, u32), diagonal: bool) -> u32 {
+        self.topology.wrapped_distance((a.0 as i32, a.1 as i32), (b.0 as i32, b.1 as i32), diagonal, self.wrap, self.width(), self.height())
+    }
+
+    pub fn width(&self) -> u32 {
+        self.layers.iter().map(|layer| layer.width).max().unwrap_or(0)
+    }
+
+    pub fn height(&self) -> u32 {
+        self.layers.iter().map(|layer| layer.height).max().unwrap_or(0)
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32, anchor: Anchor) -> Result<(), String> {
+        let (dx, dy) = anchor.offset((self.width(), self.height()), (width, height));
+        self.check_offset(dx, dy)?;
+        self.transform(width, height, |x, y| (x + dx, y + dy), |layer| layer.reframe(width, height, dx, dy))
+    }
+
+    // Keeps only the given rectangle, clipped to the map
+    pub fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) -> Result<(), String> {
+        let width = width.min(self.width().saturating_sub(x));
+        let height = height.min(self.height().saturating_sub(y));
+        let (dx, dy) = (-(x as i32), -(y as i32));
+        self.check_offset(dx, dy)?;
+        self.transform(width, height, |x, y| (x + dx, y + dy), |layer| layer.reframe(width, height, dx, dy))
+    }
+
+    pub fn shift(&mut self, dx: i32, dy: i32, wrap: bool) -> Result<(), String> {
+        let (width, height) = (self.width(), self.height());
+        self.check_offset(dx, dy)?;
+        if wrap {
+            match self.topology {
+                Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset) if height % 2 == 1 => return Err("can't wrap a pointy offset hex map with an odd height".to_string()),
+                Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset) if width % 2 == 1 => return Err("can't wrap a flat offset hex map with an odd width".to_string()),
+                _ => (),
+            }
+        }
+
+        self.transform(width, height, |x, y| {
+            if wrap {
+                ((x + dx).rem_euclid(width as i32), (y + dy).rem_euclid(height as i32))
+            } else {
+                (x + dx, y + dy)
+            }
+        }, |layer| layer.shift(dx, dy, wrap))
+    }
+
+    pub fn flip_horizontal(&mut self) -> Result<(), String> {
+        self.check_square("flip")?;
+        let (width, height) = (self.width(), self.height());
+        self.transform(width, height, |x, y| (width as i32 - 1 - x, y), Layer::flip_horizontal)
+    }
+
+    pub fn flip_vertical(&mut self) -> Result<(), String> {
+        self.check_square("flip")?;
+        let (width, height) = (self.width(), self.height());
+        self.transform(width, height, |x, y| (x, height as i32 - 1 - y), Layer::flip_vertical)
+    }
+
+    pub fn rotate_clockwise(&mut self) -> Result<(), String> {
+        self.check_square("rotate")?;
+        self.check_rotate_wrap()?;
+        let (width, height) = (self.width(), self.height());
+        self.transform(height, width, |x, y| (y, width as i32 - 1 - x), Layer::rotate_clockwise)
+    }
+
+    pub fn rotate_counter_clockwise(&mut self) -> Result<(), String> {
+        self.check_square("rotate")?;
+        self.check_rotate_wrap()?;
+        let (width, height) = (self.width(), self.height());
+        self.transform(height, width, |x, y| (height as i32 - 1 - y, x), Layer::rotate_counter_clockwise)
+    }
+
+    fn check_square(&self, operation: &str) -> Result<(), String> {
+        if self.topology.is_hex() {
+            return Err(format!("can't {} a hex map", operation));
+        }
+        Ok(())
+    }
+
+    // Rotating would turn the seam of a map that only wraps horizontally into a vertical one, which isn't a wrap mode
+    fn check_rotate_wrap(&self) -> Result<(), String> {
+        if self.wrap == Wrap::Horizontal {
+            return Err("can't rotate a map that only wraps horizontally".to_string());
+        }
+        Ok(())
+    }
+
+    // Offset hex rows (pointy) or columns (flat) alternate, moving them by an odd amount would change the shape of the map
+    fn check_offset(&self, dx: i32, dy: i32) -> Result<(), String> {
+        match self.topology {
+            Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset) if dy % 2 != 0 => Err(format!("can't move a pointy offset hex map by an odd number of rows ({})", dy)),
+            Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset) if dx % 2 != 0 => Err(format!("can't move a flat offset hex map by an odd number of columns ({})", dx)),
+            _ => Ok(()),
+        }
+    }
+
+    // Applies the same change to every layer, object and start of the map. Layers smaller than the map are grown to
+    // its size first so they line up afterwards. Objects moved outside the new size are dropped, starts are not and
+    // the map is left untouched if one would be.
+    fn transform<P, L>(&mut self, width: u32, height: u32, point: P, layer: L) -> Result<(), String>
+    where
+        P: Fn(i32, i32) -> (i32, i32),
+        L: Fn(&mut Layer),
+    {
+        let inside = |(x, y): (i32, i32)| x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height;
+
+        self.wrap.check(self.topology, width, height)?;
+
+        let mut starts = Vec::with_capacity(self.info.starts.len());
+        for &(x, y) in &self.info.starts {
+            let (nx, ny) = point(x as i32, y as i32);
+            if !inside((nx, ny)) {
+                return Err(format!("start at {}, {} would end up outside the map", x, y));
+            }
+            starts.push((nx as u32, ny as u32));
+        }
+        self.info.starts = starts;
+
+        let (map_width, map_height) = (self.width(), self.height());
+        for l in &mut self.layers {
+            if l.width != map_width || l.height != map_height {
+                l.reframe(map_width, map_height, 0, 0);
+            }
+            layer(l);
+        }
+
+        if let Some(heights) = &mut self.heights {
+            *heights = heights.transformed(width, height, &point);
+        }
+
+        for object_layer in &mut self.object_layers {
+            let objects = std::mem::take(&mut object_layer.objects);
+            object_layer.objects = objects.into_iter().filter_map(|mut object| {
+                let (nx, ny) = point(object.x as i32, object.y as i32);
+                if !inside((nx, ny)) {
+                    return None;
+                }
+                object.x = nx as u32;
+                object.y = ny as u32;
+                Some(object)
+            }).collect();
+        }
+
+        Ok(())
+    }
+
+    pub fn movement_cost(&self, x: u32, y: u32, movement_class: &str) -> Option<u32> {
+        let terrain = self.terrain(x, y);
+        if terrain.is_passable(movement_class) {
+            Some(terrain.movement_cost)
+        } else {
+            None
+        }
+    }
+
+    // Entering a tile costs its movement cost, plus the uphill cost for every level climbed on maps with heights
+    pub fn step_cost(&self, from: (u32, u32), to: (u32, u32), movement_class: &str) -> Option<u32> {
+        let cost = self.movement_cost(to.0, to.1, movement_class)?;
+        match &self.heights {
+            Some(heights) => Some(cost + heights.climb(from, to).max(0) as u32 * heights.uphill_cost),
+            None => Some(cost),
+        }
+    }
+
+    pub fn height_at(&self, x: u32, y: u32) -> u8 {
+        self.heights.as_ref().map_or(0, |heights| heights.get(x, y))
+    }
+
+    // The defense bonus of the defender's terrain, raised for every level the defender stands above the attacker
+    // and lowered for every level below
+    pub fn defense_bonus(&self, attacker: (u32, u32), defender: (u32, u32)) -> i32 {
+        let bonus = self.terrain(defender.0, defender.1).defense_bonus;
+        match &self.heights {
+            Some(heights) => bonus - heights.climb(defender, attacker) * heights.defense_bonus,
+            None => bonus,
+        }
+    }
+
+    pub fn vision_range(&self, x: u32, y: u32, base: u32) -> u32 {
+        match &self.heights {
+            Some(heights) => base + heights.get(x, y) as u32 * heights.vision_bonus,
+            None => base,
+        }
+    }
+
+    // Whether a unit with the given base vision range sees a tile. Tiles in between that are higher than both ends
+    // block the view.
+    pub fn can_see(&self, from: (u32, u32), to: (u32, u32), base: u32) -> bool {
+        let a = (from.0 as i32, from.1 as i32);
+        let b = self.topology.closest_copy(a, (to.0 as i32, to.1 as i32), true, self.wrap, self.width(), self.height());
+        let distance = self.topology.distance(a, b, true);
+        if distance > self.vision_range(from.0, from.1, base) {
+            return false;
+        }
+
+        let heights = match &self.heights {
+            Some(heights) => heights,
+            None => return true,
+        };
+        let eye = heights.get(from.0, from.1).max(heights.get(to.0, to.1));
+
+        // Walk the line between the tile centers in half tile steps
+        let (ax, ay) = self.topology.tile_center(a.0, a.1);
+        let (bx, by) = self.topology.tile_center(b.0, b.1);
+        let steps = distance * 2;
+        for i in 1..steps {
+            let t = i as f32 / steps as f32;
+            let tile = self.topology.tile_at(ax + (bx - ax) * t, ay + (by - ay) * t);
+            if tile == a || tile == b {
+                continue;
+            }
+            if self.wrap_coord(tile).and_then(|tile| heights.try_get(tile)).map_or(false, |height| height > eye) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn find_path(&self, start: (u32, u32), goal: (u32, u32), movement_class: &str, options: &PathOptions) -> Option<Path> {
+        let options = PathOptions { topology: self.topology, wrap: self.wrap, ..*options };
+        pathfinding::find_path(self.width(), self.height(), start, goal, &options, |from, to| self.step_cost(from, to, movement_class))
+    }
+
+    // Occupied tiles can't be moved into or through, the start tile itself is never treated as occupied
+    pub fn reachable(&self, start: (u32, u32), movement_points: u32, movement_class: &str, occupied: &HashSet<(u32, u32)>, options: &PathOptions) -> Reachable {
+        let options = PathOptions { topology: self.topology, wrap: self.wrap, ..*options };
+        pathfinding::reachable(self.width(), self.height(), start, movement_points, &options, |from, to| {
+            if occupied.contains(&to) {
+                None
+            } else {
+                self.step_cost(from, to, movement_class)
+            }
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn tile_set() -> TileSet {
+        testing::tile_set(&["grass", "water", "castle"])
+    }
+
+    fn sample_map() -> Map {
+        let mut map = Map::with_tile_set(tile_set());
+        let grass = map.tile_set().find("grass").unwrap();
+        let water = map.tile_set().find("water").unwrap();
+        let castle = map.tile_set().find("castle").unwrap();
+
+        let mut ground = Layer::new(5, 4);
+        for coord in ground.coords().collect::<Vec<_>>() {
+            ground.try_set(coord, if coord.x < 2 { water } else { grass }).unwrap();
+        }
+        let mut buildings = Layer::new(5, 4);
+        buildings.set(3, 2, castle);
+        map.layers.push(ground);
+        map.layers.push(buildings);
+
+        map.info.name = "Test".to_string();
+        map.info.players = 2;
+        map.info.starts = vec![(2, 0), (4, 3)];
+
+        let mut objects = ObjectLayer::new("objects");
+        let mut city = MapObject::new("city", 3, 2);
+        city.properties.insert("owner".to_string(), Resource::I64(1));
+        objects.objects.push(city);
+        map.object_layers.push(objects);
+
+        let mut heights = Heights::new(5, 4);
+        heights.set(4, 3, 2);
+        map.heights = Some(heights);
+        map.wrap = Wrap::Horizontal;
+
+        map
+    }
+
+    fn assert_same(a: &Map, b: &Map) {
+        assert_eq!(a.layers.len(), b.layers.len());
+        for (la, lb) in a.layers.iter().zip(&b.layers) {
+            assert_eq!((la.width, la.height), (lb.width, lb.height));
+            assert!(la.coords().all(|c| la.try_get(c) == lb.try_get(c)));
+        }
+        assert_eq!(a.info.name, b.info.name);
+        assert_eq!(a.info.starts, b.info.starts);
+        assert_eq!(a.topology, b.topology);
+        assert_eq!(a.wrap, b.wrap);
+        assert_eq!(a.objects().count(), b.objects().count());
+        let city = b.object_at(3, 2).unwrap();
+        assert_eq!(city.properties.get("owner").and_then(|owner| owner.as_i64()), Some(1));
+        assert_eq!(a.height_at(4, 3), b.height_at(4, 3));
+    }
+
+    fn yaml_round_trip(map: &Map) -> Map {
+        let res = Resource::from_string(&map.to_resource().to_string()).unwrap();
+        Map::from_resource(&res.node("test"), tile_set()).unwrap()
+    }
+
+    #[test]
+    fn yaml_layer_size_is_checked() {
+        let palette = [TileIndex(0), TileIndex(1)];
+
+        let res = Resource::from_string("width: 2\nheight: 2\ndata: [0, 1, 1, 0]").unwrap();
+        let layer = Layer::from_resource(&res.node("test"), &palette).unwrap();
+        assert!(layer.try_get((0, 1)) == Some(TileIndex(0)) && layer.try_get((1, 1)) == Some(TileIndex(1)));
+
+        let res = Resource::from_string("width: 3\nheight: 2\ndata: [0, 1, 1, 0]").unwrap();
+        assert!(Layer::from_resource(&res.node("test"), &palette).is_err());
+
+        let res = Resource::from_string("width: 4294967295\nheight: 4294967295\ndata: [0]").unwrap();
+        assert!(Layer::from_resource(&res.node("test"), &palette).is_err());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let map = sample_map();
+        for &compress in &[false, true] {
+            let loaded = Map::from_bytes(&map.to_bytes(compress).unwrap(), tile_set()).unwrap();
+            assert_same(&map, &loaded);
+        }
+    }
+
+    #[test]
+    fn counts_past_u16_are_errors() {
+        let mut map = sample_map();
+        map.object_layers.resize(u16::MAX as usize + 1, ObjectLayer::new("units"));
+        assert!(map.to_bytes(true).is_err());
+
+        map.object_layers.truncate(u16::MAX as usize);
+        assert_eq!(Map::from_bytes(&map.to_bytes(true).unwrap(), tile_set()).unwrap().object_layers.len(), u16::MAX as usize);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let bytes = sample_map().to_bytes(true).unwrap();
+        assert_eq!(bytes[4], MAP_VERSION);
+        for &version in &[0, MAP_VERSION + 1, u8::MAX] {
+            let mut other = bytes.clone();
+            other[4] = version;
+            assert!(Map::from_bytes(&other, tile_set()).is_err());
+        }
+    }
+
+    #[test]
+    fn truncated_binary_is_an_error() {
+        let bytes = sample_map().to_bytes(true).unwrap();
+        for len in 0..bytes.len() {
+            assert!(Map::from_bytes(&bytes[..len], tile_set()).is_err(), "{} of {} bytes loaded", len, bytes.len());
+        }
+    }
+
+    #[test]
+    fn garbage_binary_is_an_error() {
+        assert!(Map::from_bytes(b"not a map at all", tile_set()).is_err());
+
+        // A valid header followed by a string claiming to be as long as possible
+        let bytes = sample_map().to_bytes(false).unwrap();
+        let mut crafted = bytes[..7].to_vec();
+        crafted.extend_from_slice(&[0xFF; 9]);
+        crafted.push(0x01);
+        assert!(Map::from_bytes(&crafted, tile_set()).is_err());
+
+        // Every byte flipped in turn, loading may succeed but must not panic
+        for i in 0..bytes.len() {
+            let mut broken = bytes.clone();
+            broken[i] ^= 0xFF;
+            let _ = Map::from_bytes(&broken, tile_set());
+        }
+    }
+
+    #[test]
+    fn hash_survives_saving() {
+        let mut map = sample_map();
+        let city = &mut map.object_layers[0].objects[0];
+        city.properties.insert("garrison".to_string(), Resource::F64(1.0));
+        city.properties.insert("morale".to_string(), Resource::F64(0.5));
+        let hash = map.content_hash();
+
+        let from_yaml = yaml_round_trip(&map);
+        let from_bytes = Map::from_bytes(&map.to_bytes(true).unwrap(), tile_set()).unwrap();
+        assert_same(&map, &from_yaml);
+        assert_eq!(from_yaml.content_hash(), hash);
+        assert_eq!(from_bytes.content_hash(), hash);
+        assert_eq!(Map::from_bytes(&from_yaml.to_bytes(false).unwrap(), tile_set()).unwrap().content_hash(), hash);
+        assert_eq!(yaml_round_trip(&from_bytes).content_hash(), hash);
+    }
+
+    #[test]
+    fn hash_follows_content() {
+        let map = sample_map();
+        let grass = map.tile_set().find("grass").unwrap();
+
+        let mut changed = sample_map();
+        changed.layers[1].set(0, 0, grass);
+        assert_ne!(changed.content_hash(), map.content_hash());
+
+        let mut changed = sample_map();
+        changed.wrap = Wrap::None;
+        assert_ne!(changed.content_hash(), map.content_hash());
+
+        let mut changed = sample_map();
+        changed.layers[0].encoding = LayerEncoding::Rle;
+        assert_eq!(changed.content_hash(), map.content_hash());
+    }
+
+    #[test]
+    fn saved_hash_is_checked() {
+        let map = sample_map();
+        let hash = map.file_hash();
+
+        // Changed maps still load, the saved hash only makes them warn
+        let yaml = map.to_resource().to_string();
+        assert!(yaml.contains(&format!("{:016x}", hash)));
+        let edited = Resource::from_string(&yaml.replace("Test", "Edited")).unwrap();
+        let edited = Map::from_resource(&edited.node("test"), tile_set()).unwrap();
+        assert!(edited.check_hash(hash).is_err());
+        assert!(map.check_hash(hash).is_ok());
+
+        let mut bytes = map.to_bytes(true).unwrap();
+        let end = bytes.len();
+        bytes[end - 1] ^= 1;
+        assert!(Map::from_bytes(&bytes, tile_set()).is_ok());
+
+        // Tiles the map doesn't use are left out of the file hash, the full content hash still sees them
+        let mut tiles = tile_set();
+        let mut lava = Tile::new_invalid();
+        lava.name = "lava".to_string();
+        tiles.add_tile(lava);
+        let loaded = Map::from_bytes(&map.to_bytes(true).unwrap(), tiles).unwrap();
+        assert_eq!(loaded.file_hash(), hash);
+        assert_ne!(loaded.content_hash(), map.content_hash());
+
+        let mut tiles = tile_set();
+        let water = tiles.find("water").unwrap();
+        tiles.tile_mut(water).terrain.passable = false;
+        let loaded = Map::from_bytes(&map.to_bytes(true).unwrap(), tiles).unwrap();
+        assert!(loaded.check_hash(hash).is_err());
+    }
+
+    #[test]
+    fn appended_tiles_keep_their_atlases() {
+        let data = |tile: &str, kind: &str| format!("
+atlas:
+  - path: \"{0}.png\"
+textures:
+  - name: \"{0}\"
+    bounds: [0, 0, 16, 16]
+tiles:
+  - texture: \"{0}\"
+autotiles:
+  - tile: \"{0}\"
+    variants:
+      - mask: \"N S\"
+        texture: \"{0}\"
+objects:
+  - kind: \"{1}\"
+    texture: \"{0}\"
+", tile, kind);
+        let load = |tile: &str, kind: &str| testing::data_tile_set(&data(tile, kind), tile).unwrap();
+
+        let mut tiles = load("grass", "city");
+        tiles.append(load("water", "port")).unwrap();
+
+        assert_eq!(tiles.atlas_paths().len(), 2);
+        for (name, kind, atlas) in &[("grass", "city", 0), ("water", "port", 1)] {
+            let tile = tiles.tile(tiles.find(name).unwrap());
+            assert_eq!(tile.atlas, *atlas);
+            assert_eq!(tile.autotile.as_ref().unwrap().variants[0].atlas, *atlas);
+            assert_eq!(tiles.object_kind(kind).unwrap().atlas, *atlas);
+        }
+        assert!(tiles.append(load("water", "ship")).is_err());
+    }
+
+    #[test]
+    fn occupied_tiles_block_movement() {
+        let mut map = Map::with_tile_set(tile_set());
+        let grass = map.tile_set().find("grass").unwrap();
+        let mut ground = Layer::new(5, 2);
+        for coord in ground.coords().collect::<Vec<_>>() {
+            ground.try_set(coord, grass).unwrap();
+        }
+        map.layers.push(ground);
+
+        let occupied = vec![(1, 0), (1, 1), (0, 0)].into_iter().collect::<HashSet<_>>();
+        let reachable = map.reachable((0, 0), 10, "default", &occupied, &PathOptions::new());
+        assert_eq!(reachable.cost((0, 0)), Some(0));
+        assert_eq!(reachable.tiles.len(), 2);
+        assert!(reachable.contains((0, 1)));
+
+        let reachable = map.reachable((0, 0), 2, "default", &HashSet::new(), &PathOptions::new());
+        assert_eq!(reachable.cost((2, 0)), Some(2));
+        assert_eq!(reachable.cost((1, 1)), Some(2));
+        assert!(!reachable.contains((2, 1)));
+    }
+
+    #[test]
+    fn starts_must_be_on_the_map() {
+        let mut map = sample_map();
+        map.info.starts.push((5, 0));
+
+        let res = Resource::from_string(&map.to_resource().to_string()).unwrap();
+        let error = Map::from_resource(&res.node("test"), tile_set()).err().unwrap();
+        assert!(error.to_string().contains("start position is outside the 5x4 map"));
+    }
+
+    #[test]
+    fn animations_loop_through_their_frames() {
+        let mut tile = Tile::new_invalid();
+        assert!(std::ptr::eq(tile.texture_at(3.0), &tile.texture));
+
+        tile.frames = vec![(TextureRegion::new_invalid(), 0.5), (TextureRegion::new_invalid(), 1.0), (TextureRegion::new_invalid(), 0.25)];
+        let frame_at = |time: f32| tile.frames.iter().position(|(frame, _)| std::ptr::eq(frame, tile.texture_at(time)));
+
+        assert_eq!(frame_at(0.0), Some(0));
+        assert_eq!(frame_at(0.49), Some(0));
+        assert_eq!(frame_at(0.5), Some(1));
+        assert_eq!(frame_at(1.6), Some(2));
+        assert_eq!(frame_at(1.75), Some(0));
+        assert_eq!(frame_at(1.75 * 3.0 + 0.6), Some(1));
+        assert_eq!(frame_at(-0.1), Some(2));
+    }
+
+    #[test]
+    fn frames_are_loaded_from_the_data() {
+        let data = "
+atlas:
+  - path: \"water.png\"
+textures:
+  - name: \"water_0\"
+    bounds: [0, 0, 16, 16]
+  - name: \"water_1\"
+    bounds: [16, 0, 16, 16]
+tiles:
+  - name: \"water\"
+    texture: \"water_0\"
+    frames:
+      - texture: \"water_0\"
+        duration: 0.5
+      - texture: \"water_1\"
+        duration: 0.25
+";
+        let load = |data: &str| testing::data_tile_set(data, "data");
+
+        let tiles = load(data).unwrap();
+        let water = tiles.tile(tiles.find("water").unwrap());
+        assert_eq!(water.frames.iter().map(|(_, duration)| *duration).collect::<Vec<_>>(), vec![0.5, 0.25]);
+
+        assert!(load(&data.replace("duration: 0.25", "duration: 0.0")).is_err());
+        assert!(load(&data.replace("texture: \"water_1\"", "texture: \"water_2\"")).is_err());
+        assert!(load(&data.replace("duration: 0.25", "")).is_err());
+    }
+
+    fn reordered_tile_set() -> TileSet {
+        testing::tile_set(&["castle", "water", "grass"])
+    }
+
+    fn assert_same_names(a: &Map, b: &Map) {
+        for (la, lb) in a.layers.iter().zip(&b.layers) {
+            for coord in la.coords() {
+                let name = |map: &Map, layer: &Layer| map.tile_set().tile(layer.try_get(coord).unwrap()).name.clone();
+                assert_eq!(name(a, la), name(b, lb));
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_are_saved_by_name() {
+        let map = sample_map();
+        assert_eq!(map.tile_set().names(), vec!["grass", "water", "castle"]);
+
+        let res = Resource::from_string(&map.to_resource().to_string()).unwrap();
+        let loaded = Map::from_resource(&res.node("test"), reordered_tile_set()).unwrap();
+        assert!(loaded.layers[0].try_get((0, 0)) == reordered_tile_set().find("water"));
+        assert_same_names(&map, &loaded);
+        assert_eq!(map.content_hash(), loaded.content_hash());
+
+        let loaded = Map::from_bytes(&map.to_bytes(true).unwrap(), reordered_tile_set()).unwrap();
+        assert_same_names(&map, &loaded);
+    }
+
+    #[test]
+    fn palettes_map_names_to_tiles() {
+        let tiles = tile_set();
+        assert!(tiles.palette(&["castle", "grass"]).unwrap() == vec![TileIndex(0), TileIndex(3), TileIndex(1)]);
+        assert_eq!(tiles.palette(&["grass", "lava"]).err(), Some("lava".to_string()));
+        assert!(tiles.identity_palette() == vec![TileIndex(0), TileIndex(1), TileIndex(2), TileIndex(3)]);
+        assert!(tiles.find("lava").is_none());
+
+        // Maps without a name list index the tile set directly
+        let res = Resource::from_string("layers: [{width: 2, height: 1, data: [3, 2]}]").unwrap();
+        let map = Map::from_resource(&res.node("test"), tile_set()).unwrap();
+        assert!(map.layers[0].try_get((0, 0)) == tiles.find("castle"));
+
+        let res = Resource::from_string("{tiles: [grass, lava], layers: [{width: 2, height: 1, data: [1, 2]}]}").unwrap();
+        assert!(Map::from_resource(&res.node("test"), tile_set()).is_err());
+        let res = Resource::from_string("{tiles: [grass], layers: [{width: 2, height: 1, data: [1, 2]}]}").unwrap();
+        assert!(Map::from_resource(&res.node("test"), tile_set()).is_err());
+    }
+
+    #[test]
+    fn tile_names_are_unique() {
+        let data = "
+atlas:
+  - path: \"grass.png\"
+textures:
+  - name: \"grass\"
+    bounds: [0, 0, 16, 16]
+tiles:
+  - texture: \"grass\"
+  - name: \"meadow\"
+    texture: \"grass\"
+";
+        let load = |data: &str| testing::data_tile_set(data, "data");
+
+        assert_eq!(load(data).unwrap().names(), vec!["grass", "meadow"]);
+        assert!(load(&data.replace("meadow", "grass")).is_err());
+    }
+
+    // Every tile of the layer is different, numbered from 1 in storage order
+    fn numbered(width: u32, height: u32) -> Layer {
+        let mut layer = Layer::new(width, height);
+        for coord in layer.coords().collect::<Vec<_>>() {
+            layer.try_set(coord, TileIndex(1 + (coord.x + coord.y * width as i32) as usize)).unwrap();
+        }
+        layer
+    }
+
+    fn number_at(layer: &Layer, x: i32, y: i32) -> Option<usize> {
+        layer.try_get((x, y)).map(|tile| tile.0)
+    }
+
+    #[test]
+    fn resizing_keeps_the_anchor_in_place() {
+        assert_eq!(Anchor::Center.offset((3, 2), (5, 4)), (1, 1));
+        assert_eq!(Anchor::TopRight.offset((3, 2), (5, 4)), (2, 2));
+        assert_eq!(Anchor::BottomLeft.offset((3, 2), (1, 1)), (0, 0));
+        assert_eq!(Anchor::Top.offset((4, 4), (2, 2)), (-1, -2));
+
+        let mut layer = numbered(3, 2);
+        layer.resize(5, 4, Anchor::Center);
+        assert_eq!((layer.width, layer.height), (5, 4));
+        assert_eq!(number_at(&layer, 1, 1), Some(1));
+        assert_eq!(number_at(&layer, 3, 2), Some(6));
+        assert_eq!(number_at(&layer, 0, 0), Some(0));
+
+        layer.resize(2, 2, Anchor::BottomLeft);
+        assert_eq!(number_at(&layer, 1, 1), Some(1));
+        assert_eq!(number_at(&layer, 0, 0), Some(0));
+    }
+
+    #[test]
+    fn crops_and_shifts_move_tiles() {
+        let mut layer = numbered(4, 3);
+        layer.crop(1, 1, 10, 10);
+        assert_eq!((layer.width, layer.height), (3, 2));
+        assert_eq!(number_at(&layer, 0, 0), Some(6));
+        assert_eq!(number_at(&layer, 2, 1), Some(12));
+
+        let mut layer = numbered(4, 3);
+        layer.shift(1, -1, false);
+        assert_eq!(number_at(&layer, 1, 0), Some(5));
+        assert_eq!(number_at(&layer, 0, 0), Some(0));
+        assert_eq!(number_at(&layer, 3, 2), Some(0));
+
+        let mut layer = numbered(4, 3);
+        layer.shift(1, -1, true);
+        assert_eq!(number_at(&layer, 1, 0), Some(5));
+        assert_eq!(number_at(&layer, 0, 0), Some(8));
+        assert_eq!(number_at(&layer, 0, 2), Some(4));
+        let mut numbers = layer.coords().map(|coord| layer.try_get(coord).unwrap().0).collect::<Vec<_>>();
+        numbers.sort();
+        assert_eq!(numbers, (1..=12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn flips_and_rotations_undo_each_other() {
+        let original = numbered(4, 3);
+        let same = |layer: &Layer| (layer.width, layer.height) == (4, 3) && layer.coords().all(|coord| layer.try_get(coord) == original.try_get(coord));
+
+        let mut layer = original.clone();
+        layer.flip_horizontal();
+        assert_eq!(number_at(&layer, 0, 0), Some(4));
+        assert_eq!(number_at(&layer, 3, 2), Some(9));
+        layer.flip_horizontal();
+        assert!(same(&layer));
+
+        layer.flip_vertical();
+        assert_eq!(number_at(&layer, 0, 0), Some(9));
+        layer.flip_vertical();
+        assert!(same(&layer));
+
+        layer.rotate_clockwise();
+        assert_eq!((layer.width, layer.height), (3, 4));
+        assert_eq!(number_at(&layer, 0, 3), Some(1));
+        assert_eq!(number_at(&layer, 2, 0), Some(12));
+        layer.rotate_counter_clockwise();
+        assert!(same(&layer));
+
+        for _ in 0..4 {
+            layer.rotate_counter_clockwise();
+        }
+        assert!(same(&layer));
+    }
+
+    #[test]
+    fn map_transforms_carry_everything_along() {
+        let mut map = sample_map();
+        let castle = map.tile_set().find("castle").unwrap();
+        map.flip_horizontal().unwrap();
+        assert_eq!(map.info.starts, vec![(2, 0), (0, 3)]);
+        assert!(map.object_at(1, 2).is_some());
+        assert_eq!(map.height_at(0, 3), 2);
+        assert!(map.layers[1].try_get((1, 2)) == Some(castle));
+
+        map.wrap = Wrap::None;
+        map.rotate_clockwise().unwrap();
+        assert_eq!((map.width(), map.height()), (4, 5));
+        assert_eq!(map.info.starts, vec![(0, 2), (3, 4)]);
+        assert!(map.object_at(2, 3).is_some());
+        assert!(map.layers[1].try_get((2, 3)) == Some(castle));
+
+        // A crop that would leave a start behind changes nothing
+        let mut map = sample_map();
+        assert!(map.crop(1, 1, 4, 3).is_err());
+        assert_eq!((map.width(), map.height()), (5, 4));
+        assert_eq!(map.info.starts, vec![(2, 0), (4, 3)]);
+
+        map.info.starts.clear();
+        map.crop(1, 1, 4, 3).unwrap();
+        assert_eq!((map.width(), map.height()), (4, 3));
+        assert!(map.object_at(2, 1).is_some());
+        assert_eq!(map.height_at(3, 2), 2);
+    }
+
+    #[test]
+    fn impossible_transforms_are_errors() {
+        let mut map = sample_map();
+        assert!(map.rotate_clockwise().is_err());
+
+        map.topology = Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset);
+        map.wrap = Wrap::None;
+        assert!(map.flip_horizontal().is_err());
+        assert!(map.rotate_counter_clockwise().is_err());
+        assert!(map.shift(0, 1, false).is_err());
+        assert!(map.resize(5, 5, Anchor::Top).is_err());
+        map.shift(1, 2, true).unwrap();
+        assert_eq!(map.info.starts, vec![(3, 2), (0, 1)]);
+    }
+
+    #[test]
+    fn tile_access_is_bounds_checked() {
+        let mut layer = numbered(3, 2);
+        assert!(layer.contains((2u32, 1u32)));
+        assert!(!layer.contains((3, 0)));
+        assert!(!layer.contains((0, -1)));
+
+        assert_eq!(number_at(&layer, 2, 1), Some(6));
+        assert_eq!(number_at(&layer, -1, 0), None);
+        assert!(layer.try_set((3, 1), TileIndex(1)).is_err());
+        assert!(layer.try_set(Coord::new(1, 1), TileIndex(9)).is_ok());
+        assert_eq!(number_at(&layer, 1, 1), Some(9));
+
+        // The unchecked versions log and fall back to the empty tile
+        assert!(layer.get(5, 5) == TileIndex(0));
+        layer.set(5, 5, TileIndex(7));
+        assert!(layer.coords().all(|coord| layer.try_get(coord) != Some(TileIndex(7))));
+        assert_eq!(layer.coords().count(), 6);
+    }
+
+    #[test]
+    fn wrapping_maps_join_at_the_seam() {
+        let mut map = sample_map();
+        assert_eq!(map.wrap_coord((-1, 2)), Some(Coord::new(4, 2)));
+        assert_eq!(map.wrap_coord((1, 4)), None);
+        assert_eq!(map.distance((0, 1), (4, 1), false), 1);
+
+        map.wrap = Wrap::Both;
+        assert_eq!(map.wrap_coord((1, 4)), Some(Coord::new(1, 0)));
+        assert_eq!(map.distance((0, 0), (4, 3), false), 2);
+
+        map.wrap = Wrap::None;
+        assert_eq!(map.wrap_coord((-1, 2)), None);
+        assert_eq!(map.distance((0, 1), (4, 1), false), 4);
+
+        // A pointy offset hex map needs an even number of rows to wrap top to bottom
+        let mut map = sample_map();
+        map.topology = Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset);
+        map.wrap = Wrap::Both;
+        assert_eq!(yaml_round_trip(&map).wrap, Wrap::Both);
+        assert!(map.resize(5, 5, Anchor::BottomLeft).is_err());
+
+        map.wrap = Wrap::None;
+        map.resize(5, 5, Anchor::BottomLeft).unwrap();
+        map.wrap = Wrap::Both;
+        let res = Resource::from_string(&map.to_resource().to_string()).unwrap();
+        assert!(Map::from_resource(&res.node("test"), tile_set()).is_err());
+    }
+}
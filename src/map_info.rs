@@ -0,0 +1,229 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::collections::HashMap;
+
+use crate::io::{bytes::{ByteReader, ByteWriter}, error::LoadError, hash::ContentHasher, resource::{Node, Resource}};
+
+// info:
+//   name: "Twin Rivers"
+//   description: "Two kingdoms split by a river"
+//   author: "romptroll"
+//   players: 2
+//   starts:
+//     - [3, 4]
+//     - [28, 27]
+//   win_conditions: ["conquest"]
+//
+// Start positions use the same tile coordinates as Layer::get and are listed in player order.
+#[derive(Clone)]
+pub struct MapInfo {
+    pub name: String,
+    pub description: String,
+    pub author: String,
+    pub players: u32,
+    pub starts: Vec<(u32, u32)>,
+    pub win_conditions: Vec<String>,
+}
+
+impl MapInfo {
+    pub fn new() -> MapInfo {
+        MapInfo {
+            name: String::new(),
+            description: String::new(),
+            author: String::new(),
+            players: 0,
+            starts: Vec::new(),
+            win_conditions: Vec::new(),
+        }
+    }
+
+    pub fn from_resource(res: &Node) -> Result<MapInfo, LoadError> {
+        let mut info = MapInfo::new();
+
+        info.name = match res.get_optional("name") {Some(x) => x.as_str()?.to_string(), None => String::new()};
+        info.description = match res.get_optional("description") {Some(x) => x.as_str()?.to_string(), None => String::new()};
+        info.author = match res.get_optional("author") {Some(x) => x.as_str()?.to_string(), None => String::new()};
+
+        if let Some(starts) = res.get_optional("starts") {
+            for start in starts.as_vec()? {
+                info.starts.push((start.get_index(0)?.as_u32()?, start.get_index(1)?.as_u32()?));
+            }
+        }
+
+        info.players = match res.get_optional("players") {Some(x) => x.as_u32()?, None => info.starts.len() as u32};
+
+        if let Some(win_conditions) = res.get_optional("win_conditions") {
+            for win_condition in win_conditions.as_vec()? {
+                info.win_conditions.push(win_condition.as_str()?.to_string());
+            }
+        }
+
+        Ok(info)
+    }
+
+    pub fn to_resource(&self) -> Resource {
+        let mut info = HashMap::new();
+
+        info.insert("name".to_string(), Resource::Str(self.name.clone()));
+        info.insert("description".to_string(), Resource::Str(self.description.clone()));
+        info.insert("author".to_string(), Resource::Str(self.author.clone()));
+        info.insert("players".to_string(), Resource::I64(self.players as i64));
+        info.insert("starts".to_string(), Resource::Vec(self.starts.iter().map(|(x, y)| {
+            Resource::Vec(vec![Resource::I64(*x as i64), Resource::I64(*y as i64)])
+        }).collect()));
+        info.insert("win_conditions".to_string(), Resource::Vec(self.win_conditions.iter().map(|w| Resource::Str(w.clone())).collect()));
+
+        Resource::Map(info)
+    }
+
+    pub fn write_bytes(&self, out: &mut ByteWriter) {
+        out.write_str(&self.name);
+        out.write_str(&self.description);
+        out.write_str(&self.author);
+        out.write_var(self.players as u64);
+        out.write_var(self.starts.len() as u64);
+        for (x, y) in &self.starts {
+            out.write_u32(*x);
+            out.write_u32(*y);
+        }
+        out.write_var(self.win_conditions.len() as u64);
+        for win_condition in &self.win_conditions {
+            out.write_str(win_condition);
+        }
+    }
+
+    pub fn read_bytes(input: &mut ByteReader) -> Result<MapInfo, LoadError> {
+        let mut info = MapInfo::new();
+
+        info.name = input.read_str()?;
+        info.description = input.read_str()?;
+        info.author = input.read_str()?;
+        info.players = input.read_var()? as u32;
+
+        let starts = input.read_var()?;
+        for _ in 0..starts {
+            info.starts.push((input.read_u32()?, input.read_u32()?));
+        }
+
+        let win_conditions = input.read_var()?;
+        for _ in 0..win_conditions {
+            info.win_conditions.push(input.read_str()?);
+        }
+
+        Ok(info)
+    }
+
+    pub fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str(&self.name);
+        hasher.write_str(&self.description);
+        hasher.write_str(&self.author);
+        hasher.write_u32(self.players);
+        hasher.write_u64(self.starts.len() as u64);
+        for (x, y) in &self.starts {
+            hasher.write_u32(*x);
+            hasher.write_u32(*y);
+        }
+        hasher.write_u64(self.win_conditions.len() as u64);
+        for win_condition in &self.win_conditions {
+            hasher.write_str(win_condition);
+        }
+    }
+
+    pub fn start(&self, player: usize) -> Option<(u32, u32)> {
+        self.starts.get(player).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> MapInfo {
+        let mut info = MapInfo::new();
+        info.name = "Twin Rivers".to_string();
+        info.description = "Two kingdoms split by a river".to_string();
+        info.author = "romptroll".to_string();
+        info.players = 2;
+        info.starts = vec![(3, 4), (28, 27)];
+        info.win_conditions = vec!["conquest".to_string()];
+        info
+    }
+
+    fn assert_same(a: &MapInfo, b: &MapInfo) {
+        assert_eq!(a.name, b.name);
+        assert_eq!(a.description, b.description);
+        assert_eq!(a.author, b.author);
+        assert_eq!(a.players, b.players);
+        assert_eq!(a.starts, b.starts);
+        assert_eq!(a.win_conditions, b.win_conditions);
+    }
+
+    #[test]
+    fn yaml_round_trip() {
+        let res = Resource::from_string(&info().to_resource().to_string()).unwrap();
+        assert_same(&MapInfo::from_resource(&res.node("test")).unwrap(), &info());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut out = ByteWriter::new();
+        info().write_bytes(&mut out);
+
+        let mut input = ByteReader::new(&out.data, "test");
+        assert_same(&MapInfo::read_bytes(&mut input).unwrap(), &info());
+        assert!(input.is_empty());
+
+        let mut input = ByteReader::new(&out.data[..out.data.len() - 1], "test");
+        assert!(MapInfo::read_bytes(&mut input).is_err());
+    }
+
+    #[test]
+    fn players_default_to_the_starts() {
+        let res = Resource::from_string("starts: [[0, 0], [5, 5], [0, 5]]").unwrap();
+        let info = MapInfo::from_resource(&res.node("test")).unwrap();
+        assert_eq!(info.players, 3);
+        assert_eq!(info.start(2), Some((0, 5)));
+        assert_eq!(info.start(3), None);
+        assert!(info.name.is_empty() && info.win_conditions.is_empty());
+
+        for broken in &["starts: [[0]]", "starts: [[0, -1]]", "players: many", "win_conditions: conquest"] {
+            let res = Resource::from_string(broken).unwrap();
+            assert!(MapInfo::from_resource(&res.node("test")).is_err());
+        }
+    }
+
+    #[test]
+    fn hash_follows_the_content() {
+        let hash = |info: &MapInfo| {
+            let mut hasher = ContentHasher::new();
+            info.hash_content(&mut hasher);
+            hasher.finish()
+        };
+
+        let mut moved = info();
+        moved.starts.swap(0, 1);
+        assert_eq!(hash(&info()), hash(&info()));
+        assert_ne!(hash(&info()), hash(&moved));
+    }
+}
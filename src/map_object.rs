@@ -0,0 +1,276 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::collections::HashMap;
+
+use engine::renderer::texture::TextureRegion;
+
+use crate::io::{bytes::{ByteReader, ByteWriter}, error::LoadError, hash::ContentHasher, resource::{Node, Resource}};
+
+// objects:
+//   - name: "cities"
+//     objects:
+//       - kind: "city"
+//         x: 4
+//         y: 10
+//         properties:
+//           owner: 0
+//           name: "Northwatch"
+//           garrison: 3
+#[derive(Clone)]
+pub struct MapObject {
+    pub kind: String,
+    pub x: u32,
+    pub y: u32,
+    pub properties: HashMap<String, Resource>,
+}
+
+impl MapObject {
+    pub fn new(kind: &str, x: u32, y: u32) -> MapObject {
+        MapObject {
+            kind: kind.to_string(),
+            x,
+            y,
+            properties: HashMap::new(),
+        }
+    }
+
+    pub fn from_resource(res: &Node) -> Result<MapObject, LoadError> {
+        let mut object = MapObject::new(res.get("kind")?.as_str()?, res.get("x")?.as_u32()?, res.get("y")?.as_u32()?);
+
+        if let Some(properties) = res.get_optional("properties") {
+            for (name, value) in properties.as_map()? {
+                object.properties.insert(name.to_string(), value.resource().clone());
+            }
+        }
+
+        Ok(object)
+    }
+
+    pub fn to_resource(&self) -> Resource {
+        let mut object = HashMap::new();
+
+        object.insert("kind".to_string(), Resource::Str(self.kind.clone()));
+        object.insert("x".to_string(), Resource::I64(self.x as i64));
+        object.insert("y".to_string(), Resource::I64(self.y as i64));
+        if !self.properties.is_empty() {
+            object.insert("properties".to_string(), Resource::Map(self.properties.clone()));
+        }
+
+        Resource::Map(object)
+    }
+
+    pub fn property(&self, name: &str) -> Option<&Resource> {
+        self.properties.get(name)
+    }
+
+    // Adds delta to a whole number property, counting from 0 when it isn't one yet, and keeps it at min or above
+    pub fn step_property(&mut self, name: &str, delta: i64, min: i64) -> i64 {
+        let value = self.property(name).and_then(|value| value.as_i64()).unwrap_or(0);
+        let value = value.saturating_add(delta).max(min);
+        self.properties.insert(name.to_string(), Resource::I64(value));
+        value
+    }
+
+    // Hands the object to the next of the players, after the last one it belongs to nobody (owner -1)
+    pub fn next_owner(&mut self, players: u32) -> i64 {
+        let owner = self.property("owner").and_then(|owner| owner.as_i64()).unwrap_or(-1);
+        let owner = if owner < -1 || owner + 1 >= players as i64 { -1 } else { owner + 1 };
+        self.properties.insert("owner".to_string(), Resource::I64(owner));
+        owner
+    }
+}
+
+#[derive(Clone)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub objects: Vec<MapObject>,
+}
+
+impl ObjectLayer {
+    pub fn new(name: &str) -> ObjectLayer {
+        ObjectLayer {
+            name: name.to_string(),
+            objects: Vec::new(),
+        }
+    }
+
+    pub fn from_resource(res: &Node) -> Result<ObjectLayer, LoadError> {
+        let mut layer = ObjectLayer::new(match res.get_optional("name") {Some(x) => x.as_str()?, None => ""});
+
+        for object in res.get("objects")?.as_vec()? {
+            layer.objects.push(MapObject::from_resource(&object)?);
+        }
+
+        Ok(layer)
+    }
+
+    pub fn to_resource(&self) -> Resource {
+        let mut layer = HashMap::new();
+
+        layer.insert("name".to_string(), Resource::Str(self.name.clone()));
+        layer.insert("objects".to_string(), Resource::Vec(self.objects.iter().map(|object| object.to_resource()).collect()));
+
+        Resource::Map(layer)
+    }
+
+    pub fn object_at(&self, x: u32, y: u32) -> Option<&MapObject> {
+        self.objects.iter().find(|object| object.x == x && object.y == y)
+    }
+
+    pub fn object_at_mut(&mut self, x: u32, y: u32) -> Option<&mut MapObject> {
+        self.objects.iter_mut().find(|object| object.x == x && object.y == y)
+    }
+
+    pub fn remove_at(&mut self, x: u32, y: u32) -> Option<MapObject> {
+        let index = self.objects.iter().position(|object| object.x == x && object.y == y)?;
+        Some(self.objects.remove(index))
+    }
+
+    pub fn of_kind<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a MapObject> + 'a {
+        self.objects.iter().filter(move |object| object.kind == kind)
+    }
+
+    pub fn write_bytes(&self, out: &mut ByteWriter) {
+        out.write_str(&self.name);
+        out.write_var(self.objects.len() as u64);
+
+        for object in &self.objects {
+            out.write_str(&object.kind);
+            out.write_u32(object.x);
+            out.write_u32(object.y);
+
+            // Properties are stored as yaml text, they are few and free form
+            if object.properties.is_empty() {
+                out.write_str("");
+            } else {
+                out.write_str(&Resource::Map(object.properties.clone()).to_string());
+            }
+        }
+    }
+
+    pub fn read_bytes(input: &mut ByteReader) -> Result<ObjectLayer, LoadError> {
+        let mut layer = ObjectLayer::new(&input.read_str()?);
+
+        let count = input.read_var()?;
+        for _ in 0..count {
+            let mut object = MapObject::new(&input.read_str()?, input.read_u32()?, input.read_u32()?);
+
+            let properties = input.read_str()?;
+            if !properties.is_empty() {
+                match Resource::from_string(&properties)? {
+                    Resource::Map(properties) => object.properties = properties,
+                    _ => return Err(input.error("object properties are not a map")),
+                }
+            }
+
+            layer.objects.push(object);
+        }
+
+        Ok(layer)
+    }
+
+    pub fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str(&self.name);
+        hasher.write_u64(self.objects.len() as u64);
+        for object in &self.objects {
+            hasher.write_str(&object.kind);
+            hasher.write_u32(object.x);
+            hasher.write_u32(object.y);
+            hasher.write_map(&object.properties);
+        }
+    }
+}
+
+// Declared in the data yaml, tells the editor which objects can be placed and how to draw them
+// objects:
+//   - kind: "city"
+//     texture: "castle"
+//     properties:
+//       garrison: 1
+#[derive(Clone)]
+pub struct ObjectKind {
+    pub kind: String,
+    pub texture: TextureRegion,
+    pub atlas: usize,
+    pub bounds: (u32, u32, u32, u32),
+    pub properties: HashMap<String, Resource>,
+}
+
+impl ObjectKind {
+    pub fn create(&self, x: u32, y: u32) -> MapObject {
+        let mut object = MapObject::new(&self.kind, x, y);
+        object.properties = self.properties.clone();
+        object
+    }
+
+    // Only what the game plays with, the texture can differ without the game noticing
+    pub fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_str(&self.kind);
+        hasher.write_map(&self.properties);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owners_cycle_through_the_players() {
+        let mut city = MapObject::new("city", 0, 0);
+        let owners: Vec<i64> = (0..4).map(|_| city.next_owner(2)).collect();
+        assert_eq!(owners, vec![0, 1, -1, 0]);
+
+        city.properties.insert("owner".to_string(), Resource::I64(7));
+        assert_eq!(city.next_owner(2), -1);
+        assert_eq!(MapObject::new("city", 0, 0).next_owner(0), -1);
+    }
+
+    #[test]
+    fn stepped_properties_stay_above_min() {
+        let mut city = MapObject::new("city", 0, 0);
+        assert_eq!(city.step_property("garrison", 1, 0), 1);
+        assert_eq!(city.step_property("garrison", 1, 0), 2);
+        assert_eq!(city.step_property("garrison", -5, 0), 0);
+        assert_eq!(city.property("garrison").and_then(|garrison| garrison.as_i64()), Some(0));
+
+        city.properties.insert("garrison".to_string(), Resource::Str("many".to_string()));
+        assert_eq!(city.step_property("garrison", 1, 0), 1);
+    }
+
+    #[test]
+    fn yaml_round_trip() {
+        let mut layer = ObjectLayer::new("cities");
+        let mut city = MapObject::new("city", 4, 10);
+        city.properties.insert("name".to_string(), Resource::Str("Northwatch".to_string()));
+        city.step_property("garrison", 3, 0);
+        layer.objects.push(city);
+
+        let res = Resource::from_string(&layer.to_resource().to_string()).unwrap();
+        let loaded = ObjectLayer::from_resource(&res.node("test")).unwrap();
+        assert_eq!(loaded.name, "cities");
+        let city = loaded.object_at(4, 10).unwrap();
+        assert_eq!(city.property("name").and_then(|name| name.as_str()), Some("Northwatch"));
+        assert_eq!(city.property("garrison").and_then(|garrison| garrison.as_i64()), Some(3));
+    }
+}
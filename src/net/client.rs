@@ -29,7 +29,7 @@ use std::option::Option;
 use engine::core::{error_log, info_log};
 use packet::{Packet, PacketID};
 
-use crate::net::packet;
+use crate::net::{self, packet, server::DATA_PATH};
 
 pub struct Client {
     server: TcpStream,
@@ -104,6 +104,8 @@ pub struct ClientHandler {
     client: Option<Client>,
     pub data_yaml: String,
     pub map_yaml: String,
+    // Whether the map received matches the hash the server sent with it, None until the hash arrives
+    pub map_verified: Option<bool>,
     pub start: bool,
 }
 
@@ -113,10 +115,55 @@ impl ClientHandler {
             client: None,
             data_yaml: String::new(),
             map_yaml: String::new(),
+            map_verified: None,
             start: false,
         }
     }
 
+    // Compares the hash of the map received with the one the server sent, a mismatch means the map got mangled on
+    // the way or the data files differ
+    fn verify_map(&self, data: &[u8]) -> bool {
+        if data.len() != 8 {
+            error_log!("Map hash from the server is {} bytes long, expected 8", data.len());
+            return false;
+        }
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(data);
+        let expected = u64::from_le_bytes(bytes);
+
+        match net::map_hash(&self.data_yaml, DATA_PATH, &self.map_yaml) {
+            Ok(hash) if hash == expected => {
+                info_log!("Map verified, hash {:016x}", hash);
+                true
+            },
+            Ok(hash) => {
+                error_log!("Map does not match the server's, hash {:016x} but the server has {:016x}", hash, expected);
+                false
+            },
+            Err(e) => {
+                error_log!("Unable to load the map from the server: {}", e);
+                false
+            },
+        }
+    }
+
+    // Playing on a map that differs from the server's would desync right away, so only a verified map starts
+    fn start_game(&mut self) {
+        if self.map_verified == Some(true) {
+            self.start = true;
+        } else {
+            error_log!("Not starting the game, the map could not be verified against the server's");
+        }
+    }
+
+    // What to tell the player about the map, None while there is nothing wrong with it
+    pub fn map_status(&self) -> Option<&'static str> {
+        match self.map_verified {
+            Some(false) => Some("map does not match the server's"),
+            _ => None,
+        }
+    }
+
     pub fn send_name(&mut self, name: &str) {
         self.client.as_mut().unwrap().send_data(Packet::new(PacketID::Name, unsafe { name.to_string().as_mut_vec().clone() }));
     }
@@ -133,9 +180,15 @@ impl ClientHandler {
                             }
                             packet::PacketID::Map => {
                                 self.map_yaml = String::from_utf8(packet.data).unwrap();
+                                self.map_verified = None;
                                 //info_log!("Map: {}", self.map_yaml);
                             }
-                            packet::PacketID::Start => {}
+                            packet::PacketID::Hash => {
+                                self.map_verified = Some(self.verify_map(&packet.data));
+                            }
+                            packet::PacketID::Start => {
+                                self.start_game();
+                            }
                             _ => {}
                         }
                     }
@@ -145,4 +198,52 @@ impl ClientHandler {
             _ => {}
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::DATA;
+
+    const MAP: &str = "
+tiles: [grass]
+layers:
+  - width: 2
+    height: 1
+    data: [1, 1]
+";
+
+    fn received(map: &str) -> ClientHandler {
+        let mut client = ClientHandler::new();
+        client.data_yaml = DATA.to_string();
+        client.map_yaml = map.to_string();
+        client
+    }
+
+    #[test]
+    fn tampered_maps_fail_verification() {
+        let hash = net::map_hash(DATA, DATA_PATH, MAP).unwrap().to_le_bytes();
+        assert!(received(MAP).verify_map(&hash));
+
+        assert!(!received(&MAP.replace("[1, 1]", "[1, 0]")).verify_map(&hash));
+        assert!(!received(&MAP.replace("width: 2", "width: 3")).verify_map(&hash));
+        assert!(!received(MAP).verify_map(&hash[..7]));
+    }
+
+    #[test]
+    fn only_verified_maps_start() {
+        let mut client = received(MAP);
+        client.start_game();
+        assert!(!client.start);
+
+        client.map_verified = Some(false);
+        client.start_game();
+        assert!(!client.start);
+        assert!(client.map_status().is_some());
+
+        client.map_verified = Some(true);
+        client.start_game();
+        assert!(client.start);
+        assert!(client.map_status().is_none());
+    }
+}
@@ -23,4 +23,47 @@
 
 pub mod client;
 pub mod server;
-pub mod packet;
\ No newline at end of file
+pub mod packet;
+
+use crate::{io::{error::LoadError, resource::Resource}, map::{Map, TileSet}};
+
+// Map::content_hash of a map sent as yaml, worked out the same way on the server and the clients so they can compare
+// what they ended up with. data_path is the data file's path on the server, maps list their tile sets by it. No
+// images are opened, the hash doesn't depend on them.
+pub fn map_hash(data_yaml: &str, data_path: &str, map_yaml: &str) -> Result<u64, LoadError> {
+    let data = Resource::from_string(data_yaml)?;
+    let tiles = TileSet::from_resource_without_images(&data.node(data_path), data_path)?;
+    let map = Resource::from_string(map_yaml)?;
+    Ok(Map::from_resource(&map.node("<map>"), tiles)?.content_hash())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::DATA;
+
+    const MAP: &str = "
+tiles: [grass, water]
+layers:
+  - width: 3
+    height: 2
+    data: [1, 2, 1, 2, 2, 1]
+";
+
+    #[test]
+    fn hashing_opens_no_images() {
+        let hash = map_hash(DATA, "data.yaml", MAP).unwrap();
+        assert_eq!(map_hash(DATA, "data.yaml", MAP).unwrap(), hash);
+
+        let other = MAP.replace("[1, 2, 1, 2, 2, 1]", "[1, 2, 1, 2, 1, 1]");
+        assert_ne!(map_hash(DATA, "data.yaml", &other).unwrap(), hash);
+    }
+
+    #[test]
+    fn hashing_still_checks_the_data() {
+        let unknown = DATA.replace("texture: \"water\"", "texture: \"lava\"");
+        assert!(map_hash(&unknown, "data.yaml", MAP).is_err());
+
+        let no_atlas = DATA.replace("    bounds: [16, 0, 16, 16]", "    atlas: 1\n    bounds: [16, 0, 16, 16]");
+        assert!(map_hash(&no_atlas, "data.yaml", MAP).is_err());
+    }
+}
@@ -39,6 +39,8 @@ pub enum PacketID {
     Multiple,
     Data,
     Map,
+    // Map::content_hash of the map just sent, u64 little endian
+    Hash,
 }
 
 pub const MULTIPLE_PACKET_HEADER_SIZE: usize = 4; // Multiple id, Num of packets, Current package index, Packet ID
@@ -32,7 +32,9 @@ use std::io::Read;
 use engine::core::{error_log, info_log};
 use packet::{Packet, PacketID};
 
-use crate::{entities::player::Player, io::resource::Resource, net::packet};
+use crate::{entities::player::Player, io::{error::{LoadError, LoadErrorKind}, resource::Resource}, net::{self, packet}};
+
+pub const DATA_PATH: &str = "res/data_jt.yaml";
 
 pub struct Server {
     listener: TcpListener,
@@ -175,11 +177,30 @@ impl ServerManager {
     }
 
     pub fn start(&mut self) {
-        let data = std::fs::read_to_string("res/data_jt.yaml").unwrap();
-        
-        let map = std::fs::read_to_string(Resource::from_string(&data).get("map").unwrap().as_vec().unwrap()[0].get("path").unwrap().as_str().unwrap()).unwrap();
-        
-        self.server.send_data(Packet::new(PacketID::Data, data.bytes().collect()));
-        self.server.send_data(Packet::new(PacketID::Map, map.bytes().collect()));
+        match ServerManager::load_game_data() {
+            Ok((data, map, hash)) => {
+                self.server.send_data(Packet::new(PacketID::Data, data.bytes().collect()));
+                self.server.send_data(Packet::new(PacketID::Map, map.bytes().collect()));
+                self.server.send_data(Packet::new(PacketID::Hash, hash.to_le_bytes().to_vec()));
+            },
+            Err(e) => {
+                error_log!("Unable to start game: {}", e);
+            },
+        }
+    }
+
+    // The data and map yaml as text, and the hash of the map they make up
+    fn load_game_data() -> Result<(String, String, u64), LoadError> {
+        let data_path = DATA_PATH;
+        let data = std::fs::read_to_string(data_path).map_err(|e| LoadError::new(data_path, "", LoadErrorKind::Io(e.to_string())))?;
+
+        let res = Resource::from_string(&data)?;
+        let map_path = res.node(data_path).get("map")?.get_index(0)?.get("path")?.as_str()?.to_string();
+        let map = std::fs::read_to_string(&map_path).map_err(|e| LoadError::new(&map_path, "", LoadErrorKind::Io(e.to_string())))?;
+
+        let hash = net::map_hash(&data, data_path, &map)?;
+        info_log!("Sending map {} with hash {:016x}", map_path, hash);
+
+        Ok((data, map, hash))
     }
 }
\ No newline at end of file
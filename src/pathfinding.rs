@@ -0,0 +1,503 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::{cmp::Ordering, collections::{BinaryHeap, HashMap}};
+
+use crate::grid::{Coord, Topology, Wrap};
+
+#[derive(Clone, Copy)]
+pub struct PathOptions {
+    pub topology: Topology,
+    pub wrap: Wrap,
+    pub diagonal: bool,
+    pub max_cost: Option<u32>,
+}
+
+impl PathOptions {
+    pub fn new() -> PathOptions {
+        PathOptions {
+            topology: Topology::Square,
+            wrap: Wrap::None,
+            diagonal: false,
+            max_cost: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Path {
+    pub tiles: Vec<(u32, u32)>,
+    pub cost: u32,
+}
+
+#[derive(Clone, Copy)]
+pub struct Reach {
+    pub cost: u32,
+    pub previous: Option<(u32, u32)>,
+}
+
+pub struct Reachable {
+    pub tiles: HashMap<(u32, u32), Reach>,
+}
+
+impl Reachable {
+    pub fn contains(&self, tile: (u32, u32)) -> bool {
+        self.tiles.contains_key(&tile)
+    }
+
+    pub fn cost(&self, tile: (u32, u32)) -> Option<u32> {
+        self.tiles.get(&tile).map(|reach| reach.cost)
+    }
+
+    pub fn path(&self, goal: (u32, u32)) -> Option<Path> {
+        let cost = self.cost(goal)?;
+
+        let mut tiles = vec![goal];
+        let mut current = goal;
+        while let Some(previous) = self.tiles[&current].previous {
+            tiles.push(previous);
+            current = previous;
+        }
+        tiles.reverse();
+
+        Some(Path { tiles, cost })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Node {
+    priority: u32,
+    cost: u32,
+    index: usize,
+}
+
+// BinaryHeap is a max heap so the ordering is reversed, ties are broken in favour of the node
+// furthest along the path which keeps A* from fanning out over equally good tiles
+impl Ord for Node {
+    fn cmp(&self, other: &Node) -> Ordering {
+        other.priority.cmp(&self.priority)
+            .then_with(|| self.cost.cmp(&other.cost))
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn heuristic(width: u32, height: u32, options: &PathOptions, x: u32, y: u32, goal: (u32, u32)) -> u32 {
+    options.topology.wrapped_distance((x as i32, y as i32), (goal.0 as i32, goal.1 as i32), options.diagonal, options.wrap, width, height)
+}
+
+// Tiles a single step away that can be entered, with the price of stepping onto them
+pub fn neighbors<F>(width: u32, height: u32, x: u32, y: u32, options: &PathOptions, step_cost: &F) -> Vec<(u32, u32, u32)>
+where F: Fn((u32, u32), (u32, u32)) -> Option<u32> {
+    let mut neighbors = Vec::new();
+
+    for neighbor in options.topology.neighbors_in(Coord::from((x, y)), width, height, options.diagonal, options.wrap.edge()) {
+        let (nx, ny) = (neighbor.x as u32, neighbor.y as u32);
+
+        // Don't cut corners past tiles that can't be entered
+        let diagonal_step = nx != x && ny != y;
+        if options.topology == Topology::Square && diagonal_step && (step_cost((x, y), (nx, y)).is_none() || step_cost((x, y), (x, ny)).is_none()) {
+            continue;
+        }
+
+        if let Some(cost) = step_cost((x, y), (nx, ny)) {
+            neighbors.push((nx, ny, cost));
+        }
+    }
+
+    neighbors
+}
+
+// cost returns the price of stepping from the first tile onto the second or None if that step can't be made,
+// every step costs at least 1 so the distance heuristic never overestimates
+pub fn find_path<F>(width: u32, height: u32, start: (u32, u32), goal: (u32, u32), options: &PathOptions, cost: F) -> Option<Path>
+where F: Fn((u32, u32), (u32, u32)) -> Option<u32> {
+    if !Coord::from(start).inside(width, height) || !Coord::from(goal).inside(width, height) {
+        return None;
+    }
+
+    let step_cost = |from: (u32, u32), to: (u32, u32)| cost(from, to).map(|c| c.max(1));
+
+    // Give up early when no neighbor can step onto the goal
+    let into_goal = options.topology.neighbors_in(Coord::from(goal), width, height, options.diagonal, options.wrap.edge());
+    if start != goal && into_goal.filter_map(|n| n.to_u32()).all(|n| step_cost(n, goal).is_none()) {
+        return None;
+    }
+
+    let size = (width * height) as usize;
+    let mut costs = vec![u32::MAX; size];
+    let mut came_from: Vec<Option<usize>> = vec![None; size];
+    let mut open = BinaryHeap::new();
+
+    let start_index = (start.0 + start.1 * width) as usize;
+    let goal_index = (goal.0 + goal.1 * width) as usize;
+
+    costs[start_index] = 0;
+    open.push(Node { priority: heuristic(width, height, options, start.0, start.1, goal), cost: 0, index: start_index });
+
+    while let Some(node) = open.pop() {
+        if node.index == goal_index {
+            let mut tiles = Vec::new();
+            let mut current = Some(goal_index);
+            while let Some(index) = current {
+                tiles.push((index as u32 % width, index as u32 / width));
+                current = came_from[index];
+            }
+            tiles.reverse();
+
+            return Some(Path { tiles, cost: node.cost });
+        }
+
+        if node.cost > costs[node.index] {
+            continue;
+        }
+
+        let x = node.index as u32 % width;
+        let y = node.index as u32 / width;
+
+        for (nx, ny, step) in neighbors(width, height, x, y, options, &step_cost) {
+            let new_cost = node.cost + step;
+
+            if let Some(max_cost) = options.max_cost {
+                if new_cost > max_cost {
+                    continue;
+                }
+            }
+
+            let index = (nx + ny * width) as usize;
+            if new_cost < costs[index] {
+                costs[index] = new_cost;
+                came_from[index] = Some(node.index);
+                open.push(Node { priority: new_cost + heuristic(width, height, options, nx, ny, goal), cost: new_cost, index });
+            }
+        }
+    }
+
+    None
+}
+
+// Dijkstra flood from start, every tile that can be reached with at most movement_points is returned
+// together with its cost and the tile it was reached from
+pub fn reachable<F>(width: u32, height: u32, start: (u32, u32), movement_points: u32, options: &PathOptions, cost: F) -> Reachable
+where F: Fn((u32, u32), (u32, u32)) -> Option<u32> {
+    let mut reachable = Reachable { tiles: HashMap::new() };
+
+    if !Coord::from(start).inside(width, height) {
+        return reachable;
+    }
+
+    let budget = match options.max_cost {
+        Some(max_cost) => max_cost.min(movement_points),
+        None => movement_points,
+    };
+
+    let step_cost = |from: (u32, u32), to: (u32, u32)| cost(from, to).map(|c| c.max(1));
+
+    let mut open = BinaryHeap::new();
+
+    reachable.tiles.insert(start, Reach { cost: 0, previous: None });
+    open.push(Node { priority: 0, cost: 0, index: (start.0 + start.1 * width) as usize });
+
+    while let Some(node) = open.pop() {
+        let x = node.index as u32 % width;
+        let y = node.index as u32 / width;
+
+        if node.cost > reachable.tiles[&(x, y)].cost {
+            continue;
+        }
+
+        for (nx, ny, step) in neighbors(width, height, x, y, options, &step_cost) {
+            let new_cost = node.cost + step;
+            if new_cost > budget {
+                continue;
+            }
+
+            let better = match reachable.tiles.get(&(nx, ny)) {
+                Some(reach) => new_cost < reach.cost,
+                None => true,
+            };
+
+            if better {
+                reachable.tiles.insert((nx, ny), Reach { cost: new_cost, previous: Some((x, y)) });
+                open.push(Node { priority: new_cost, cost: new_cost, index: (nx + ny * width) as usize });
+            }
+        }
+    }
+
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::resource::Resource, terrain::Terrain};
+
+    // Rows are listed bottom first: '.' costs 1, '~' costs 5 and '#' can't be entered
+    struct Grid {
+        width: u32,
+        height: u32,
+        tiles: Vec<char>,
+    }
+
+    impl Grid {
+        fn new(rows: &[&str]) -> Grid {
+            Grid {
+                width: rows[0].len() as u32,
+                height: rows.len() as u32,
+                tiles: rows.iter().flat_map(|row| row.chars()).collect(),
+            }
+        }
+
+        fn cost(&self, tile: (u32, u32)) -> Option<u32> {
+            match self.tiles[(tile.0 + tile.1 * self.width) as usize] {
+                '.' => Some(1),
+                '~' => Some(5),
+                _ => None,
+            }
+        }
+
+        fn find_path(&self, start: (u32, u32), goal: (u32, u32), options: &PathOptions) -> Option<Path> {
+            find_path(self.width, self.height, start, goal, options, |_, to| self.cost(to))
+        }
+    }
+
+    fn assert_connected(path: &Path, diagonal: bool) {
+        for step in path.tiles.windows(2) {
+            let distance = Topology::Square.distance((step[0].0 as i32, step[0].1 as i32), (step[1].0 as i32, step[1].1 as i32), diagonal);
+            assert_eq!(distance, 1);
+        }
+    }
+
+    #[test]
+    fn shortest_path_cost() {
+        let grid = Grid::new(&[
+            ".....",
+            ".....",
+            ".....",
+        ]);
+
+        let path = grid.find_path((0, 0), (4, 2), &PathOptions::new()).unwrap();
+        assert_eq!(path.cost, 6);
+        assert_eq!(path.tiles.len(), 7);
+        assert_eq!(path.tiles.first(), Some(&(0, 0)));
+        assert_eq!(path.tiles.last(), Some(&(4, 2)));
+        assert_connected(&path, false);
+
+        let path = grid.find_path((0, 0), (4, 2), &PathOptions { diagonal: true, ..PathOptions::new() }).unwrap();
+        assert_eq!(path.cost, 4);
+        assert_connected(&path, true);
+
+        let path = grid.find_path((2, 1), (2, 1), &PathOptions::new()).unwrap();
+        assert_eq!(path.cost, 0);
+        assert_eq!(path.tiles, vec![(2, 1)]);
+    }
+
+    #[test]
+    fn expensive_tiles_are_avoided() {
+        let grid = Grid::new(&[
+            "..~..",
+            "..~..",
+            ".....",
+        ]);
+
+        // Through the expensive tile is 4 steps costing 8, around it 8 steps costing 8 as well
+        let path = grid.find_path((0, 0), (4, 0), &PathOptions::new()).unwrap();
+        assert_eq!(path.cost, 8);
+        assert_connected(&path, false);
+
+        let grid = Grid::new(&[
+            "..~..",
+            "..~..",
+            "..~..",
+        ]);
+        let path = grid.find_path((0, 0), (4, 0), &PathOptions::new()).unwrap();
+        assert_eq!(path.cost, 8);
+        assert!(path.tiles.contains(&(2, 0)));
+    }
+
+    #[test]
+    fn impassable_tiles_are_walked_around() {
+        let grid = Grid::new(&[
+            "..#..",
+            "..#..",
+            ".....",
+        ]);
+
+        let path = grid.find_path((0, 0), (4, 0), &PathOptions::new()).unwrap();
+        assert_eq!(path.cost, 8);
+        assert!(path.tiles.iter().all(|&tile| grid.cost(tile).is_some()));
+        assert!(path.tiles.contains(&(2, 2)));
+
+        let walled = Grid::new(&[
+            "..#..",
+            "..#..",
+            "..#..",
+        ]);
+        assert!(walled.find_path((0, 0), (4, 0), &PathOptions::new()).is_none());
+        assert!(walled.find_path((0, 0), (2, 1), &PathOptions::new()).is_none());
+        assert!(walled.find_path((0, 0), (5, 0), &PathOptions::new()).is_none());
+    }
+
+    #[test]
+    fn diagonal_steps_dont_cut_corners() {
+        let grid = Grid::new(&[
+            "...",
+            ".#.",
+            "...",
+        ]);
+
+        let options = PathOptions { diagonal: true, ..PathOptions::new() };
+        // Every diagonal step next to the wall would brush past it, so only the ones at the far corners are left
+        let path = grid.find_path((0, 0), (2, 2), &options).unwrap();
+        assert_eq!(path.cost, 4);
+        assert!(!path.tiles.contains(&(1, 1)));
+        assert_connected(&path, true);
+
+        let grid = Grid::new(&[
+            ".#",
+            "#.",
+        ]);
+        assert!(grid.find_path((0, 0), (1, 1), &options).is_none());
+    }
+
+    #[test]
+    fn max_cost_limits_the_search() {
+        let grid = Grid::new(&["....."]);
+
+        let options = PathOptions { max_cost: Some(4), ..PathOptions::new() };
+        assert_eq!(grid.find_path((0, 0), (4, 0), &options).map(|path| path.cost), Some(4));
+
+        let options = PathOptions { max_cost: Some(3), ..PathOptions::new() };
+        assert!(grid.find_path((0, 0), (4, 0), &options).is_none());
+    }
+
+    #[test]
+    fn reachable_tiles_stay_within_movement_points() {
+        let grid = Grid::new(&[
+            ".....",
+            "..#..",
+            ".~...",
+        ]);
+        let reachable = reachable(grid.width, grid.height, (0, 0), 3, &PathOptions::new(), |_, to| grid.cost(to));
+
+        assert_eq!(reachable.cost((0, 0)), Some(0));
+        assert_eq!(reachable.cost((0, 2)), Some(2));
+        assert_eq!(reachable.cost((1, 1)), Some(2));
+        assert_eq!(reachable.cost((3, 0)), Some(3));
+        assert!(!reachable.contains((1, 2)));
+        assert!(!reachable.contains((2, 1)));
+        assert!(!reachable.contains((4, 0)));
+        assert_eq!(reachable.tiles.len(), 7);
+
+        let path = reachable.path((3, 0)).unwrap();
+        assert_eq!(path.cost, 3);
+        assert_eq!(path.tiles, vec![(0, 0), (1, 0), (2, 0), (3, 0)]);
+        assert!(reachable.path((4, 2)).is_none());
+    }
+
+    #[test]
+    fn reachable_costs_match_found_paths() {
+        let grid = Grid::new(&[
+            "..~..",
+            ".#~#.",
+            "..~..",
+        ]);
+        let options = PathOptions { diagonal: true, ..PathOptions::new() };
+        let reachable = reachable(grid.width, grid.height, (0, 0), 20, &options, |_, to| grid.cost(to));
+
+        for (&tile, reach) in &reachable.tiles {
+            assert_eq!(grid.find_path((0, 0), tile, &options).map(|path| path.cost), Some(reach.cost));
+        }
+        assert_eq!(reachable.tiles.len(), 13);
+
+        let limited = PathOptions { max_cost: Some(2), ..options };
+        let reachable = super::reachable(grid.width, grid.height, (0, 0), 20, &limited, |_, to| grid.cost(to));
+        assert!(reachable.tiles.values().all(|reach| reach.cost <= 2));
+        assert!(super::reachable(grid.width, grid.height, (5, 0), 20, &options, |_, to| grid.cost(to)).tiles.is_empty());
+    }
+
+    #[test]
+    fn movement_classes_decide_what_can_be_entered() {
+        let res = Resource::from_string("
+            land:
+              movement_cost: 2
+            water:
+              passable:
+                default: false
+                naval: true
+        ").unwrap();
+        let land = Terrain::from_resource(&res.node("terrain").get("land").unwrap()).unwrap();
+        let water = Terrain::from_resource(&res.node("terrain").get("water").unwrap()).unwrap();
+
+        // A lake in the middle of the bottom row, with a land bridge along the top
+        let terrains = vec![&land, &water, &water, &water, &land, &land, &land, &land, &land, &land];
+        let path = |class: &str| find_path(5, 2, (0, 0), (4, 0), &PathOptions::new(), |_, (x, y)| {
+            let terrain = terrains[(x + y * 5) as usize];
+            if terrain.is_passable(class) {
+                Some(terrain.movement_cost)
+            } else {
+                None
+            }
+        });
+
+        let walking = path("default").unwrap();
+        assert_eq!(walking.cost, 12);
+        assert!(walking.tiles.iter().all(|&(x, y)| y == 1 || x == 0 || x == 4));
+
+        let sailing = path("naval").unwrap();
+        assert_eq!(sailing.cost, 5);
+        assert_eq!(sailing.tiles, vec![(0, 0), (1, 0), (2, 0), (3, 0), (4, 0)]);
+
+        assert!(land.is_passable("naval"));
+        assert!(!water.is_passable("mounted"));
+    }
+
+    #[test]
+    fn paths_cross_the_seam_of_wrapping_maps() {
+        let grid = Grid::new(&[
+            ".#...",
+            ".#...",
+            ".#...",
+        ]);
+
+        assert!(grid.find_path((0, 1), (4, 1), &PathOptions::new()).is_none());
+
+        let options = PathOptions { wrap: Wrap::Horizontal, ..PathOptions::new() };
+        let path = grid.find_path((0, 1), (4, 1), &options).unwrap();
+        assert_eq!(path.tiles, vec![(0, 1), (4, 1)]);
+
+        // Only the rows wrap, so going down past the bottom isn't a shortcut
+        let path = grid.find_path((2, 0), (2, 2), &options).unwrap();
+        assert_eq!(path.cost, 2);
+        assert!(grid.find_path((2, 0), (2, 2), &PathOptions { wrap: Wrap::Both, max_cost: Some(1), ..PathOptions::new() }).is_some());
+
+        let reachable = reachable(grid.width, grid.height, (0, 0), 2, &options, |_, to| grid.cost(to));
+        assert_eq!(reachable.cost((3, 0)), Some(2));
+        assert!(!reachable.contains((2, 0)));
+    }
+}
@@ -0,0 +1,247 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::collections::HashMap;
+
+use crate::{grid::{self, Coord}, image::Image, io::error::{LoadError, LoadErrorKind}, map::{Map, Tile, TileIndex}};
+
+const MAX_SIZE: f32 = 16384.0;
+
+struct Sprite {
+    atlas: usize,
+    bounds: (u32, u32, u32, u32),
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    tint: [f32; 4],
+}
+
+fn load_atlases(map: &Map) -> Result<Vec<Image>, LoadError> {
+    let mut atlases = Vec::new();
+    for path in map.tile_set().atlas_paths() {
+        atlases.push(Image::from_png(path)?);
+    }
+    Ok(atlases)
+}
+
+fn tint_of(tile: &Tile) -> [f32; 4] {
+    [tile.color.r, tile.color.g, tile.color.b, tile.color.a]
+}
+
+// Multiplies a pixel by a tile's color like the game's shader does
+fn tinted(color: [u8; 4], tint: [f32; 4]) -> [u8; 4] {
+    let mut out = color;
+    for (c, tint) in out.iter_mut().zip(&tint) {
+        *c = (*c as f32 * tint.clamp(0.0, 1.0)).round() as u8;
+    }
+    out
+}
+
+// Same placement as Map::render, in world units with y up
+fn sprites(map: &Map) -> Vec<Sprite> {
+    let tile_set = map.tile_set();
+    let topology = map.topology;
+    let (tile_w, tile_h) = topology.tile_size();
+    let step = map.heights.as_ref().map_or(0.0, |heights| heights.step);
+    let mut sprites = Vec::new();
+
+    // Layer by layer, or row by row across the layers on maps with heights
+    let mut rows = Vec::new();
+    if map.heights.is_some() {
+        for y in (0..map.height()).rev() {
+            rows.extend(map.layers.iter().filter(|layer| y < layer.height).map(|layer| (layer, y)));
+        }
+    } else {
+        for layer in &map.layers {
+            rows.extend((0..layer.height).rev().map(|y| (layer, y)));
+        }
+    }
+
+    for (layer, y) in rows {
+        for x in 0..layer.width {
+            let index = layer.get(x, y);
+            if index == unsafe { TileIndex::new(0) } {
+                continue;
+            }
+
+            let tile = tile_set.tile(index);
+            let (atlas, bounds) = match tile.autotile.as_ref().and_then(|autotile| autotile.variant(autotile.mask(&map.layers, x, y, topology, map.wrap.edge()), topology)) {
+                Some(variant) => (variant.atlas, variant.bounds),
+                None => (tile.atlas, tile.bounds),
+            };
+            if bounds.2 == 0 || bounds.3 == 0 {
+                continue;
+            }
+
+            let (tile_x, tile_y) = topology.tile_origin(x as i32, y as i32);
+            sprites.push(Sprite {
+                atlas,
+                bounds,
+                x: tile_x + tile.x_off / bounds.2 as f32 * tile_w,
+                y: tile_y + tile.y_off / bounds.3 as f32 * tile_h,
+                width: tile.x_scl * tile_w,
+                height: tile.y_scl * tile_h + map.height_at(x, y) as f32 * step * tile_h,
+                tint: tint_of(tile),
+            });
+        }
+    }
+
+    for object in map.objects() {
+        if let Some(kind) = tile_set.object_kind(&object.kind) {
+            let (x, y) = topology.tile_origin(object.x as i32, object.y as i32);
+            let lift = map.height_at(object.x, object.y) as f32 * step * tile_h;
+            sprites.push(Sprite { atlas: kind.atlas, bounds: kind.bounds, x, y: y + lift, width: tile_w, height: tile_h, tint: [1.0; 4] });
+        }
+    }
+
+    sprites
+}
+
+// Renders the map the way the game draws it, minus animation. At scale 1 a tile's texture is
+// drawn at its size in the atlas.
+pub fn render(map: &Map, scale: f32) -> Result<Image, LoadError> {
+    let atlases = load_atlases(map)?;
+    let sprites = sprites(map);
+
+    if sprites.is_empty() {
+        return Ok(Image::new(1, 1));
+    }
+
+    // Pixels per world unit, taken from the first tile like the Tiled export does
+    let (tile_w, _) = map.topology.tile_size();
+    let first = map.tile_set().tiles().iter().skip(1).find(|tile| tile.bounds.2 > 0 && tile.x_scl > 0.0);
+    let unit = first.map_or(16.0, |tile| tile.bounds.2 as f32 / tile.x_scl / tile_w) * scale;
+
+    let min_x = sprites.iter().map(|sprite| sprite.x).fold(f32::MAX, f32::min);
+    let min_y = sprites.iter().map(|sprite| sprite.y).fold(f32::MAX, f32::min);
+    let max_x = sprites.iter().map(|sprite| sprite.x + sprite.width).fold(f32::MIN, f32::max);
+    let max_y = sprites.iter().map(|sprite| sprite.y + sprite.height).fold(f32::MIN, f32::max);
+
+    let width = ((max_x - min_x) * unit).ceil();
+    let height = ((max_y - min_y) * unit).ceil();
+    if !(width >= 1.0 && height >= 1.0 && width <= MAX_SIZE && height <= MAX_SIZE) {
+        return Err(LoadError::new("", "", LoadErrorKind::Invalid(format!("a {}x{} image is not possible, try another scale", width, height))));
+    }
+
+    let mut image = Image::new(width as u32, height as u32);
+
+    for sprite in &sprites {
+        let atlas = match atlases.get(sprite.atlas) {
+            Some(atlas) => atlas,
+            None => continue,
+        };
+
+        let x0 = ((sprite.x - min_x) * unit).round() as i64;
+        let x1 = ((sprite.x + sprite.width - min_x) * unit).round() as i64;
+        let y0 = ((max_y - sprite.y - sprite.height) * unit).round() as i64;
+        let y1 = ((max_y - sprite.y) * unit).round() as i64;
+        let (bx, by, bw, bh) = sprite.bounds;
+
+        for py in y0.max(0)..y1.min(image.height as i64) {
+            let v = by + (((py - y0) as f32 + 0.5) / (y1 - y0) as f32 * bh as f32) as u32;
+            for px in x0.max(0)..x1.min(image.width as i64) {
+                let u = bx + (((px - x0) as f32 + 0.5) / (x1 - x0) as f32 * bw as f32) as u32;
+                if u < atlas.width && v < atlas.height {
+                    image.blend(px as u32, py as u32, tinted(atlas.get(u, v), sprite.tint));
+                }
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+// Most common color among the opaque pixels of a texture, colors are grouped by their top 4 bits
+fn dominant_color(atlas: &Image, bounds: (u32, u32, u32, u32)) -> [u8; 4] {
+    let mut buckets: HashMap<(u8, u8, u8), (u32, [u32; 3])> = HashMap::new();
+    let (bx, by, bw, bh) = bounds;
+
+    for y in by..(by + bh).min(atlas.height) {
+        for x in bx..(bx + bw).min(atlas.width) {
+            let color = atlas.get(x, y);
+            if color[3] < 128 {
+                continue;
+            }
+            let bucket = buckets.entry((color[0] >> 4, color[1] >> 4, color[2] >> 4)).or_insert((0, [0; 3]));
+            bucket.0 += 1;
+            for (sum, c) in bucket.1.iter_mut().zip(&color) {
+                *sum += *c as u32;
+            }
+        }
+    }
+
+    match buckets.values().max_by_key(|(count, _)| *count) {
+        Some((count, sum)) => [(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8, 255],
+        None => [0, 0, 0, 0],
+    }
+}
+
+// One pixel per tile in the dominant color of the topmost tile, tinted like the tile, for map previews
+pub fn thumbnail(map: &Map) -> Result<Image, LoadError> {
+    let atlases = load_atlases(map)?;
+    let tile_set = map.tile_set();
+
+    let colors: Vec<[u8; 4]> = tile_set.tiles().iter().map(|tile| match atlases.get(tile.atlas) {
+        Some(atlas) if tile.bounds.2 > 0 => tinted(dominant_color(atlas, tile.bounds), tint_of(tile)),
+        _ => [0, 0, 0, 0],
+    }).collect();
+
+    let (width, height) = (map.width().max(1), map.height().max(1));
+    let mut image = Image::new(width, height);
+
+    for coord in grid::region(Coord::new(0, 0), map.width(), map.height()) {
+        let top = map.layers.iter().rev()
+            .filter_map(|layer| layer.try_get(coord))
+            .find(|tile| *tile != unsafe { TileIndex::new(0) });
+
+        if let Some(tile) = top {
+            image.set(coord.x as u32, height - 1 - coord.y as u32, colors[tile.index()]);
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tints_multiply_pixels() {
+        assert_eq!(tinted([200, 100, 50, 255], [1.0; 4]), [200, 100, 50, 255]);
+        assert_eq!(tinted([200, 100, 50, 255], [0.5, 1.0, 0.0, 1.0]), [100, 100, 0, 255]);
+        assert_eq!(tinted([200, 100, 50, 255], [2.0, -1.0, 1.0, 0.5]), [200, 0, 50, 128]);
+    }
+
+    #[test]
+    fn dominant_color_ignores_transparent_pixels() {
+        let mut atlas = Image::new(4, 1);
+        atlas.set(0, 0, [250, 0, 0, 255]);
+        atlas.set(1, 0, [10, 200, 10, 255]);
+        atlas.set(2, 0, [12, 202, 12, 255]);
+        atlas.set(3, 0, [0, 0, 250, 0]);
+        assert_eq!(dominant_color(&atlas, (0, 0, 4, 1)), [11, 201, 11, 255]);
+        assert_eq!(dominant_color(&atlas, (3, 0, 1, 1)), [0, 0, 0, 0]);
+    }
+}
@@ -21,10 +21,8 @@
  *   SOFTWARE.
  */
 
-use std::{collections::HashMap};
-
-use engine::{core::{input::{Input, Key}, window::{MouseButtonLeft, MouseButtonMiddle, Window}}, game::GameData, gui::{comps::Button, gui::GUI}, renderer::{color::{self, Color}, graphics::Graphics, texture::{Texture, TextureRegion}}, scene::Scene};
-use crate::{camera::{Camera}, io::resource::Resource, map::{Layer, Map, Tile, TileIndex, TileSet}};
+use engine::{core::{error_log, info_log, input::{Input, Key}, window::{MouseButtonLeft, MouseButtonMiddle, Window}}, game::GameData, gui::{comps::Button, gui::GUI}, renderer::{color, graphics::Graphics}, scene::Scene};
+use crate::{analysis::Analysis, camera::{Camera}, generation::{biome::TerrainGenerator, cellular::CellularConfig, network::NetworkGenerator, symmetry::{self, SymmetricGenerator}}, io::{error::LoadError, resource::Resource}, map::{Layer, Map, TileIndex, TileSet}, map_object::ObjectLayer, pathfinding::PathOptions, tiled};
 
 
 pub struct EditorScene {
@@ -32,10 +30,18 @@ pub struct EditorScene {
     gui: GUI,
     gfx: Graphics,
     map: Map,
-    atlas: std::rc::Rc<Texture>,
+    map_path: String,
     tile_selection: Vec<(Button, TileIndex)>,
     tile_selected: TileIndex,
+    object_selection: Vec<(Button, usize)>,
+    object_selected: usize,
+    object_mode: bool,
+    terrain_generator: Option<TerrainGenerator>,
+    cellular: Option<CellularConfig>,
+    symmetric: Option<SymmetricGenerator>,
+    network: Option<NetworkGenerator>,
     camera: Camera,
+    time: f32,
     layer: u8,
 }
 
@@ -46,93 +52,242 @@ impl EditorScene {
             gui: GUI::new(win),
             gfx: Graphics::new(win),
             map: Map::new(),
-            atlas: Texture::from_color(1, 1, 0xFFFFFFFF),
+            map_path: String::new(),
             tile_selection: Vec::new(),
             tile_selected: unsafe { TileIndex::new(0) },
+            object_selection: Vec::new(),
+            object_selected: 0,
+            object_mode: false,
+            terrain_generator: None,
+            cellular: None,
+            symmetric: None,
+            network: None,
             camera: Camera::new(0.0, 0.0, 20.0, 16.0),
+            time: 0.0,
             layer: 0,
         }
     }
 }
 
-impl Scene for EditorScene {
-    fn on_start(&mut self, _gd: &mut GameData) {
-        let data = Resource::from_file("res/data_jt.yaml");
-
-        let atlas_path = data.get("atlas").unwrap().get_index(0).unwrap().get("path").unwrap().as_str().unwrap();
-        let atlas = Texture::from_file(atlas_path);
-
-        let mut textures = HashMap::new();
+impl EditorScene {
+    fn load(&mut self) -> Result<(), LoadError> {
+        let data_path = "res/data_jt.yaml";
+        let data = Resource::from_file(data_path)?;
+        let data = data.node(data_path);
 
-        for texture in data.get("textures").unwrap().as_vec().unwrap() {
-            let texture_name = texture.get("name").unwrap().as_str().unwrap();
-            let bounds = texture.get("bounds").unwrap().as_vec().unwrap();
-            
-            let w = bounds[2].as_i64().unwrap() as u32;
-            let h = bounds[3].as_i64().unwrap() as u32;
-            let x = bounds[0].as_i64().unwrap() as u32;
-            let y = atlas.height() - bounds[1].as_i64().unwrap() as u32 - h;
+        let tiles = TileSet::from_file(data_path)?;
 
-            let texture = TextureRegion::new(x, y, w, h, &atlas);
-            
-            textures.insert(texture_name, texture);
+        let map_path = data.get("map")?.get_index(0)?.get("path")?.as_str()?;
+        let mut map = Map::from_file(map_path, tiles)?;
+        if map.layers.is_empty() {
+            return Err(data.get("map")?.get_index(0)?.error("map has no layers"));
         }
-
-
-        
-        let mut tiles = TileSet::new();
-
-        for tile_d in data.get("tiles").unwrap().as_vec().unwrap() {
-            let r = match tile_d.get("r") {Some(x) => x.as_f64().unwrap() as f32, None => 1.0};
-            let g = match tile_d.get("g") {Some(x) => x.as_f64().unwrap() as f32, None => 1.0};
-            let b = match tile_d.get("b") {Some(x) => x.as_f64().unwrap() as f32, None => 1.0};
-            let texture = tile_d.get("texture").unwrap().as_str().unwrap();
-            let texture = textures.get(texture).unwrap();
-            let mut tile = Tile::new(Color::from((r, g, b, 1.0)), texture.clone());
-            tile.x_off = match tile_d.get("x_off") {Some(x) => x.as_f64().unwrap() as f32, None => 0.0};
-            tile.y_off = match tile_d.get("y_off") {Some(x) => x.as_f64().unwrap() as f32, None => 0.0};
-            tile.x_scl = match tile_d.get("x_scl") {Some(x) => x.as_f64().unwrap() as f32, None => 1.0};
-            tile.y_scl = match tile_d.get("y_scl") {Some(x) => x.as_f64().unwrap() as f32, None => 1.0};
-            tiles.add_tile(tile);
+        if map.layers.len() < 2 {
+            map.layers.push(Layer::new(map.width(), map.height()));
         }
 
-        for i in 0..tiles.len() {
+        let mut tile_selection = Vec::new();
+        for i in 0..map.tile_set().len() {
             let mut button = Button::new();
             button.x = 64.0 * i as f32;
             button.y = 0.0;
             button.width = 64.0;
             button.height = 64.0;
-            self.tile_selection.push((button, unsafe { TileIndex::new(i) } ));
+            tile_selection.push((button, unsafe { TileIndex::new(i) } ));
         }
 
-        let mut map = Map::from_file(&data.get("map").unwrap().as_vec().unwrap()[0].get("path").unwrap().as_str().unwrap(), tiles);
-        map.layers.push(Layer::new(map.layers[0].width, map.layers[0].height));
+        let mut object_selection = Vec::new();
+        for i in 0..map.tile_set().object_kinds().len() {
+            let mut button = Button::new();
+            button.x = 64.0 * i as f32;
+            button.y = 0.0;
+            button.width = 64.0;
+            button.height = 64.0;
+            object_selection.push((button, i));
+        }
 
+        self.terrain_generator = match data.get_optional("biomes") {
+            Some(biomes) => Some(TerrainGenerator::from_resource(&biomes, map.tile_set(), map.width(), map.height())?),
+            None => None,
+        };
+
+        self.cellular = match data.get_optional("cellular") {
+            Some(cellular) => Some(CellularConfig::from_resource(&cellular, map.tile_set(), map.width(), map.height())?),
+            None => None,
+        };
+
+        self.symmetric = match data.get_optional("symmetry") {
+            Some(symmetry) => Some(SymmetricGenerator::from_resource(&symmetry, map.tile_set())?),
+            None => None,
+        };
+
+        self.network = match data.get_optional("network") {
+            Some(network) => Some(NetworkGenerator::from_resource(&network, map.tile_set())?),
+            None => None,
+        };
+
+        self.tile_selection = tile_selection;
+        self.object_selection = object_selection;
+        self.object_selected = 0;
         self.map = map;
-        self.atlas = atlas;
+        self.map_path = map_path.to_string();
+
+        Ok(())
+    }
+}
+
+impl Scene for EditorScene {
+    fn on_start(&mut self, _gd: &mut GameData) {
+        if let Err(e) = self.load() {
+            error_log!("Unable to load editor: {}", e);
+        }
     }
 
     fn on_update(&mut self, gd: &mut GameData) {
+        self.time += gd.delta_time();
+
         let mouse_x = (self.inp.mouse_x() + 1.0) / self.gfx.scaling().0 + self.camera.x;
         let mouse_y = (self.inp.mouse_y() + 1.0) / self.gfx.scaling().1 + self.camera.y;
         let x = self.inp.mouse_x() / 2.0 + 0.5;
         let y = self.inp.mouse_y() / 2.0 + 0.5;
 
-        if self.inp.mouse(MouseButtonLeft) && !self.inp.mouse(MouseButtonMiddle) { 
+        if self.inp.mouse(MouseButtonLeft) && !self.inp.mouse(MouseButtonMiddle) && !self.object_mode { 
             //info_log!("{}:{}", mouse_x, mouse_y);
 
-            if (mouse_x as u32) < self.map.layers[0].width && (mouse_y as u32) < self.map.layers[0].height {
-                self.map.layers[self.layer as usize].set(mouse_x as u32, mouse_y as u32, self.tile_selected);
+            let tile = self.map.wrap_coord(self.map.topology.tile_at(mouse_x, mouse_y));
+            if let (Some(tile), Some(layer)) = (tile, self.map.layers.get_mut(self.layer as usize)) {
+                let _ = layer.try_set(tile, self.tile_selected);
             }
             
         }
 
+        // Clicking a tile in object mode places the selected object there, or removes the object already on it
+        if self.inp.mouse_down(MouseButtonLeft) && !self.inp.mouse(MouseButtonMiddle) && self.object_mode {
+            if let Some(tile) = self.map.wrap_coord(self.map.topology.tile_at(mouse_x, mouse_y)) {
+                let (tile_x, tile_y) = (tile.x as u32, tile.y as u32);
+
+                if self.map.object_layers.is_empty() {
+                    self.map.object_layers.push(ObjectLayer::new("objects"));
+                }
+
+                let removed = self.map.object_layers.iter_mut().rev().find_map(|layer| layer.remove_at(tile_x, tile_y));
+
+                if removed.is_none() {
+                    if let Some(kind) = self.map.tile_set().object_kinds().get(self.object_selected) {
+                        let object = kind.create(tile_x, tile_y);
+                        self.map.object_layers[0].objects.push(object);
+                    }
+                }
+            }
+        }
+
+        // P hands the object under the mouse to the next player, K and J raise and lower its garrison
+        if self.object_mode {
+            let players = self.map.info.players;
+            let next_owner = self.inp.key_down(Key::P);
+            let step = if self.inp.key_down(Key::K) { 1 } else if self.inp.key_down(Key::J) { -1 } else { 0 };
+            let map = &mut self.map;
+            let object = map.wrap_coord(map.topology.tile_at(mouse_x, mouse_y))
+                .and_then(|tile| map.object_at_mut(tile.x as u32, tile.y as u32));
+
+            if let Some(object) = object {
+                if next_owner {
+                    let owner = object.next_owner(players);
+                    info_log!("{} at {}, {} now has owner {}", object.kind, object.x, object.y, owner);
+                }
+                if step != 0 {
+                    let garrison = object.step_property("garrison", step, 0);
+                    info_log!("{} at {}, {} now has garrison {}", object.kind, object.x, object.y, garrison);
+                }
+            }
+        }
+
+        if self.inp.key_down(Key::N) {
+            if let Some(generator) = self.terrain_generator.as_mut() {
+                generator.seed = rand::random();
+                info_log!("Generating terrain with seed {}", generator.seed);
+
+                let mut map = generator.generate(self.map.tile_set());
+                map.info = self.map.info.clone();
+                map.topology = self.map.topology;
+                map.wrap = self.map.wrap;
+
+                // Objects stay where they were unless the new terrain is too small for them
+                let (width, height) = (map.width(), map.height());
+                let mut dropped = 0;
+                map.object_layers = std::mem::take(&mut self.map.object_layers);
+                for object_layer in &mut map.object_layers {
+                    let count = object_layer.objects.len();
+                    object_layer.objects.retain(|object| object.x < width && object.y < height);
+                    dropped += count - object_layer.objects.len();
+                }
+                if dropped > 0 {
+                    info_log!("Removed {} objects outside the new {}x{} map", dropped, width, height);
+                }
+
+                // Old heights belong to the old terrain, so they are never kept
+                match (&self.map.heights, &map.heights) {
+                    (_, Some(_)) => info_log!("Regenerated the heights from the new terrain"),
+                    (Some(_), None) => info_log!("Removed the heights, the generator doesn't make any"),
+                    (None, None) => {},
+                }
+                self.map = map;
+            }
+        }
+
+        // Makes whatever is on the map symmetric, so generate with G or N first
+        if self.inp.key_down(Key::M) {
+            if let Some(generator) = self.symmetric.as_mut() {
+                generator.seed = rand::random();
+                match generator.apply(&mut self.map) {
+                    Ok(()) => info_log!("Made the map {} symmetric, score {}", generator.symmetry, symmetry::score(&self.map, generator.symmetry)),
+                    Err(e) => error_log!("Unable to make the map symmetric: {}", e),
+                }
+            }
+        }
+
+        // Rivers and roads go on their own layer, on top of whatever is on the map
+        if self.inp.key_down(Key::L) {
+            if let Some(generator) = self.network.as_mut() {
+                generator.seed = rand::random();
+                let report = generator.apply(&mut self.map);
+                info_log!("Generated {}", report);
+            }
+        }
+
+        if self.inp.key_down(Key::S) && !self.map_path.is_empty() {
+            if let Err(e) = self.map.to_file(&self.map_path) {
+                error_log!("{}", e);
+            }
+        }
+
+        // Exports next to the map file for editing in Tiled
+        if self.inp.key_down(Key::E) && !self.map_path.is_empty() {
+            let path = std::path::Path::new(&self.map_path).with_extension("tmx");
+            let path = path.to_string_lossy();
+            match tiled::export(&self.map, &path) {
+                Ok(()) => info_log!("Exported map to {}", path),
+                Err(e) => error_log!("Unable to export map: {}", e),
+            }
+        }
+
+        // Logs how the passable land splits up and whether the starts can reach each other
+        if self.inp.key_down(Key::C) {
+            info_log!("{}", Analysis::new(&self.map, "default", &PathOptions::new()));
+        }
+
+        if self.inp.key_down(Key::O) {
+            self.object_mode = !self.object_mode;
+        }
+
         if self.inp.mouse_down(MouseButtonMiddle) {
             self.camera.press(self.inp.mouse_x(), self.inp.mouse_y())
         }
 
         if self.inp.mouse(MouseButtonMiddle) {
-            self.camera.shift(self.inp.mouse_x(), self.inp.mouse_y())
+            self.camera.shift(self.inp.mouse_x(), self.inp.mouse_y());
+            let (horizontal, vertical) = self.map.periods();
+            self.camera.wrap(horizontal, vertical);
         }
 
         if self.inp.mouse_scroll_y() != 0.0 && !self.inp.mouse(MouseButtonMiddle) {
@@ -151,8 +306,21 @@ impl Scene for EditorScene {
             self.layer = 1;
         }
 
+        // Replaces the current layer, adding it first when the map doesn't have that many layers yet
         if self.inp.key_down(Key::G) {
-            unsafe { self.map.layers[0] = Layer::cellular_automata(100, 100, 100, TileIndex::new(0), TileIndex::new(1)); };
+            let layer = match self.cellular.as_mut() {
+                Some(config) => {
+                    config.seed = rand::random();
+                    info_log!("Generating cellular layer with seed {}", config.seed);
+                    config.generate()
+                },
+                None => unsafe { Layer::cellular_automata(100, 100, 100, TileIndex::new(0), TileIndex::new(1)) },
+            };
+            let index = if self.cellular.is_some() { self.layer as usize } else { 0 };
+            while self.map.layers.len() <= index {
+                self.map.layers.push(Layer::new(layer.width, layer.height));
+            }
+            self.map.layers[index] = layer;
         }
 
         self.inp.update();
@@ -170,7 +338,7 @@ impl Scene for EditorScene {
         self.gfx.set_scale(2.0 / scale_x, 2.0 / scale_y);
         self.gfx.set_translation(-1.0 - off_x * 2.0, -1.0 - off_y * 2.0);
 
-        self.map.render(&mut self.gfx);
+        self.map.render_view(&mut self.gfx, self.time, (self.camera.x, self.camera.y, self.camera.w, self.camera.h));
 
         self.gfx.update();
         self.gfx.flush();
@@ -179,16 +347,34 @@ impl Scene for EditorScene {
         self.gui.graphics.set_scale(2.0 / self.gfx.frame_width() as f32, 2.0 / self.gfx.frame_height() as f32);
         self.gui.graphics.set_translation(-1.0, -1.0);
 
-        self.gui.graphics.texture(self.map.tile_set().tile(self.tile_selected).texture.clone());
-        self.gui.graphics.fill_rect(0.0, self.gfx.frame_height() as f32 - 64.0, 64.0, 64.0);
+        if self.object_mode {
+            if let Some(kind) = self.map.tile_set().object_kinds().get(self.object_selected) {
+                self.gui.graphics.texture(kind.texture.clone());
+                self.gui.graphics.fill_rect(0.0, self.gfx.frame_height() as f32 - 64.0, 64.0, 64.0);
+            }
 
-        for (b, i) in &mut self.tile_selection {
-            self.gui.style.foreground_texture = self.map.tile_set().tile(*i).texture.clone();
-            self.gui.style.background_texture = self.map.tile_set().tile(*i).texture.clone();
-            self.gui.button(b);
+            for (b, i) in &mut self.object_selection {
+                let texture = self.map.tile_set().object_kinds()[*i].texture.clone();
+                self.gui.style.foreground_texture = texture.clone();
+                self.gui.style.background_texture = texture;
+                self.gui.button(b);
 
-            if b.pressed {
-                self.tile_selected = *i;
+                if b.pressed {
+                    self.object_selected = *i;
+                }
+            }
+        } else {
+            self.gui.graphics.texture(self.map.tile_set().tile(self.tile_selected).texture.clone());
+            self.gui.graphics.fill_rect(0.0, self.gfx.frame_height() as f32 - 64.0, 64.0, 64.0);
+
+            for (b, i) in &mut self.tile_selection {
+                self.gui.style.foreground_texture = self.map.tile_set().tile(*i).texture.clone();
+                self.gui.style.background_texture = self.map.tile_set().tile(*i).texture.clone();
+                self.gui.button(b);
+
+                if b.pressed {
+                    self.tile_selected = *i;
+                }
             }
         }
 
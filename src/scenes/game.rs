@@ -21,10 +21,8 @@
  *   SOFTWARE.
  */
 
-use std::{collections::HashMap};
-
-use engine::{core::{info_log, input::{Input, Key}, window::{MouseButtonMiddle, Window}}, game::GameData, gui::{comps::Button, gui::GUI}, renderer::{color::{self, Color}, graphics::Graphics, texture::{Texture, TextureRegion}}, scene::Scene};
-use crate::{camera::{Camera}, io::resource::Resource, map::{Map, Tile, TileIndex, TileSet}};
+use engine::{core::{error_log, info_log, input::{Input, Key}, window::{MouseButtonMiddle, Window}}, game::GameData, gui::{comps::Button, gui::GUI}, renderer::{color, graphics::Graphics}, scene::Scene};
+use crate::{camera::{Camera}, io::{error::LoadError, resource::Resource}, map::{Map, TileSet}};
  
 
 pub struct GameScene {
@@ -32,8 +30,8 @@ pub struct GameScene {
     gui: GUI,
     gfx: Graphics,
     map: Map,
-    atlas: std::rc::Rc<Texture>,
     camera: Camera,
+    time: f32,
 }
 
 impl GameScene {
@@ -43,60 +41,38 @@ impl GameScene {
             gui: GUI::new(win),
             gfx: Graphics::new(win),
             map: Map::new(),
-            atlas: Texture::from_color(1, 1, 0xFFFFFFFF),
             camera: Camera::new(0.0, 0.0, 20.0, 16.0),
+            time: 0.0,
         }
     }
 }
 
-impl Scene for GameScene {
-    fn on_start(&mut self, _gd: &mut GameData) {
-        let data = Resource::from_file("res/data_jt.yaml");
-
-        let atlas_path = data.get("atlas").unwrap().get_index(0).unwrap().get("path").unwrap().as_str().unwrap();
-        let atlas = Texture::from_file(atlas_path);
+impl GameScene {
+    fn load(&mut self) -> Result<(), LoadError> {
+        let data_path = "res/data_jt.yaml";
+        let data = Resource::from_file(data_path)?;
+        let data = data.node(data_path);
 
-        let mut textures = HashMap::new();
+        let tiles = TileSet::from_file(data_path)?;
 
-        for texture in data.get("textures").unwrap().as_vec().unwrap() {
-            let texture_name = texture.get("name").unwrap().as_str().unwrap();
-            let bounds = texture.get("bounds").unwrap().as_vec().unwrap();
-            
-            let w = bounds[2].as_i64().unwrap() as u32;
-            let h = bounds[3].as_i64().unwrap() as u32;
-            let x = bounds[0].as_i64().unwrap() as u32;
-            let y = atlas.height() - bounds[1].as_i64().unwrap() as u32 - h;
+        let map = Map::from_file(data.get("map")?.get_index(0)?.get("path")?.as_str()?, tiles)?;
 
-            let texture = TextureRegion::new(x, y, w, h, &atlas);
-            
-            textures.insert(texture_name, texture);
-        }
+        self.map = map;
 
+        Ok(())
+    }
+}
 
-        
-        let mut tiles = TileSet::new();
-
-        for tile_d in data.get("tiles").unwrap().as_vec().unwrap() {
-            let r = match tile_d.get("r") {Some(x) => x.as_f64().unwrap() as f32, None => 1.0};
-            let g = match tile_d.get("g") {Some(x) => x.as_f64().unwrap() as f32, None => 1.0};
-            let b = match tile_d.get("b") {Some(x) => x.as_f64().unwrap() as f32, None => 1.0};
-            let texture = tile_d.get("texture").unwrap().as_str().unwrap();
-            let texture = textures.get(texture).unwrap();
-            let mut tile = Tile::new(Color::from((r, g, b, 1.0)), texture.clone());
-            tile.x_off = match tile_d.get("x_off") {Some(x) => x.as_f64().unwrap() as f32, None => 0.0};
-            tile.y_off = match tile_d.get("y_off") {Some(x) => x.as_f64().unwrap() as f32, None => 0.0};
-            tile.x_scl = match tile_d.get("x_scl") {Some(x) => x.as_f64().unwrap() as f32, None => 1.0};
-            tile.y_scl = match tile_d.get("y_scl") {Some(x) => x.as_f64().unwrap() as f32, None => 1.0};
-            tiles.add_tile(tile);
+impl Scene for GameScene {
+    fn on_start(&mut self, _gd: &mut GameData) {
+        if let Err(e) = self.load() {
+            error_log!("Unable to load game: {}", e);
         }
-
-        let map = Map::from_file(&data.get("map").unwrap().as_vec().unwrap()[0].get("path").unwrap().as_str().unwrap(), tiles);
-
-        self.map = map;
-        self.atlas = atlas;
     }
 
     fn on_update(&mut self, gd: &mut GameData) {
+        self.time += gd.delta_time();
+
         let x = self.inp.mouse_x() / 2.0 + 0.5;
         let y = self.inp.mouse_y() / 2.0 + 0.5;
 
@@ -105,7 +81,9 @@ impl Scene for GameScene {
         }
 
         if self.inp.mouse(MouseButtonMiddle) {
-            self.camera.shift(self.inp.mouse_x(), self.inp.mouse_y())
+            self.camera.shift(self.inp.mouse_x(), self.inp.mouse_y());
+            let (horizontal, vertical) = self.map.periods();
+            self.camera.wrap(horizontal, vertical);
         }
 
         if self.inp.mouse_scroll_y() != 0.0 && !self.inp.mouse(MouseButtonMiddle) {
@@ -129,7 +107,7 @@ impl Scene for GameScene {
         self.gfx.set_scale(2.0 / scale_x, 2.0 / scale_y);
         self.gfx.set_translation(-1.0 - off_x * 2.0, -1.0 - off_y * 2.0);
 
-        self.map.render(&mut self.gfx);
+        self.map.render_view(&mut self.gfx, self.time, (self.camera.x, self.camera.y, self.camera.w, self.camera.h));
 
         self.gfx.update();
         self.gfx.flush();
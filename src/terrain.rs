@@ -0,0 +1,179 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::{collections::HashMap, convert::TryFrom};
+
+use crate::io::{error::LoadError, hash::ContentHasher, resource::{Node, Resource}};
+
+#[derive(Clone)]
+pub struct Terrain {
+    pub movement_cost: u32,
+    pub defense_bonus: i32,
+    pub passable: bool,
+    pub movement_classes: HashMap<String, bool>,
+    pub tags: Vec<String>,
+}
+
+impl Terrain {
+    pub fn new() -> Terrain {
+        Terrain {
+            movement_cost: 1,
+            defense_bonus: 0,
+            passable: true,
+            movement_classes: HashMap::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    // terrain:
+    //   movement_cost: 2
+    //   defense_bonus: 1
+    //   passable:
+    //     default: true
+    //     naval: false
+    //   tags: ["desert"]
+    pub fn from_resource(res: &Node) -> Result<Terrain, LoadError> {
+        let mut terrain = Terrain::new();
+
+        terrain.movement_cost = match res.get_optional("movement_cost") {Some(x) => x.as_u32()?, None => 1};
+        terrain.defense_bonus = match res.get_optional("defense_bonus") {
+            Some(x) => {
+                let bonus = x.as_i64()?;
+                i32::try_from(bonus).map_err(|_| x.error(&format!("defense_bonus {} is outside of the i32 range", bonus)))?
+            },
+            None => 0,
+        };
+
+        match res.get_optional("passable") {
+            Some(passable) => {
+                if let Resource::Map(_) = passable.resource() {
+                    for (class, passable) in passable.as_map()? {
+                        if class == "default" {
+                            terrain.passable = passable.as_bool()?;
+                        } else {
+                            terrain.movement_classes.insert(class.to_string(), passable.as_bool()?);
+                        }
+                    }
+                } else {
+                    terrain.passable = passable.as_bool()?;
+                }
+            },
+            None => {}
+        }
+
+        if let Some(tags) = res.get_optional("tags") {
+            for tag in tags.as_vec()? {
+                terrain.tags.push(tag.as_str()?.to_string());
+            }
+        }
+
+        Ok(terrain)
+    }
+
+    pub fn is_passable(&self, movement_class: &str) -> bool {
+        match self.movement_classes.get(movement_class) {
+            Some(passable) => *passable,
+            None => self.passable,
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    // Tags and movement classes are sets, so they are hashed in sorted order
+    pub fn hash_content(&self, hasher: &mut ContentHasher) {
+        hasher.write_u32(self.movement_cost);
+        hasher.write_i32(self.defense_bonus);
+        hasher.write_bool(self.passable);
+
+        let mut classes: Vec<(&String, &bool)> = self.movement_classes.iter().collect();
+        classes.sort();
+        hasher.write_u64(classes.len() as u64);
+        for (class, passable) in classes {
+            hasher.write_str(class);
+            hasher.write_bool(*passable);
+        }
+
+        let mut tags: Vec<&String> = self.tags.iter().collect();
+        tags.sort();
+        tags.dedup();
+        hasher.write_u64(tags.len() as u64);
+        for tag in tags {
+            hasher.write_str(tag);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terrain(yaml: &str) -> Result<Terrain, LoadError> {
+        let res = Resource::from_string(yaml).unwrap();
+        Terrain::from_resource(&res.node("terrain"))
+    }
+
+    fn hash(terrain: &Terrain) -> u64 {
+        let mut hasher = ContentHasher::new();
+        terrain.hash_content(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn missing_properties_have_defaults() {
+        let plain = terrain("{}").unwrap();
+        assert_eq!((plain.movement_cost, plain.defense_bonus, plain.passable), (1, 0, true));
+        assert!(plain.is_passable("naval"));
+        assert!(!plain.has_tag("forest"));
+    }
+
+    #[test]
+    fn properties_are_read() {
+        let hills = terrain("{movement_cost: 2, defense_bonus: -1, passable: false, tags: [hills, rough]}").unwrap();
+        assert_eq!((hills.movement_cost, hills.defense_bonus), (2, -1));
+        assert!(!hills.is_passable("default"));
+        assert!(hills.has_tag("rough"));
+
+        let water = terrain("passable: {default: false, naval: true, flying: true}").unwrap();
+        assert!(!water.is_passable("default"));
+        assert!(!water.is_passable("mounted"));
+        assert!(water.is_passable("naval") && water.is_passable("flying"));
+    }
+
+    #[test]
+    fn wrong_values_are_errors() {
+        for broken in &["movement_cost: -1", "movement_cost: slow", "passable: maybe", "passable: {naval: 1}", "tags: forest", "defense_bonus: 1.5", "defense_bonus: 4294967297"] {
+            assert!(terrain(broken).is_err(), "{}", broken);
+        }
+    }
+
+    #[test]
+    fn hash_ignores_the_order_of_sets() {
+        let a = terrain("{passable: {naval: true, flying: true}, tags: [hills, rough]}").unwrap();
+        let b = terrain("{passable: {flying: true, naval: true}, tags: [rough, hills, rough]}").unwrap();
+        let c = terrain("{passable: {flying: true, naval: false}, tags: [rough, hills]}").unwrap();
+        assert_eq!(hash(&a), hash(&b));
+        assert_ne!(hash(&a), hash(&c));
+    }
+}
@@ -0,0 +1,92 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use std::{ops::Deref, path::{Path, PathBuf}};
+
+use crate::{io::{error::LoadError, resource::Resource}, map::{Tile, TileSet}};
+
+// Helpers shared by the tests of several modules
+
+// Grass and impassable water, the atlas is never opened
+pub const DATA: &str = "
+atlas:
+  - path: \"grass.png\"
+textures:
+  - name: \"grass\"
+    bounds: [0, 0, 16, 16]
+  - name: \"water\"
+    bounds: [16, 0, 16, 16]
+tiles:
+  - texture: \"grass\"
+  - texture: \"water\"
+    terrain:
+      passable: false
+";
+
+// A directory under the system temp dir that is removed again when dropped, also when the test fails
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("tbs_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn file(&self, name: &str) -> String {
+        self.path.join(name).to_string_lossy().to_string()
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+// Tiles that only have a name, in the given order after the empty tile
+pub fn tile_set(names: &[&str]) -> TileSet {
+    let mut tiles = TileSet::new();
+    for name in names {
+        let mut tile = Tile::new_invalid();
+        tile.name = name.to_string();
+        tiles.add_tile(tile);
+    }
+    tiles
+}
+
+// Loads data yaml without its images, path is where maps would find it
+pub fn data_tile_set(data: &str, path: &str) -> Result<TileSet, LoadError> {
+    let res = Resource::from_string(data).unwrap();
+    TileSet::from_resource_without_images(&res.node(path), path)
+}
@@ -0,0 +1,1435 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+pub mod xml;
+
+use std::{collections::HashMap, path::{Component, Path, PathBuf}};
+
+use crate::{grid::{self, HexCoordinates, HexOrientation, Topology}, io::{encoding::LayerEncoding, error::{LoadError, LoadErrorKind}, resource::{Node, Resource}}, map::{Layer, Map, Tile, TileIndex, TileSet, TileSource}, map_info::MapInfo, map_object::{MapObject, ObjectLayer}};
+use self::xml::Element;
+
+// Tiled keeps flip and rotation flags in the top four bits of a gid. Our tiles can't be flipped, so layers
+// using them are refused, tile objects just lose them.
+const GID_MASK: u32 = 0x0FFF_FFFF;
+
+type Properties = Vec<(String, Resource)>;
+
+struct TiledTileset {
+    first_gid: u32,
+    name: String,
+    image: String,
+    image_width: u32,
+    image_height: u32,
+    tile_width: u32,
+    tile_height: u32,
+    columns: u32,
+    tile_count: u32,
+    margin: u32,
+    spacing: u32,
+    tiles: Vec<(u32, Properties)>,
+}
+
+struct TiledLayer {
+    name: String,
+    data: Vec<u32>,
+}
+
+struct TiledObject {
+    name: String,
+    kind: String,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    gid: u32,
+    properties: Properties,
+}
+
+struct TiledObjectGroup {
+    name: String,
+    objects: Vec<TiledObject>,
+}
+
+// A Tiled map in between the Tiled formats and ours. Tile rows are stored top row first like Tiled does.
+pub struct TiledMap {
+    width: u32,
+    height: u32,
+    tile_width: u32,
+    tile_height: u32,
+    hex_side: u32,
+    topology: Topology,
+    properties: Properties,
+    tilesets: Vec<TiledTileset>,
+    layers: Vec<TiledLayer>,
+    object_groups: Vec<TiledObjectGroup>,
+}
+
+fn invalid(file: &str, msg: &str) -> LoadError {
+    LoadError::new(file, "", LoadErrorKind::Invalid(msg.to_string()))
+}
+
+fn json_gid(res: &Node) -> Result<u32, LoadError> {
+    let gid = res.as_i64()?;
+    if gid < 0 || gid > u32::MAX as i64 {
+        return Err(res.error(&format!("{} is not a tile id", gid)));
+    }
+    Ok(gid as u32)
+}
+
+fn tmx_gid(file: &str, element: &Element) -> Result<u32, LoadError> {
+    match element.attr("gid") {
+        Some(gid) => gid.parse().map_err(|_| element.error(file, &format!("{} is not a tile id", gid))),
+        None => Ok(0),
+    }
+}
+
+// Joins a path relative to dir, folding away . and .. where possible
+fn join(dir: &Path, relative: &str) -> String {
+    let mut out = PathBuf::new();
+    for component in dir.join(relative).components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                if out.file_name().is_some() {
+                    out.pop();
+                } else {
+                    out.push("..");
+                }
+            },
+            other => out.push(other.as_os_str()),
+        }
+    }
+    out.to_string_lossy().replace('\\', "/")
+}
+
+// Path of to as seen from the directory from, both relative to the working directory
+fn relative(from: &Path, to: &str) -> String {
+    let from: Vec<_> = Path::new(&join(from, "")).components().map(|c| c.as_os_str().to_owned()).collect();
+    let to_path = join(Path::new(""), to);
+    let to: Vec<_> = Path::new(&to_path).components().map(|c| c.as_os_str().to_owned()).collect();
+
+    let common = from.iter().zip(to.iter()).take_while(|(a, b)| a == b).count();
+    let mut out = PathBuf::new();
+    for _ in common..from.len() {
+        out.push("..");
+    }
+    for component in &to[common..] {
+        out.push(component);
+    }
+    out.to_string_lossy().replace('\\', "/")
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        let value = match c {
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' => 62,
+            '/' => 63,
+            '=' => break,
+            _ => return None,
+        };
+        buffer = buffer << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(out)
+}
+
+fn decode_gids(text: &str, encoding: &str, compression: &str) -> Result<Vec<u32>, String> {
+    if !compression.is_empty() {
+        return Err(format!("{} compressed layer data is not supported, save the map with CSV or uncompressed base64", compression));
+    }
+
+    match encoding {
+        "csv" => text.split(',').map(|gid| gid.trim().parse::<u32>().map_err(|_| format!("invalid tile \"{}\"", gid.trim()))).collect(),
+        "base64" => {
+            let bytes = base64_decode(text).ok_or("invalid base64 data")?;
+            if bytes.len() % 4 != 0 {
+                return Err("base64 data is not a whole number of tiles".to_string());
+            }
+            Ok(bytes.chunks(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+        },
+        other => Err(format!("unknown layer encoding \"{}\"", other)),
+    }
+}
+
+fn property_type(value: &Resource) -> &'static str {
+    match value {
+        Resource::Bool(_) => "bool",
+        Resource::I64(_) => "int",
+        Resource::F64(_) => "float",
+        _ => "string",
+    }
+}
+
+fn property_string(value: &Resource) -> String {
+    match value {
+        Resource::Bool(b) => b.to_string(),
+        Resource::I64(i) => i.to_string(),
+        Resource::F64(f) => f.to_string(),
+        Resource::Str(s) => s.clone(),
+        Resource::None => String::new(),
+        other => other.to_string().trim_start_matches("---").trim().to_string(),
+    }
+}
+
+fn property<'a>(properties: &'a Properties, name: &str) -> Option<&'a Resource> {
+    properties.iter().find(|(property, _)| property == name).map(|(_, value)| value)
+}
+
+fn property_str(properties: &Properties, name: &str) -> Option<String> {
+    property(properties, name).map(property_string)
+}
+
+fn json_properties(res: Option<Node>) -> Result<Properties, LoadError> {
+    let mut properties = Vec::new();
+    let res = match res {
+        Some(res) => res,
+        None => return Ok(properties),
+    };
+
+    // Tiled 1.2 and newer write a list, older versions a map of name to value
+    match res.resource() {
+        Resource::Map(_) => {
+            for (name, value) in res.as_map()? {
+                properties.push((name.to_string(), value.resource().clone()));
+            }
+        },
+        _ => {
+            for property_d in res.as_vec()? {
+                let name = property_d.get("name")?.as_str()?.to_string();
+                let value = property_d.get("value")?;
+                let kind = match property_d.get_optional("type") {Some(x) => x.as_str()?.to_string(), None => String::new()};
+                let value = match kind.as_str() {
+                    "float" => Resource::F64(value.as_f64()?),
+                    "int" => Resource::I64(value.as_i64()?),
+                    "bool" => Resource::Bool(value.as_bool()?),
+                    _ => value.resource().clone(),
+                };
+                properties.push((name, value));
+            }
+        },
+    }
+
+    Ok(properties)
+}
+
+fn tmx_properties(file: &str, element: &Element) -> Result<Properties, LoadError> {
+    let mut properties = Vec::new();
+    let properties_e = match element.child("properties") {
+        Some(properties_e) => properties_e,
+        None => return Ok(properties),
+    };
+
+    for property_e in properties_e.children("property") {
+        let name = property_e.attr("name").ok_or_else(|| property_e.error(file, "property without a name"))?;
+        let text = property_e.attr("value").unwrap_or(&property_e.text);
+        let value = match property_e.attr("type").unwrap_or("string") {
+            "int" => Resource::I64(text.parse().map_err(|_| property_e.error(file, &format!("\"{}\" is not an int", text)))?),
+            "float" => Resource::F64(text.parse().map_err(|_| property_e.error(file, &format!("\"{}\" is not a float", text)))?),
+            "bool" => Resource::Bool(text == "true"),
+            _ => Resource::Str(text.to_string()),
+        };
+        properties.push((name.to_string(), value));
+    }
+
+    Ok(properties)
+}
+
+fn json_property_list(properties: &Properties) -> Resource {
+    Resource::Vec(properties.iter().map(|(name, value)| {
+        let mut property = HashMap::new();
+        property.insert("name".to_string(), Resource::Str(name.clone()));
+        property.insert("type".to_string(), Resource::Str(property_type(value).to_string()));
+        property.insert("value".to_string(), match value {
+            Resource::Bool(_) | Resource::I64(_) | Resource::F64(_) => value.clone(),
+            _ => Resource::Str(property_string(value)),
+        });
+        Resource::Map(property)
+    }).collect())
+}
+
+fn tmx_property_list(properties: &Properties) -> Element {
+    let mut properties_e = Element::new("properties");
+    for (name, value) in properties {
+        properties_e.children.push(Element::new("property")
+            .with_attr("name", name)
+            .with_attr("type", property_type(value))
+            .with_attr("value", &property_string(value)));
+    }
+    properties_e
+}
+
+fn write_json(res: &Resource, depth: usize, out: &mut String) {
+    let indent = " ".repeat(depth * 2);
+    match res {
+        Resource::None => out.push_str("null"),
+        Resource::Bool(b) => out.push_str(&b.to_string()),
+        Resource::I64(i) => out.push_str(&i.to_string()),
+        Resource::F64(f) => out.push_str(&if f.is_finite() { f.to_string() } else { "0".to_string() }),
+        Resource::Str(s) => {
+            out.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\t' => out.push_str("\\t"),
+                    c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        },
+        // Lists of plain values such as tile data stay on one line
+        Resource::Vec(vec) if vec.iter().all(|value| match value { Resource::Map(_) | Resource::Vec(_) => false, _ => true }) => {
+            out.push('[');
+            for (i, value) in vec.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_json(value, depth + 1, out);
+            }
+            out.push(']');
+        },
+        Resource::Vec(vec) => {
+            out.push_str("[\n");
+            for (i, value) in vec.iter().enumerate() {
+                out.push_str(&indent);
+                out.push_str("  ");
+                write_json(value, depth + 1, out);
+                out.push_str(if i + 1 < vec.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&indent);
+            out.push(']');
+        },
+        Resource::Map(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push_str("{\n");
+            for (i, key) in keys.iter().enumerate() {
+                out.push_str(&indent);
+                out.push_str("  ");
+                write_json(&Resource::Str(key.to_string()), depth + 1, out);
+                out.push_str(": ");
+                write_json(&map[*key], depth + 1, out);
+                out.push_str(if i + 1 < keys.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&indent);
+            out.push('}');
+        },
+    }
+}
+
+fn json_map(entries: Vec<(&str, Resource)>) -> Resource {
+    Resource::Map(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+impl TiledTileset {
+    fn from_json(res: &Node, first_gid: u32, dir: &Path) -> Result<TiledTileset, LoadError> {
+        if res.get_optional("image").is_none() {
+            return Err(res.error("image collection tile sets are not supported, tiles have to come from one image"));
+        }
+
+        let mut tileset = TiledTileset {
+            first_gid,
+            name: res.get("name")?.as_str()?.to_string(),
+            image: join(dir, res.get("image")?.as_str()?),
+            image_width: res.get("imagewidth")?.as_u32()?,
+            image_height: res.get("imageheight")?.as_u32()?,
+            tile_width: res.get("tilewidth")?.as_u32()?,
+            tile_height: res.get("tileheight")?.as_u32()?,
+            columns: res.get("columns")?.as_u32()?,
+            tile_count: res.get("tilecount")?.as_u32()?,
+            margin: match res.get_optional("margin") {Some(x) => x.as_u32()?, None => 0},
+            spacing: match res.get_optional("spacing") {Some(x) => x.as_u32()?, None => 0},
+            tiles: Vec::new(),
+        };
+
+        if let Some(tiles) = res.get_optional("tiles") {
+            for tile_d in tiles.as_vec()? {
+                tileset.tiles.push((tile_d.get("id")?.as_u32()?, json_properties(tile_d.get_optional("properties"))?));
+            }
+        }
+
+        Ok(tileset)
+    }
+
+    fn from_tmx(file: &str, element: &Element, first_gid: u32, dir: &Path) -> Result<TiledTileset, LoadError> {
+        let image = element.child("image").ok_or_else(|| element.error(file, "image collection tile sets are not supported, tiles have to come from one image"))?;
+
+        let attr = |element: &Element, name: &str| -> Result<u32, LoadError> {
+            let value = element.attr(name).ok_or_else(|| element.error(file, &format!("missing attribute \"{}\"", name)))?;
+            value.parse().map_err(|_| element.error(file, &format!("attribute \"{}\" is not a number", name)))
+        };
+        let optional = |name: &str| -> Result<u32, LoadError> {
+            if element.attr(name).is_some() { attr(element, name) } else { Ok(0) }
+        };
+
+        let tile_width = attr(element, "tilewidth")?;
+        let image_width = attr(image, "width")?;
+        let margin = optional("margin")?;
+        let spacing = optional("spacing")?;
+        let columns = match element.attr("columns") {
+            Some(_) => attr(element, "columns")?,
+            None => (image_width - margin * 2 + spacing) / (tile_width + spacing),
+        };
+
+        let mut tileset = TiledTileset {
+            first_gid,
+            name: element.attr("name").unwrap_or("tileset").to_string(),
+            image: join(dir, image.attr("source").ok_or_else(|| image.error(file, "missing attribute \"source\""))?),
+            image_width,
+            image_height: attr(image, "height")?,
+            tile_width,
+            tile_height: attr(element, "tileheight")?,
+            columns,
+            tile_count: attr(element, "tilecount")?,
+            margin,
+            spacing,
+            tiles: Vec::new(),
+        };
+
+        for tile_e in element.children("tile") {
+            tileset.tiles.push((attr(tile_e, "id")?, tmx_properties(file, tile_e)?));
+        }
+
+        Ok(tileset)
+    }
+
+    fn load(path: &str, first_gid: u32) -> Result<TiledTileset, LoadError> {
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        if path.ends_with(".tsx") {
+            TiledTileset::from_tmx(path, &Element::from_file(path)?, first_gid, dir)
+        } else {
+            let res = Resource::from_file(path)?;
+            TiledTileset::from_json(&res.node(path), first_gid, dir)
+        }
+    }
+
+    fn bounds(&self, id: u32) -> (u32, u32, u32, u32) {
+        let x = self.margin + (id % self.columns) * (self.tile_width + self.spacing);
+        let y = self.margin + (id / self.columns) * (self.tile_height + self.spacing);
+        (x, y, self.tile_width, self.tile_height)
+    }
+
+    fn properties(&self, id: u32) -> Option<&Properties> {
+        self.tiles.iter().find(|(tile, _)| *tile == id).map(|(_, properties)| properties)
+    }
+
+    fn to_json(&self, dir: &Path) -> Resource {
+        let tiles = self.tiles.iter().map(|(id, properties)| json_map(vec![
+            ("id", Resource::I64(*id as i64)),
+            ("properties", json_property_list(properties)),
+        ])).collect();
+
+        json_map(vec![
+            ("firstgid", Resource::I64(self.first_gid as i64)),
+            ("name", Resource::Str(self.name.clone())),
+            ("image", Resource::Str(relative(dir, &self.image))),
+            ("imagewidth", Resource::I64(self.image_width as i64)),
+            ("imageheight", Resource::I64(self.image_height as i64)),
+            ("tilewidth", Resource::I64(self.tile_width as i64)),
+            ("tileheight", Resource::I64(self.tile_height as i64)),
+            ("columns", Resource::I64(self.columns as i64)),
+            ("tilecount", Resource::I64(self.tile_count as i64)),
+            ("margin", Resource::I64(self.margin as i64)),
+            ("spacing", Resource::I64(self.spacing as i64)),
+            ("tiles", Resource::Vec(tiles)),
+        ])
+    }
+
+    fn to_tmx(&self, dir: &Path) -> Element {
+        let mut element = Element::new("tileset")
+            .with_attr("firstgid", &self.first_gid.to_string())
+            .with_attr("name", &self.name)
+            .with_attr("tilewidth", &self.tile_width.to_string())
+            .with_attr("tileheight", &self.tile_height.to_string())
+            .with_attr("spacing", &self.spacing.to_string())
+            .with_attr("margin", &self.margin.to_string())
+            .with_attr("tilecount", &self.tile_count.to_string())
+            .with_attr("columns", &self.columns.to_string());
+
+        element.children.push(Element::new("image")
+            .with_attr("source", &relative(dir, &self.image))
+            .with_attr("width", &self.image_width.to_string())
+            .with_attr("height", &self.image_height.to_string()));
+
+        for (id, properties) in &self.tiles {
+            let mut tile_e = Element::new("tile").with_attr("id", &id.to_string());
+            tile_e.children.push(tmx_property_list(properties));
+            element.children.push(tile_e);
+        }
+
+        element
+    }
+}
+
+impl TiledObject {
+    fn from_json(res: &Node) -> Result<TiledObject, LoadError> {
+        let string = |name: &str| -> Result<String, LoadError> {
+            match res.get_optional(name) {Some(x) => Ok(x.as_str()?.to_string()), None => Ok(String::new())}
+        };
+        let number = |name: &str| -> Result<f64, LoadError> {
+            match res.get_optional(name) {Some(x) => x.as_f64(), None => Ok(0.0)}
+        };
+
+        Ok(TiledObject {
+            name: string("name")?,
+            kind: if res.get_optional("class").is_some() { string("class")? } else { string("type")? },
+            x: number("x")?,
+            y: number("y")?,
+            width: number("width")?,
+            height: number("height")?,
+            gid: match res.get_optional("gid") {Some(x) => json_gid(&x)? & GID_MASK, None => 0},
+            properties: json_properties(res.get_optional("properties"))?,
+        })
+    }
+
+    fn from_tmx(file: &str, element: &Element) -> Result<TiledObject, LoadError> {
+        let number = |name: &str| -> Result<f64, LoadError> {
+            match element.attr(name) {
+                Some(value) => value.parse().map_err(|_| element.error(file, &format!("attribute \"{}\" is not a number", name))),
+                None => Ok(0.0),
+            }
+        };
+
+        Ok(TiledObject {
+            name: element.attr("name").unwrap_or("").to_string(),
+            kind: element.attr("class").or(element.attr("type")).unwrap_or("").to_string(),
+            x: number("x")?,
+            y: number("y")?,
+            width: number("width")?,
+            height: number("height")?,
+            gid: tmx_gid(file, element)? & GID_MASK,
+            properties: tmx_properties(file, element)?,
+        })
+    }
+
+    // Tile objects are anchored at their bottom left corner, everything else at the top left
+    fn center(&self) -> (f64, f64) {
+        if self.gid != 0 {
+            (self.x + self.width / 2.0, self.y - self.height / 2.0)
+        } else {
+            (self.x + self.width / 2.0, self.y + self.height / 2.0)
+        }
+    }
+}
+
+impl TiledMap {
+    pub fn from_file(path: &str) -> Result<TiledMap, LoadError> {
+        if path.ends_with(".tmx") {
+            TiledMap::from_tmx(path, &Element::from_file(path)?)
+        } else {
+            let res = Resource::from_file(path)?;
+            TiledMap::from_json(path, &res.node(path))
+        }
+    }
+
+    fn topology(orientation: &str, stagger_axis: &str, stagger_index: &str, height: u32) -> Result<Topology, String> {
+        let topology = match (orientation, stagger_axis) {
+            ("orthogonal", _) => Topology::Square,
+            ("hexagonal", "y") => Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset),
+            ("hexagonal", _) => Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset),
+            (other, _) => return Err(format!("{} maps are not supported, only orthogonal and hexagonal", other)),
+        };
+
+        if topology.is_hex() && stagger_index != TiledMap::stagger_index(topology, height) {
+            return Err(format!("hex maps with stagger index \"{}\" are not supported at this height, use \"{}\"", stagger_index, TiledMap::stagger_index(topology, height)));
+        }
+
+        Ok(topology)
+    }
+
+    // Which rows or columns Tiled has to shift to match our odd-r and odd-q layouts. Tiled counts rows
+    // from the top while we count from the bottom, so for pointy maps it depends on the height.
+    fn stagger_index(topology: Topology, height: u32) -> &'static str {
+        match topology {
+            Topology::Hex(HexOrientation::Pointy, _) => if height % 2 == 1 { "odd" } else { "even" },
+            _ => "even",
+        }
+    }
+
+    fn from_json(path: &str, res: &Node) -> Result<TiledMap, LoadError> {
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+
+        if res.get_optional("infinite").map_or(Ok(false), |x| x.as_bool())? {
+            return Err(res.get("infinite")?.error("infinite maps are not supported"));
+        }
+
+        let height = res.get("height")?.as_u32()?;
+        let orientation = res.get("orientation")?;
+        let stagger_axis = match res.get_optional("staggeraxis") {Some(x) => x.as_str()?.to_string(), None => String::new()};
+        let stagger_index = match res.get_optional("staggerindex") {Some(x) => x.as_str()?.to_string(), None => String::new()};
+
+        let mut map = TiledMap {
+            width: res.get("width")?.as_u32()?,
+            height,
+            tile_width: res.get("tilewidth")?.as_u32()?,
+            tile_height: res.get("tileheight")?.as_u32()?,
+            hex_side: match res.get_optional("hexsidelength") {Some(x) => x.as_u32()?, None => 0},
+            topology: TiledMap::topology(orientation.as_str()?, &stagger_axis, &stagger_index, height).map_err(|e| orientation.error(&e))?,
+            properties: json_properties(res.get_optional("properties"))?,
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            object_groups: Vec::new(),
+        };
+
+        for tileset_d in res.get("tilesets")?.as_vec()? {
+            let first_gid = tileset_d.get("firstgid")?.as_u32()?;
+            let tileset = match tileset_d.get_optional("source") {
+                Some(source) => TiledTileset::load(&join(dir, source.as_str()?), first_gid)?,
+                None => TiledTileset::from_json(&tileset_d, first_gid, dir)?,
+            };
+            map.tilesets.push(tileset);
+        }
+
+        map.read_json_layers(&res.get("layers")?)?;
+
+        Ok(map)
+    }
+
+    fn read_json_layers(&mut self, layers: &Node) -> Result<(), LoadError> {
+        for layer_d in layers.as_vec()? {
+            let name = match layer_d.get_optional("name") {Some(x) => x.as_str()?.to_string(), None => String::new()};
+            match layer_d.get("type")?.as_str()? {
+                "tilelayer" => {
+                    let data_d = layer_d.get("data")?;
+                    let data = match data_d.resource() {
+                        Resource::Str(text) => {
+                            let encoding = match layer_d.get_optional("encoding") {Some(x) => x.as_str()?.to_string(), None => "csv".to_string()};
+                            let compression = match layer_d.get_optional("compression") {Some(x) => x.as_str()?.to_string(), None => String::new()};
+                            decode_gids(text, &encoding, &compression).map_err(|e| data_d.error(&e))?
+                        },
+                        _ => data_d.as_vec()?.iter().map(json_gid).collect::<Result<Vec<u32>, LoadError>>()?,
+                    };
+                    self.push_layer(name, data).map_err(|e| data_d.error(&e))?;
+                },
+                "objectgroup" => {
+                    let mut objects = Vec::new();
+                    for object_d in layer_d.get("objects")?.as_vec()? {
+                        objects.push(TiledObject::from_json(&object_d)?);
+                    }
+                    self.object_groups.push(TiledObjectGroup { name, objects });
+                },
+                "group" => self.read_json_layers(&layer_d.get("layers")?)?,
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn from_tmx(path: &str, root: &Element) -> Result<TiledMap, LoadError> {
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+
+        if root.name != "map" {
+            return Err(root.error(path, "not a Tiled map"));
+        }
+        if root.attr("infinite") == Some("1") {
+            return Err(root.error(path, "infinite maps are not supported"));
+        }
+
+        let attr = |name: &str| -> Result<u32, LoadError> {
+            let value = root.attr(name).ok_or_else(|| root.error(path, &format!("missing attribute \"{}\"", name)))?;
+            value.parse().map_err(|_| root.error(path, &format!("attribute \"{}\" is not a number", name)))
+        };
+
+        let height = attr("height")?;
+        let topology = TiledMap::topology(
+            root.attr("orientation").unwrap_or("orthogonal"),
+            root.attr("staggeraxis").unwrap_or(""),
+            root.attr("staggerindex").unwrap_or(""),
+            height,
+        ).map_err(|e| root.error(path, &e))?;
+
+        let mut map = TiledMap {
+            width: attr("width")?,
+            height,
+            tile_width: attr("tilewidth")?,
+            tile_height: attr("tileheight")?,
+            hex_side: if root.attr("hexsidelength").is_some() { attr("hexsidelength")? } else { 0 },
+            topology,
+            properties: tmx_properties(path, root)?,
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            object_groups: Vec::new(),
+        };
+
+        for tileset_e in root.children("tileset") {
+            let first_gid = tileset_e.attr("firstgid").and_then(|gid| gid.parse().ok()).ok_or_else(|| tileset_e.error(path, "missing attribute \"firstgid\""))?;
+            let tileset = match tileset_e.attr("source") {
+                Some(source) => TiledTileset::load(&join(dir, source), first_gid)?,
+                None => TiledTileset::from_tmx(path, tileset_e, first_gid, dir)?,
+            };
+            map.tilesets.push(tileset);
+        }
+
+        map.read_tmx_layers(path, root)?;
+
+        Ok(map)
+    }
+
+    fn read_tmx_layers(&mut self, path: &str, parent: &Element) -> Result<(), LoadError> {
+        for element in &parent.children {
+            let name = element.attr("name").unwrap_or("").to_string();
+            match element.name.as_str() {
+                "layer" => {
+                    let data_e = element.child("data").ok_or_else(|| element.error(path, "layer has no data"))?;
+                    if data_e.child("chunk").is_some() {
+                        return Err(data_e.error(path, "infinite maps are not supported"));
+                    }
+                    let data = match data_e.attr("encoding") {
+                        Some(encoding) => decode_gids(&data_e.text, encoding, data_e.attr("compression").unwrap_or("")).map_err(|e| data_e.error(path, &e))?,
+                        None => data_e.children("tile").map(|tile| tmx_gid(path, tile)).collect::<Result<Vec<u32>, LoadError>>()?,
+                    };
+                    self.push_layer(name, data).map_err(|e| data_e.error(path, &e))?;
+                },
+                "objectgroup" => {
+                    let mut objects = Vec::new();
+                    for object_e in element.children("object") {
+                        objects.push(TiledObject::from_tmx(path, object_e)?);
+                    }
+                    self.object_groups.push(TiledObjectGroup { name, objects });
+                },
+                "group" => self.read_tmx_layers(path, element)?,
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn push_layer(&mut self, name: String, data: Vec<u32>) -> Result<(), String> {
+        let size = grid::tile_count(self.width, self.height)?;
+        if data.len() != size {
+            return Err(format!("expected {} tiles for a {}x{} map but found {}", size, self.width, self.height, data.len()));
+        }
+        if let Some(i) = data.iter().position(|gid| gid & !GID_MASK != 0) {
+            let (x, y) = (i as u32 % self.width, i as u32 / self.width);
+            return Err(format!("tile {} at {}, {} is flipped or rotated, which is not supported", data[i] & GID_MASK, x, y));
+        }
+        self.layers.push(TiledLayer { name, data });
+        Ok(())
+    }
+
+    fn tileset_of(&self, gid: u32) -> Option<&TiledTileset> {
+        self.tilesets.iter().filter(|tileset| tileset.first_gid <= gid).max_by_key(|tileset| tileset.first_gid)
+    }
+
+    fn row_pitch(&self) -> f64 {
+        match self.topology {
+            Topology::Hex(HexOrientation::Pointy, _) => (self.tile_height + self.hex_side.max(self.tile_height / 2)) as f64 / 2.0,
+            _ => self.tile_height as f64,
+        }
+    }
+
+    fn column_pitch(&self) -> f64 {
+        match self.topology {
+            Topology::Hex(HexOrientation::Flat, _) => (self.tile_width + self.hex_side.max(self.tile_width / 2)) as f64 / 2.0,
+            _ => self.tile_width as f64,
+        }
+    }
+
+    // Center of a tile in Tiled's pixels, y pointing down
+    fn tile_to_pixel(&self, x: u32, y: u32) -> (f64, f64) {
+        let (tw, th) = (self.tile_width as f64, self.tile_height as f64);
+        let row = (self.height - 1 - y) as f64;
+        match self.topology {
+            Topology::Hex(HexOrientation::Pointy, _) => (x as f64 * tw + tw / 2.0 + (y & 1) as f64 * tw / 2.0, row * self.row_pitch() + th / 2.0),
+            Topology::Hex(HexOrientation::Flat, _) => (x as f64 * self.column_pitch() + tw / 2.0, row * th + th / 2.0 + (1 - (x & 1)) as f64 * th / 2.0),
+            Topology::Square => (x as f64 * tw + tw / 2.0, row * th + th / 2.0),
+        }
+    }
+
+    fn pixel_to_tile(&self, px: f64, py: f64) -> (i32, i32) {
+        let (tw, th) = (self.tile_width as f64, self.tile_height as f64);
+        let half_sqrt_3 = 3f64.sqrt() / 2.0;
+        let (wx, wy) = match self.topology {
+            Topology::Hex(HexOrientation::Pointy, _) => {
+                let row = (py - th / 2.0) / self.row_pitch();
+                (px / tw, (self.height as f64 - 1.0 - row) * half_sqrt_3 + 0.5)
+            },
+            Topology::Hex(HexOrientation::Flat, _) => {
+                let column = (px - tw / 2.0) / self.column_pitch();
+                (column * half_sqrt_3 + 0.5, self.height as f64 + 0.5 - py / th)
+            },
+            Topology::Square => (px / tw, self.height as f64 - py / th),
+        };
+        self.topology.tile_at(wx as f32, wy as f32)
+    }
+
+    // Builds the data file for the Tiled tile sets and a map using it. Tiles are named after their "name"
+    // property, or the tile set name and tile id.
+    pub fn to_map(&self, file: &str, data_path: &str) -> Result<(Resource, Map), LoadError> {
+        let mut atlases = Vec::new();
+        let mut textures = Vec::new();
+        let mut tiles = Vec::new();
+        let mut tile_set = TileSet::new();
+        let mut gids = HashMap::new();
+
+        for (atlas, tileset) in self.tilesets.iter().enumerate() {
+            if tileset.columns == 0 {
+                return Err(invalid(file, &format!("tile set \"{}\" has no columns", tileset.name)));
+            }
+            atlases.push(json_map(vec![("path", Resource::Str(tileset.image.clone()))]));
+
+            for id in 0..tileset.tile_count {
+                let properties = tileset.properties(id);
+                let mut name = properties.and_then(|properties| property_str(properties, "name")).unwrap_or(format!("{}_{}", tileset.name, id));
+                if tile_set.find(&name).is_some() {
+                    name = format!("{}_{}", name, tileset.first_gid + id);
+                }
+
+                let (x, y, w, h) = tileset.bounds(id);
+                textures.push(json_map(vec![
+                    ("name", Resource::Str(name.clone())),
+                    ("atlas", Resource::I64(atlas as i64)),
+                    ("bounds", Resource::Vec(vec![Resource::I64(x as i64), Resource::I64(y as i64), Resource::I64(w as i64), Resource::I64(h as i64)])),
+                ]));
+
+                let mut tile_d = vec![
+                    ("texture", Resource::Str(name.clone())),
+                    ("x_scl", Resource::F64(w as f64 / self.tile_width as f64)),
+                    ("y_scl", Resource::F64(h as f64 / self.tile_height as f64)),
+                ];
+
+                if let Some(properties) = properties {
+                    let mut terrain = HashMap::new();
+                    for key in &["movement_cost", "defense_bonus", "passable"] {
+                        if let Some(value) = property(properties, key) {
+                            terrain.insert(key.to_string(), value.clone());
+                        }
+                    }
+                    if let Some(tags) = property_str(properties, "tags") {
+                        terrain.insert("tags".to_string(), Resource::Vec(tags.split(',').map(|tag| tag.trim()).filter(|tag| !tag.is_empty()).map(|tag| Resource::Str(tag.to_string())).collect()));
+                    }
+                    if !terrain.is_empty() {
+                        tile_d.push(("terrain", Resource::Map(terrain)));
+                    }
+                }
+                tiles.push(json_map(tile_d));
+
+                let mut tile = Tile::new_invalid();
+                tile.name = name;
+                gids.insert(tileset.first_gid + id, tile_set.add_tile(tile));
+            }
+        }
+
+        tile_set.add_source(TileSource { path: data_path.to_string(), first: 1, len: tile_set.len() - 1 });
+
+        let mut map = Map::with_tile_set(tile_set);
+        map.topology = self.topology;
+
+        for layer in &self.layers {
+            let mut out = Layer::new(self.width, self.height);
+            out.encoding = LayerEncoding::Rows;
+            for (i, gid) in layer.data.iter().enumerate() {
+                if *gid == 0 {
+                    continue;
+                }
+                let tile = match gids.get(gid) {
+                    Some(tile) => *tile,
+                    None => return Err(invalid(file, &format!("layer \"{}\" uses tile {} which is in no tile set", layer.name, gid))),
+                };
+                let (x, row) = (i as u32 % self.width, i as u32 / self.width);
+                out.set(x, self.height - 1 - row, tile);
+            }
+            map.layers.push(out);
+        }
+
+        let mut kinds: Vec<Resource> = Vec::new();
+        let mut kind_names = Vec::new();
+        let mut starts = Vec::new();
+
+        for group in &self.object_groups {
+            let mut layer = ObjectLayer::new(&group.name);
+            for object in &group.objects {
+                let (cx, cy) = object.center();
+                let (x, y) = self.pixel_to_tile(cx, cy);
+                if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+                    return Err(invalid(file, &format!("object \"{}\" at {}, {} is outside the map", object.name, object.x, object.y)));
+                }
+
+                let kind = if !object.kind.is_empty() { object.kind.clone() } else if !object.name.is_empty() { object.name.clone() } else { "object".to_string() };
+
+                if kind == "start" {
+                    let player = property(&object.properties, "player").and_then(|player| match player { Resource::I64(i) => Some(*i), _ => None });
+                    starts.push((player.unwrap_or(starts.len() as i64), (x as u32, y as u32)));
+                    continue;
+                }
+
+                if object.gid != 0 && !kind_names.contains(&kind) {
+                    if let Some(tile) = gids.get(&object.gid) {
+                        kind_names.push(kind.clone());
+                        kinds.push(json_map(vec![
+                            ("kind", Resource::Str(kind.clone())),
+                            ("texture", Resource::Str(map.tile_set().tile(*tile).name.clone())),
+                        ]));
+                    }
+                }
+
+                let mut map_object = MapObject::new(&kind, x as u32, y as u32);
+                for (name, value) in &object.properties {
+                    map_object.properties.insert(name.clone(), value.clone());
+                }
+                layer.objects.push(map_object);
+            }
+            map.object_layers.push(layer);
+        }
+
+        starts.sort_by_key(|(player, _)| *player);
+
+        let mut info = MapInfo::new();
+        info.name = property_str(&self.properties, "name").unwrap_or(Path::new(file).file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_string()));
+        info.description = property_str(&self.properties, "description").unwrap_or_default();
+        info.author = property_str(&self.properties, "author").unwrap_or_default();
+        info.starts = starts.into_iter().map(|(_, start)| start).collect();
+        info.players = match property(&self.properties, "players") {Some(Resource::I64(players)) => *players as u32, _ => info.starts.len() as u32};
+        if let Some(win_conditions) = property_str(&self.properties, "win_conditions") {
+            info.win_conditions = win_conditions.split(',').map(|condition| condition.trim().to_string()).filter(|condition| !condition.is_empty()).collect();
+        }
+        map.info = info;
+
+        let mut data = vec![
+            ("atlas", Resource::Vec(atlases)),
+            ("textures", Resource::Vec(textures)),
+            ("tiles", Resource::Vec(tiles)),
+        ];
+        if !kinds.is_empty() {
+            data.push(("objects", Resource::Vec(kinds)));
+        }
+        let data = json_map(data);
+
+        // The map has to hash the same once it's loaded with the data file, so it gets the tiles the data file
+        // describes instead of the bare ones it was built with
+        let mut imported = Map::with_tile_set(TileSet::from_resource_without_images(&data.node(data_path), data_path)?);
+        imported.topology = map.topology;
+        imported.info = map.info;
+        imported.layers = map.layers;
+        imported.object_layers = map.object_layers;
+
+        Ok((data, imported))
+    }
+
+    // Every atlas becomes a Tiled tile set cut into a grid of its most common tile size, tiles that don't
+    // sit on that grid can't be exported
+    pub fn from_map(map: &Map, file: &str) -> Result<TiledMap, LoadError> {
+        if let Topology::Hex(_, HexCoordinates::Axial) = map.topology {
+            return Err(invalid(file, "Tiled has no axial hex maps, only offset ones can be exported"));
+        }
+
+        let tile_set = map.tile_set();
+        let (width, height) = (map.width(), map.height());
+
+        let first_tile = tile_set.tiles().iter().skip(1).find(|tile| tile.bounds.2 > 0 && tile.x_scl > 0.0 && tile.y_scl > 0.0);
+        let (tile_width, tile_height) = match first_tile {
+            Some(tile) => ((tile.bounds.2 as f32 / tile.x_scl).round() as u32, (tile.bounds.3 as f32 / tile.y_scl).round() as u32),
+            None => (16, 16),
+        };
+
+        let mut tiled = TiledMap {
+            width,
+            height,
+            tile_width,
+            tile_height,
+            hex_side: match map.topology {
+                Topology::Hex(HexOrientation::Pointy, _) => tile_height / 2,
+                Topology::Hex(HexOrientation::Flat, _) => tile_width / 2,
+                Topology::Square => 0,
+            },
+            topology: map.topology,
+            properties: Vec::new(),
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            object_groups: Vec::new(),
+        };
+
+        let mut gids = vec![0u32; tile_set.len()];
+        let mut first_gid = 1;
+
+        for (atlas, path) in tile_set.atlas_paths().iter().enumerate() {
+            let atlas_tiles: Vec<usize> = (1..tile_set.len()).filter(|i| tile_set.tiles()[*i].atlas == atlas).collect();
+            if atlas_tiles.is_empty() {
+                continue;
+            }
+
+            let mut sizes: HashMap<(u32, u32), u32> = HashMap::new();
+            for i in &atlas_tiles {
+                let bounds = tile_set.tiles()[*i].bounds;
+                *sizes.entry((bounds.2, bounds.3)).or_insert(0) += 1;
+            }
+            let (tw, th) = sizes.into_iter().max_by_key(|(size, count)| (*count, *size)).map(|(size, _)| size).unwrap_or((16, 16));
+
+            let (image_width, image_height) = tile_set.atlas_size(atlas).ok_or_else(|| invalid(file, &format!("the size of atlas \"{}\" is not known", path)))?;
+            let columns = image_width / tw.max(1);
+            let rows = image_height / th.max(1);
+            if columns == 0 || rows == 0 {
+                return Err(invalid(file, &format!("atlas \"{}\" is smaller than its {}x{} tiles", path, tw, th)));
+            }
+
+            let mut tileset = TiledTileset {
+                first_gid,
+                name: Path::new(path).file_stem().map_or(format!("atlas_{}", atlas), |stem| stem.to_string_lossy().to_string()),
+                image: path.clone(),
+                image_width,
+                image_height,
+                tile_width: tw,
+                tile_height: th,
+                columns,
+                tile_count: columns * rows,
+                margin: 0,
+                spacing: 0,
+                tiles: Vec::new(),
+            };
+
+            for i in atlas_tiles {
+                let tile = &tile_set.tiles()[i];
+                let (x, y, w, h) = tile.bounds;
+                if (w, h) != (tw, th) || x % tw != 0 || y % th != 0 {
+                    return Err(invalid(file, &format!("tile \"{}\" doesn't fit the {}x{} grid of atlas \"{}\"", tile.name, tw, th, path)));
+                }
+
+                let id = (y / th) * columns + x / tw;
+                gids[i] = first_gid + id;
+
+                if tileset.properties(id).is_none() {
+                    let terrain = &tile.terrain;
+                    let mut properties = vec![
+                        ("name".to_string(), Resource::Str(tile.name.clone())),
+                        ("movement_cost".to_string(), Resource::I64(terrain.movement_cost as i64)),
+                        ("defense_bonus".to_string(), Resource::I64(terrain.defense_bonus as i64)),
+                        ("passable".to_string(), Resource::Bool(terrain.passable)),
+                    ];
+                    if !terrain.tags.is_empty() {
+                        properties.push(("tags".to_string(), Resource::Str(terrain.tags.join(","))));
+                    }
+                    tileset.tiles.push((id, properties));
+                }
+            }
+
+            tileset.tiles.sort_by_key(|(id, _)| *id);
+            first_gid += tileset.tile_count;
+            tiled.tilesets.push(tileset);
+        }
+
+        for (i, layer) in map.layers.iter().enumerate() {
+            let mut data = vec![0; width as usize * height as usize];
+            for y in 0..layer.height.min(height) {
+                for x in 0..layer.width.min(width) {
+                    let tile = layer.get(x, y);
+                    if tile == unsafe { TileIndex::new(0) } {
+                        continue;
+                    }
+                    let gid = gids.get(tile.index()).copied().unwrap_or(0);
+                    if gid == 0 {
+                        return Err(invalid(file, &format!("tile \"{}\" has no atlas to export", tile_set.tile(tile).name)));
+                    }
+                    data[(height - 1 - y) as usize * width as usize + x as usize] = gid;
+                }
+            }
+            tiled.layers.push(TiledLayer { name: format!("layer {}", i), data });
+        }
+
+        let object_at = |kind: &str, x: u32, y: u32, properties: Properties| {
+            let (cx, cy) = tiled.tile_to_pixel(x, y);
+            TiledObject {
+                name: String::new(),
+                kind: kind.to_string(),
+                x: cx - tile_width as f64 / 2.0,
+                y: cy - tile_height as f64 / 2.0,
+                width: tile_width as f64,
+                height: tile_height as f64,
+                gid: 0,
+                properties,
+            }
+        };
+
+        let mut object_groups = Vec::new();
+        for layer in &map.object_layers {
+            let mut objects = Vec::new();
+            for object in &layer.objects {
+                let mut properties: Properties = object.properties.iter().map(|(name, value)| (name.clone(), value.clone())).collect();
+                properties.sort_by(|a, b| a.0.cmp(&b.0));
+                objects.push(object_at(&object.kind, object.x, object.y, properties));
+            }
+            object_groups.push(TiledObjectGroup { name: layer.name.clone(), objects });
+        }
+
+        if !map.info.starts.is_empty() {
+            let objects = map.info.starts.iter().enumerate()
+                .map(|(player, (x, y))| object_at("start", *x, *y, vec![("player".to_string(), Resource::I64(player as i64))]))
+                .collect();
+            object_groups.push(TiledObjectGroup { name: "starts".to_string(), objects });
+        }
+        tiled.object_groups = object_groups;
+
+        let info = &map.info;
+        tiled.properties = vec![
+            ("name".to_string(), Resource::Str(info.name.clone())),
+            ("description".to_string(), Resource::Str(info.description.clone())),
+            ("author".to_string(), Resource::Str(info.author.clone())),
+            ("players".to_string(), Resource::I64(info.players as i64)),
+            ("win_conditions".to_string(), Resource::Str(info.win_conditions.join(","))),
+        ];
+
+        Ok(tiled)
+    }
+
+    fn orientation(&self) -> Vec<(&'static str, String)> {
+        match self.topology {
+            Topology::Square => vec![("orientation", "orthogonal".to_string())],
+            Topology::Hex(orientation, _) => vec![
+                ("orientation", "hexagonal".to_string()),
+                ("hexsidelength", self.hex_side.to_string()),
+                ("staggeraxis", if orientation == HexOrientation::Pointy { "y" } else { "x" }.to_string()),
+                ("staggerindex", TiledMap::stagger_index(self.topology, self.height).to_string()),
+            ],
+        }
+    }
+
+    pub fn to_json(&self, dir: &Path) -> String {
+        let mut layers = Vec::new();
+        let mut id = 1;
+
+        for layer in &self.layers {
+            layers.push(json_map(vec![
+                ("id", Resource::I64(id)),
+                ("type", Resource::Str("tilelayer".to_string())),
+                ("name", Resource::Str(layer.name.clone())),
+                ("x", Resource::I64(0)),
+                ("y", Resource::I64(0)),
+                ("width", Resource::I64(self.width as i64)),
+                ("height", Resource::I64(self.height as i64)),
+                ("opacity", Resource::I64(1)),
+                ("visible", Resource::Bool(true)),
+                ("data", Resource::Vec(layer.data.iter().map(|gid| Resource::I64(*gid as i64)).collect())),
+            ]));
+            id += 1;
+        }
+
+        let mut object_id = 1;
+        for group in &self.object_groups {
+            let mut objects = Vec::new();
+            for object in &group.objects {
+                objects.push(json_map(vec![
+                    ("id", Resource::I64(object_id)),
+                    ("name", Resource::Str(object.name.clone())),
+                    ("type", Resource::Str(object.kind.clone())),
+                    ("x", Resource::F64(object.x)),
+                    ("y", Resource::F64(object.y)),
+                    ("width", Resource::F64(object.width)),
+                    ("height", Resource::F64(object.height)),
+                    ("rotation", Resource::I64(0)),
+                    ("visible", Resource::Bool(true)),
+                    ("properties", json_property_list(&object.properties)),
+                ]));
+                object_id += 1;
+            }
+
+            layers.push(json_map(vec![
+                ("id", Resource::I64(id)),
+                ("type", Resource::Str("objectgroup".to_string())),
+                ("name", Resource::Str(group.name.clone())),
+                ("x", Resource::I64(0)),
+                ("y", Resource::I64(0)),
+                ("opacity", Resource::I64(1)),
+                ("visible", Resource::Bool(true)),
+                ("draworder", Resource::Str("topdown".to_string())),
+                ("objects", Resource::Vec(objects)),
+            ]));
+            id += 1;
+        }
+
+        let mut map = vec![
+            ("type", Resource::Str("map".to_string())),
+            ("version", Resource::Str("1.2".to_string())),
+            ("renderorder", Resource::Str("right-down".to_string())),
+            ("width", Resource::I64(self.width as i64)),
+            ("height", Resource::I64(self.height as i64)),
+            ("tilewidth", Resource::I64(self.tile_width as i64)),
+            ("tileheight", Resource::I64(self.tile_height as i64)),
+            ("infinite", Resource::Bool(false)),
+            ("nextlayerid", Resource::I64(id)),
+            ("nextobjectid", Resource::I64(object_id)),
+            ("properties", json_property_list(&self.properties)),
+            ("tilesets", Resource::Vec(self.tilesets.iter().map(|tileset| tileset.to_json(dir)).collect())),
+            ("layers", Resource::Vec(layers)),
+        ];
+        let orientation = self.orientation();
+        for (key, value) in &orientation {
+            map.push((key, match value.parse::<i64>() { Ok(i) => Resource::I64(i), Err(_) => Resource::Str(value.clone()) }));
+        }
+
+        let mut out = String::new();
+        write_json(&json_map(map), 0, &mut out);
+        out.push('\n');
+        out
+    }
+
+    pub fn to_tmx(&self, dir: &Path) -> String {
+        let mut root = Element::new("map")
+            .with_attr("version", "1.2")
+            .with_attr("renderorder", "right-down")
+            .with_attr("width", &self.width.to_string())
+            .with_attr("height", &self.height.to_string())
+            .with_attr("tilewidth", &self.tile_width.to_string())
+            .with_attr("tileheight", &self.tile_height.to_string())
+            .with_attr("infinite", "0");
+        for (key, value) in self.orientation() {
+            root = root.with_attr(key, &value);
+        }
+
+        root.children.push(tmx_property_list(&self.properties));
+        for tileset in &self.tilesets {
+            root.children.push(tileset.to_tmx(dir));
+        }
+
+        let mut id = 1;
+        for layer in &self.layers {
+            let mut data = Element::new("data").with_attr("encoding", "csv");
+            let rows: Vec<String> = layer.data.chunks(self.width.max(1) as usize)
+                .map(|row| row.iter().map(|gid| gid.to_string()).collect::<Vec<String>>().join(","))
+                .collect();
+            data.text = format!("\n{}\n", rows.join(",\n"));
+
+            let mut layer_e = Element::new("layer")
+                .with_attr("id", &id.to_string())
+                .with_attr("name", &layer.name)
+                .with_attr("width", &self.width.to_string())
+                .with_attr("height", &self.height.to_string());
+            layer_e.children.push(data);
+            root.children.push(layer_e);
+            id += 1;
+        }
+
+        let mut object_id = 1;
+        for group in &self.object_groups {
+            let mut group_e = Element::new("objectgroup")
+                .with_attr("id", &id.to_string())
+                .with_attr("name", &group.name);
+            for object in &group.objects {
+                let mut object_e = Element::new("object")
+                    .with_attr("id", &object_id.to_string())
+                    .with_attr("name", &object.name)
+                    .with_attr("type", &object.kind)
+                    .with_attr("x", &object.x.to_string())
+                    .with_attr("y", &object.y.to_string())
+                    .with_attr("width", &object.width.to_string())
+                    .with_attr("height", &object.height.to_string());
+                if !object.properties.is_empty() {
+                    object_e.children.push(tmx_property_list(&object.properties));
+                }
+                group_e.children.push(object_e);
+                object_id += 1;
+            }
+            root.children.push(group_e);
+            id += 1;
+        }
+
+        root.to_string()
+    }
+}
+
+// Converts a Tiled .tmx or .json map into a data file at data_path and a map at map_path using it
+pub fn import(path: &str, data_path: &str, map_path: &str) -> Result<(), LoadError> {
+    let tiled = TiledMap::from_file(path)?;
+    let (data, map) = tiled.to_map(path, data_path)?;
+
+    data.to_file(data_path);
+    map.to_file(map_path).map_err(|e| LoadError::new(map_path, "", LoadErrorKind::Invalid(e)))?;
+
+    Ok(())
+}
+
+// Writes a map as a Tiled .tmx or .json map, image paths are made relative to it
+pub fn export(map: &Map, path: &str) -> Result<(), LoadError> {
+    let tiled = TiledMap::from_map(map, path)?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+
+    let out = if path.ends_with(".tmx") { tiled.to_tmx(dir) } else { tiled.to_json(dir) };
+    std::fs::write(path, out).map_err(|e| LoadError::new(path, "", LoadErrorKind::Io(e.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{image::Image, testing::TempDir};
+
+    // Headless tile sets read the size of their atlas, so there has to be one on disk
+    fn atlas_dir(name: &str) -> TempDir {
+        let dir = TempDir::new(&format!("tiled_{}", name));
+        Image::new(32, 16).to_png(&dir.file("atlas.png")).unwrap();
+        dir
+    }
+
+    fn sample_map(dir: &Path, topology: Topology) -> Map {
+        let data = format!("
+atlas:
+  - path: \"{}\"
+textures:
+  - name: \"grass\"
+    bounds: [0, 0, 16, 16]
+  - name: \"water\"
+    bounds: [16, 0, 16, 16]
+tiles:
+  - texture: \"grass\"
+    terrain:
+      movement_cost: 2
+      tags: [\"land\"]
+  - texture: \"water\"
+    terrain:
+      passable: false
+", dir.join("atlas.png").to_string_lossy());
+        let res = Resource::from_string(&data).unwrap();
+        let tiles = TileSet::from_resource_headless(&res.node("data.yaml"), "data.yaml").unwrap();
+        let (grass, water) = (tiles.find("grass").unwrap(), tiles.find("water").unwrap());
+
+        let mut map = Map::with_tile_set(tiles);
+        map.topology = topology;
+
+        let mut ground = Layer::new(5, 4);
+        for c in ground.coords().collect::<Vec<_>>() {
+            ground.try_set(c, if (c.x + 2 * c.y) % 3 == 0 { water } else { grass }).unwrap();
+        }
+        let mut top = Layer::new(5, 4);
+        top.set(4, 3, water);
+        map.layers.push(ground);
+        map.layers.push(top);
+
+        map.info.name = "Round trip".to_string();
+        map.info.players = 2;
+        map.info.starts = vec![(0, 0), (4, 3)];
+
+        let mut objects = ObjectLayer::new("cities");
+        let mut city = MapObject::new("city", 2, 1);
+        city.properties.insert("owner".to_string(), Resource::I64(1));
+        objects.objects.push(city);
+        map.object_layers.push(objects);
+
+        map
+    }
+
+    fn tile_names(map: &Map) -> Vec<Vec<String>> {
+        map.layers.iter().map(|layer| layer.coords().map(|c| match layer.try_get(c) {
+            Some(tile) if tile != unsafe { TileIndex::new(0) } => map.tile_set().tile(tile).name.clone(),
+            _ => String::new(),
+        }).collect()).collect()
+    }
+
+    fn export(map: &Map, file: &str, dir: &Path) -> String {
+        let tiled = TiledMap::from_map(map, file).unwrap();
+        if file.ends_with(".tmx") { tiled.to_tmx(dir) } else { tiled.to_json(dir) }
+    }
+
+    fn parse(file: &str, text: &str) -> Result<TiledMap, LoadError> {
+        if file.ends_with(".tmx") {
+            TiledMap::from_tmx(file, &Element::parse(file, text)?)
+        } else {
+            let res = Resource::from_string(text)?;
+            TiledMap::from_json(file, &res.node(file))
+        }
+    }
+
+    fn round_trip(name: &str, topology: Topology) {
+        let dir = atlas_dir(name);
+        let map = sample_map(&dir, topology);
+
+        for extension in &["tmx", "json"] {
+            let file = dir.join(format!("map.{}", extension)).to_string_lossy().to_string();
+            let (data, imported) = parse(&file, &export(&map, &file, &dir)).unwrap().to_map(&file, "imported.yaml").unwrap();
+
+            assert!(imported.topology == map.topology);
+            assert_eq!(tile_names(&imported), tile_names(&map));
+            assert_eq!(imported.info.name, map.info.name);
+            assert_eq!(imported.info.players, map.info.players);
+            assert_eq!(imported.info.starts, map.info.starts);
+            let city = imported.object_at(2, 1).unwrap();
+            assert_eq!(city.kind, "city");
+            assert_eq!(city.property("owner").and_then(|owner| owner.as_i64()), Some(1));
+
+            // The imported files load together and go around again
+            let tiles = TileSet::from_resource_headless(&data.node("imported.yaml"), "imported.yaml").unwrap();
+            assert!(!tiles.terrain(tiles.find("water").unwrap()).passable);
+            let saved = Resource::from_string(&imported.to_resource().to_string()).unwrap();
+            let loaded = Map::from_resource(&saved.node("imported map"), tiles).unwrap();
+            let (_, again) = parse(&file, &export(&loaded, &file, &dir)).unwrap().to_map(&file, "imported.yaml").unwrap();
+            assert_eq!(tile_names(&again), tile_names(&map));
+        }
+    }
+
+    #[test]
+    fn square_maps_round_trip() {
+        round_trip("square", Topology::Square);
+    }
+
+    #[test]
+    fn pointy_hex_maps_round_trip() {
+        round_trip("pointy", Topology::Hex(HexOrientation::Pointy, HexCoordinates::Offset));
+    }
+
+    #[test]
+    fn flat_hex_maps_round_trip() {
+        round_trip("flat", Topology::Hex(HexOrientation::Flat, HexCoordinates::Offset));
+    }
+
+    fn json_map_with(data: &str, width: &str) -> String {
+        format!("{{\"width\": {}, \"height\": 1, \"tilewidth\": 16, \"tileheight\": 16, \"orientation\": \"orthogonal\", \"tilesets\": [], \"layers\": [{{\"type\": \"tilelayer\", \"name\": \"a\", \"data\": {}}}]}}", width, data)
+    }
+
+    fn tmx_map_with(data: &str) -> String {
+        format!("<map width=\"2\" height=\"1\" tilewidth=\"16\" tileheight=\"16\" orientation=\"orthogonal\"><layer name=\"a\"><data{}</data></layer></map>", data)
+    }
+
+    #[test]
+    fn flipped_tiles_are_refused() {
+        assert!(parse("map.json", &json_map_with("[0, 1]", "2")).is_ok());
+        assert!(parse("map.json", &json_map_with("[0, 2147483649]", "2")).is_err());
+        assert!(parse("map.tmx", &tmx_map_with(" encoding=\"csv\">0,1")).is_ok());
+        assert!(parse("map.tmx", &tmx_map_with(" encoding=\"csv\">0,1073741825")).is_err());
+        assert!(parse("map.tmx", &tmx_map_with("><tile/><tile gid=\"536870913\"/>")).is_err());
+    }
+
+    #[test]
+    fn bad_tile_ids_are_errors() {
+        assert!(parse("map.json", &json_map_with("[0, -1]", "2")).is_err());
+        assert!(parse("map.json", &json_map_with("[0, 4294967297]", "2")).is_err());
+        assert!(parse("map.tmx", &tmx_map_with("><tile/><tile gid=\"1\"/>")).is_ok());
+        assert!(parse("map.tmx", &tmx_map_with("><tile/><tile gid=\"one\"/>")).is_err());
+    }
+
+    #[test]
+    fn huge_layers_are_errors() {
+        assert!(parse("map.json", &json_map_with("[0, 1]", "4294967295")).is_err());
+        assert!(parse("map.json", &json_map_with("[0, 1]", "3")).is_err());
+    }
+}
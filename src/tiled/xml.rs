@@ -0,0 +1,352 @@
+/*
+ *   Copyright (c) 2021 Ludwig Bogsveen
+ *   All rights reserved.
+
+ *   Permission is hereby granted, free of charge, to any person obtaining a copy
+ *   of this software and associated documentation files (the "Software"), to deal
+ *   in the Software without restriction, including without limitation the rights
+ *   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ *   copies of the Software, and to permit persons to whom the Software is
+ *   furnished to do so, subject to the following conditions:
+ 
+ *   The above copyright notice and this permission notice shall be included in all
+ *   copies or substantial portions of the Software.
+ 
+ *   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ *   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ *   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ *   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ *   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ *   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *   SOFTWARE.
+ */
+
+use crate::io::error::{LoadError, LoadErrorKind};
+
+// Just enough XML for Tiled files: elements, attributes, text, comments and CDATA. Namespaces and
+// DTDs are not understood, declarations and doctypes are skipped.
+#[derive(Clone)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+    pub line: usize,
+}
+
+struct Parser<'a> {
+    file: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &str) -> LoadError {
+        LoadError::new(self.file, &format!("line {}", self.line), LoadErrorKind::Parse(msg.to_string()))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        if c == '\n' {
+            self.line += 1;
+        }
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), LoadError> {
+        if !self.starts_with(s) {
+            return Err(self.error(&format!("expected \"{}\"", s)));
+        }
+        for _ in s.chars() {
+            self.next();
+        }
+        Ok(())
+    }
+
+    fn skip_until(&mut self, end: &str) -> Result<String, LoadError> {
+        let mut skipped = String::new();
+        while !self.starts_with(end) {
+            skipped.push(self.next().ok_or_else(|| self.error(&format!("unexpected end of file, expected \"{}\"", end)))?);
+        }
+        self.expect(end)?;
+        Ok(skipped)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, |c| c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    // Skips whitespace, comments, declarations and doctypes between elements
+    fn skip_misc(&mut self) -> Result<(), LoadError> {
+        loop {
+            self.skip_whitespace();
+            if self.starts_with("<?") {
+                self.skip_until("?>")?;
+            } else if self.starts_with("<!--") {
+                self.skip_until("-->")?;
+            } else if self.starts_with("<!") {
+                self.skip_until(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, LoadError> {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || c == '_' || c == '-' || c == '.' || c == ':' {
+                name.push(c);
+                self.next();
+            } else {
+                break;
+            }
+        }
+        if name.is_empty() {
+            return Err(self.error("expected a name"));
+        }
+        Ok(name)
+    }
+
+    fn unescape(&self, text: &str) -> Result<String, LoadError> {
+        let mut out = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('&') {
+            out.push_str(&rest[..start]);
+            let end = rest[start..].find(';').ok_or_else(|| self.error("unterminated entity"))? + start;
+            let entity = &rest[start + 1..end];
+            let c = match entity {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(std::char::from_u32).ok_or_else(|| self.error(&format!("invalid entity \"&{};\"", entity)))?,
+                _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(std::char::from_u32).ok_or_else(|| self.error(&format!("invalid entity \"&{};\"", entity)))?,
+                _ => return Err(self.error(&format!("unknown entity \"&{};\"", entity))),
+            };
+            out.push(c);
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    fn element(&mut self) -> Result<Element, LoadError> {
+        let line = self.line;
+        self.expect("<")?;
+        let name = self.name()?;
+        let mut attributes = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.starts_with("/>") {
+                self.expect("/>")?;
+                return Ok(Element { name, attributes, children: Vec::new(), text: String::new(), line });
+            }
+            if self.starts_with(">") {
+                self.expect(">")?;
+                break;
+            }
+
+            let attribute = self.name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = match self.next() {
+                Some(c) if c == '"' || c == '\'' => c,
+                _ => return Err(self.error("expected a quoted attribute value")),
+            };
+            let value = self.skip_until(&quote.to_string())?;
+            attributes.push((attribute, self.unescape(&value)?));
+        }
+
+        let mut children = Vec::new();
+        let mut text = String::new();
+
+        loop {
+            if self.starts_with("</") {
+                self.expect("</")?;
+                let closing = self.name()?;
+                if closing != name {
+                    return Err(self.error(&format!("expected </{}> but found </{}>", name, closing)));
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                let text = text.trim().to_string();
+                return Ok(Element { name, attributes, children, text, line });
+            } else if self.starts_with("<!--") {
+                self.skip_until("-->")?;
+            } else if self.starts_with("<![CDATA[") {
+                self.expect("<![CDATA[")?;
+                text.push_str(&self.skip_until("]]>")?);
+            } else if self.starts_with("<") {
+                children.push(self.element()?);
+            } else {
+                let mut raw = String::new();
+                while let Some(c) = self.peek() {
+                    if c == '<' {
+                        break;
+                    }
+                    raw.push(c);
+                    self.next();
+                }
+                if self.peek().is_none() {
+                    return Err(self.error(&format!("unexpected end of file, <{}> is never closed", name)));
+                }
+                text.push_str(&self.unescape(&raw)?);
+            }
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+impl Element {
+    pub fn new(name: &str) -> Element {
+        Element {
+            name: name.to_string(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+            line: 0,
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Element, LoadError> {
+        let data = std::fs::read_to_string(path).map_err(|e| LoadError::new(path, "", LoadErrorKind::Io(e.to_string())))?;
+        Element::parse(path, &data)
+    }
+
+    pub fn parse(file: &str, data: &str) -> Result<Element, LoadError> {
+        let mut parser = Parser { file, chars: data.chars().collect(), pos: 0, line: 1 };
+        parser.skip_misc()?;
+        let root = parser.element()?;
+        parser.skip_misc()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected content after the root element"));
+        }
+        Ok(root)
+    }
+
+    pub fn error(&self, file: &str, msg: &str) -> LoadError {
+        LoadError::new(file, &format!("line {} <{}>", self.line, self.name), LoadErrorKind::Invalid(msg.to_string()))
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(attribute, _)| attribute == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    pub fn with_attr(mut self, name: &str, value: &str) -> Element {
+        self.attributes.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn to_string(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        self.write(&mut out, 0);
+        out
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        let indent = " ".repeat(depth);
+        out.push_str(&indent);
+        out.push('<');
+        out.push_str(&self.name);
+        for (name, value) in &self.attributes {
+            out.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+        }
+
+        if self.children.is_empty() && self.text.is_empty() {
+            out.push_str("/>\n");
+            return;
+        }
+
+        out.push('>');
+        if self.children.is_empty() {
+            out.push_str(&escape(&self.text));
+        } else {
+            out.push('\n');
+            if !self.text.is_empty() {
+                out.push_str(&escape(&self.text));
+                out.push('\n');
+            }
+            for child in &self.children {
+                child.write(out, depth + 1);
+            }
+            out.push_str(&indent);
+        }
+        out.push_str(&format!("</{}>\n", self.name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_tiled_writes() {
+        let text = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<!-- saved by hand -->
+<map width='2' name=\"a &amp; b &#x41;&#66;\">
+ <layer name=\"ground\">
+  <data encoding=\"csv\"><![CDATA[1,2]]></data>
+ </layer>
+ <objectgroup/>
+</map>
+";
+        let root = Element::parse("test", text).unwrap();
+        assert_eq!(root.name, "map");
+        assert_eq!(root.attr("width"), Some("2"));
+        assert_eq!(root.attr("name"), Some("a & b AB"));
+        assert_eq!(root.children.len(), 2);
+
+        let data = root.child("layer").and_then(|layer| layer.child("data")).unwrap();
+        assert_eq!(data.text, "1,2");
+        assert_eq!(data.line, 5);
+        assert_eq!(root.children("objectgroup").count(), 1);
+    }
+
+    #[test]
+    fn written_elements_parse_back() {
+        let mut root = Element::new("map").with_attr("name", "<\"quoted\" & escaped>");
+        let mut data = Element::new("data");
+        data.text = "1 < 2".to_string();
+        root.children.push(data);
+        root.children.push(Element::new("empty").with_attr("x", "0"));
+
+        let parsed = Element::parse("test", &root.to_string()).unwrap();
+        assert_eq!(parsed.attr("name"), Some("<\"quoted\" & escaped>"));
+        assert_eq!(parsed.child("data").unwrap().text, "1 < 2");
+        assert_eq!(parsed.child("empty").and_then(|empty| empty.attr("x")), Some("0"));
+    }
+
+    #[test]
+    fn broken_documents_are_errors() {
+        for text in &["<map>", "<map></layer>", "<map a=1/>", "<map>&nope;</map>", "<map/><map/>", "<map>&#xZZ;</map>", ""] {
+            assert!(Element::parse("test", text).is_err(), "{:?} parsed", text);
+        }
+    }
+}